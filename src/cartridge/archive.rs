@@ -0,0 +1,133 @@
+//! Support for ROMs stored in zip and gzip archives
+
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use flate2::read::GzDecoder;
+use zip::ZipArchive;
+
+use super::CartridgeError;
+
+/// Read the ROM image at `path`, decompressing it if it's a zip or
+/// gzip archive. For zip files the first ".gb" or ".gbc" entry is
+/// used.
+pub fn read_image(path: &Path) -> Result<Vec<u8>, CartridgeError> {
+    let extension =
+        path.extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_lowercase());
+
+    let file = try!(File::open(path));
+
+    let mut image = Vec::new();
+
+    match extension.as_ref().map(|e| &e[..]) {
+        Some("zip") => try!(read_zip(file, &mut image)),
+        Some("gz")  => {
+            let mut decoder = try!(GzDecoder::new(file));
+
+            try!(decoder.read_to_end(&mut image));
+        }
+        _ => {
+            let mut file = file;
+
+            try!(file.read_to_end(&mut image));
+        }
+    }
+
+    Ok(image)
+}
+
+fn read_zip(file: File, image: &mut Vec<u8>) -> Result<(), CartridgeError> {
+    let mut zip = try!(ZipArchive::new(file).map_err(invalid_zip));
+
+    for i in 0..zip.len() {
+        let mut entry = try!(zip.by_index(i).map_err(invalid_zip));
+
+        if is_rom(entry.name()) {
+            try!(entry.read_to_end(image));
+
+            return Ok(());
+        }
+    }
+
+    Err(CartridgeError::InvalidArchive("no ROM found in archive".to_string()))
+}
+
+fn invalid_zip(e: ::zip::result::ZipError) -> CartridgeError {
+    CartridgeError::InvalidArchive(format!("{}", e))
+}
+
+/// Return `true` if `name` looks like a ROM image
+fn is_rom(name: &str) -> bool {
+    let name = name.to_lowercase();
+
+    name.ends_with(".gb") || name.ends_with(".gbc")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{File, remove_file};
+    use std::io::Write;
+    use std::path::PathBuf;
+    use std::env::temp_dir;
+
+    use flate2::Compression;
+    use flate2::write::GzEncoder;
+    use zip::ZipWriter;
+    use zip::CompressionMethod;
+
+    use super::read_image;
+
+    fn temp_path(name: &str) -> PathBuf {
+        temp_dir().join(format!("gb-rs-archive-test-{}", name))
+    }
+
+    fn rom() -> Vec<u8> {
+        (0..0x8000).map(|i| i as u8).collect()
+    }
+
+    #[test]
+    fn gzip() {
+        let path = temp_path("rom.gb.gz");
+
+        {
+            let file = File::create(&path).unwrap();
+            let mut encoder = GzEncoder::new(file, Compression::Default);
+
+            encoder.write_all(&rom()).unwrap();
+            encoder.finish().unwrap();
+        }
+
+        let image = read_image(&path).unwrap();
+
+        remove_file(&path).unwrap();
+
+        assert!(image == rom());
+    }
+
+    #[test]
+    fn zip() {
+        let path = temp_path("roms.zip");
+
+        {
+            let file = File::create(&path).unwrap();
+            let mut zip = ZipWriter::new(file);
+
+            zip.start_file("README.txt", CompressionMethod::Stored).unwrap();
+            zip.write_all(b"Not a ROM").unwrap();
+
+            zip.start_file("game.GB", CompressionMethod::Deflated).unwrap();
+            zip.write_all(&rom()).unwrap();
+
+            zip.finish().unwrap();
+        }
+
+        let image = read_image(&path).unwrap();
+
+        remove_file(&path).unwrap();
+
+        assert!(image == rom());
+    }
+}
@@ -0,0 +1,282 @@
+//! Game Boy Camera (Pocket Camera) ASIC.
+//!
+//! Writing a value with bit 4 set to the RAM bank register maps the
+//! camera registers in the RAM range instead of the RAM. Register 0
+//! starts a capture and reports whether it's still in progress,
+//! registers 1 to 5 configure the sensor and registers 6 to 0x35
+//! contain a 4x4 matrix of 3 thresholds used to convert the sensor's
+//! output into 2bpp pixels. The registers are mirrored every 0x80
+//! bytes.
+//!
+//! Once the capture is done the picture is written in RAM bank 0 at
+//! offset 0x100 as 16x14 tiles in the usual 2bpp tile format.
+
+use ui::{ImageSource, CAMERA_WIDTH, CAMERA_HEIGHT};
+use savestate::{Writer, Reader, SaveStateError};
+
+/// Camera state
+pub struct Camera {
+    /// `true` if the camera registers are mapped in the RAM range
+    mapped:     bool,
+    /// `true` if writes to the RAM are allowed
+    ram_enable: bool,
+    /// Register values
+    regs:       [u8; NREGS],
+    /// Number of system clock ticks before the end of the current
+    /// capture. 0 if no capture is in progress.
+    busy:       u32,
+    /// Sensor
+    source:     Box<ImageSource>,
+}
+
+impl Camera {
+    pub fn new(source: Box<ImageSource>) -> Camera {
+        Camera {
+            mapped:     false,
+            ram_enable: false,
+            regs:       [0; NREGS],
+            busy:       0,
+            source:     source,
+        }
+    }
+
+    /// Replace the image source
+    pub fn set_source(&mut self, source: Box<ImageSource>) {
+        self.source = source;
+    }
+
+    /// Return `true` if the registers are mapped in place of the RAM
+    pub fn mapped(&self) -> bool {
+        self.mapped
+    }
+
+    /// Map the registers in place of the RAM
+    pub fn set_mapped(&mut self, mapped: bool) {
+        self.mapped = mapped;
+    }
+
+    /// Return `true` if RAM writes are allowed
+    pub fn ram_enable(&self) -> bool {
+        self.ram_enable
+    }
+
+    /// Allow or forbid RAM writes
+    pub fn set_ram_enable(&mut self, enable: bool) {
+        self.ram_enable = enable;
+    }
+
+    /// Serialize the ASIC state. The picture is only taken from the
+    /// source once the capture completes so there's nothing else to
+    /// save.
+    pub fn save_state(&self, w: &mut Writer) {
+        w.bool(self.mapped);
+        w.bool(self.ram_enable);
+        w.bytes(&self.regs);
+        w.u32(self.busy);
+    }
+
+    /// Restore a state serialized by `save_state`
+    pub fn load_state(&mut self, r: &mut Reader) -> Result<(), SaveStateError> {
+        self.mapped     = try!(r.bool());
+        self.ram_enable = try!(r.bool());
+        try!(r.bytes_into(&mut self.regs));
+        self.busy       = try!(r.u32());
+
+        Ok(())
+    }
+
+    /// Called at each tick of the system clock. `ram` is the
+    /// cartridge RAM where the picture will be stored. Returns `true`
+    /// when a new picture has been written to the RAM.
+    pub fn step(&mut self, ram: &mut [u8]) -> bool {
+        if self.busy == 0 {
+            return false;
+        }
+
+        self.busy -= 1;
+
+        if self.busy == 0 {
+            self.capture(ram);
+            return true;
+        }
+
+        false
+    }
+
+    /// Read register at `addr` (offset in the RAM range)
+    pub fn register(&self, addr: u32) -> u8 {
+        match addr & 0x7f {
+            // Only the control register can be read back
+            0 => (self.regs[0] & 0x06) | ((self.busy != 0) as u8),
+            _ => 0x00,
+        }
+    }
+
+    /// Write `val` to the register at `addr` (offset in the RAM
+    /// range)
+    pub fn set_register(&mut self, addr: u32, val: u8) {
+        let reg = (addr & 0x7f) as usize;
+
+        if reg >= NREGS {
+            debug!("Write to unknown camera register {:02x}", reg);
+            return;
+        }
+
+        if reg == 0 {
+            if val & 1 != 0 {
+                if self.busy == 0 {
+                    self.busy = self.capture_ticks();
+                }
+            } else {
+                // Abort capture
+                self.busy = 0;
+            }
+
+            self.regs[0] = val & 0x07;
+        } else {
+            self.regs[reg] = val;
+        }
+    }
+
+    /// Exposure time, in units of 16 CPU cycles
+    fn exposure(&self) -> u32 {
+        ((self.regs[2] as u32) << 8) | self.regs[3] as u32
+    }
+
+    /// Return the duration of a capture in system clock ticks
+    fn capture_ticks(&self) -> u32 {
+        // Without the N bit the sensor adds 512 cycles per capture
+        let n = if self.regs[1] & 0x80 != 0 { 0 } else { 512 };
+
+        // The capture duration is expressed in CPU cycles (1MHz),
+        // our system clock is 4 times faster
+        (32446 + n + 16 * self.exposure()) * 4
+    }
+
+    /// Grab a picture from the image source and store it in RAM
+    fn capture(&mut self, ram: &mut [u8]) {
+        let mut pixels = vec![0; CAMERA_WIDTH * CAMERA_HEIGHT];
+
+        self.source.capture(&mut pixels);
+
+        let invert   = self.regs[4] & 0x08 != 0;
+        let exposure = self.exposure();
+
+        for y in 0..CAMERA_HEIGHT {
+            for x in 0..CAMERA_WIDTH {
+                let p = pixels[y * CAMERA_WIDTH + x] as u32;
+
+                // This is a crude approximation of the sensor's
+                // response, good enough for the game's automatic
+                // exposure to converge on something sensible.
+                let mut v = p * exposure / EXPOSURE_UNITY;
+
+                if v > 0xff {
+                    v = 0xff;
+                }
+
+                if invert {
+                    v = 0xff - v;
+                }
+
+                let color = self.dither(x, y, v as u8);
+
+                let tile   = (y / 8) * (CAMERA_WIDTH / 8) + x / 8;
+                let offset = PICTURE_OFFSET + tile * 16 + (y % 8) * 2;
+                let bit    = 7 - (x % 8);
+
+                if offset + 1 >= ram.len() {
+                    // No RAM to store the picture
+                    return;
+                }
+
+                let lo = ram[offset]     & !(1u8 << bit);
+                let hi = ram[offset + 1] & !(1u8 << bit);
+
+                ram[offset]     = lo | ((color & 1) << bit);
+                ram[offset + 1] = hi | ((color >> 1) << bit);
+            }
+        }
+    }
+
+    /// Convert an 8bit sensor value into a 2bpp color using the
+    /// threshold matrix. 0 is white, 3 is black.
+    fn dither(&self, x: usize, y: usize, v: u8) -> u8 {
+        let m = MATRIX_OFFSET + ((y % 4) * 4 + (x % 4)) * 3;
+
+        if v < self.regs[m] {
+            3
+        } else if v < self.regs[m + 1] {
+            2
+        } else if v < self.regs[m + 2] {
+            1
+        } else {
+            0
+        }
+    }
+}
+
+/// Number of camera registers
+const NREGS: usize = 0x36;
+
+/// Offset of the threshold matrix in the registers
+const MATRIX_OFFSET: usize = 6;
+
+/// Offset of the picture in RAM bank 0
+const PICTURE_OFFSET: usize = 0x100;
+
+/// Exposure value for which the sensor output is the source image
+const EXPOSURE_UNITY: u32 = 0x800;
+
+#[cfg(test)]
+mod tests {
+    use super::Camera;
+    use ui::{ImageSource, CAMERA_WIDTH};
+
+    /// Left half black, right half white
+    struct Halves;
+
+    impl ImageSource for Halves {
+        fn capture(&mut self, pixels: &mut [u8]) {
+            for (i, p) in pixels.iter_mut().enumerate() {
+                *p = if i % CAMERA_WIDTH < CAMERA_WIDTH / 2 { 0 } else { 0xff };
+            }
+        }
+    }
+
+    #[test]
+    fn capture() {
+        let mut camera = Camera::new(Box::new(Halves));
+        let mut ram    = vec![0x55; 0x2000];
+
+        // Exposure 0x800
+        camera.set_register(2, 0x08);
+        camera.set_register(3, 0x00);
+
+        // Same thresholds everywhere
+        for m in 0..16 {
+            camera.set_register(6 + m * 3,     0x40);
+            camera.set_register(6 + m * 3 + 1, 0x80);
+            camera.set_register(6 + m * 3 + 2, 0xc0);
+        }
+
+        camera.set_register(0, 0x01);
+
+        assert!(camera.register(0) & 1 == 1);
+
+        while camera.register(0) & 1 == 1 {
+            camera.step(&mut ram);
+        }
+
+        // First tile is on the left: black
+        assert!(ram[0x100] == 0xff);
+        assert!(ram[0x101] == 0xff);
+
+        // Tile 8 is on the right: white
+        assert!(ram[0x100 + 8 * 16] == 0x00);
+        assert!(ram[0x101 + 8 * 16] == 0x00);
+
+        // Data before the picture is left untouched
+        assert!(ram[0xff] == 0x55);
+    }
+}
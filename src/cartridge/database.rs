@@ -0,0 +1,188 @@
+//! Database of cartridges whose header can't be trusted: unlicensed
+//! mappers, wrong RAM sizes, multicarts without any distinctive
+//! header etc... Entries override what we would otherwise deduce
+//! from the header.
+
+use super::offsets;
+use crc::crc32;
+
+/// How an entry is matched against a ROM image
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum DatabaseKey {
+    /// CRC-32 of the whole image, as found in No-Intro style DAT
+    /// files
+    Crc32(u32),
+    /// Global checksum stored in the header. Cheaper to match than
+    /// the CRC but a lot less discriminating, only use it for carts
+    /// whose dumps all share the same header.
+    GlobalChecksum(u16),
+}
+
+/// Known-good information about a cartridge
+#[derive(Debug,Clone,Copy)]
+pub struct KnownCartridge {
+    /// Key used to identify the ROM
+    pub key:            DatabaseKey,
+    /// Full name of the game
+    pub name:           &'static str,
+    /// Cartridge type to use instead of the one in the header
+    pub cartridge_type: Option<u8>,
+    /// Number and size of the RAM banks to use instead of the ones
+    /// in the header
+    pub ram:            Option<(usize, usize)>,
+    /// Force (or prevent) MBC1 multicart mode instead of relying on
+    /// the logo detection heuristic
+    pub multicart:      Option<bool>,
+}
+
+/// Look for `rom` in the database
+pub fn lookup(rom: &[u8]) -> Option<&'static KnownCartridge> {
+    find(DATABASE, rom)
+}
+
+fn find<'a>(entries: &'a [KnownCartridge],
+            rom: &[u8]) -> Option<&'a KnownCartridge> {
+    if rom.len() < offsets::HEADER_END {
+        return None;
+    }
+
+    let global_checksum =
+        ((rom[offsets::GLOBAL_CHECKSUM] as u16) << 8) |
+        rom[offsets::GLOBAL_CHECKSUM + 1] as u16;
+
+    // The CRC is only computed if we have an entry to match it
+    // against, it's not free on big images
+    let mut crc = None;
+
+    for entry in entries {
+        let matches =
+            match entry.key {
+                DatabaseKey::GlobalChecksum(c) => c == global_checksum,
+                DatabaseKey::Crc32(c) => {
+                    if crc.is_none() {
+                        crc = Some(crc32(rom));
+                    }
+
+                    crc == Some(c)
+                }
+            };
+
+        if matches {
+            return Some(entry);
+        }
+    }
+
+    None
+}
+
+/// The database itself. Only add entries for dumps that have
+/// actually been checked: a bogus key would silently break an
+/// otherwise working game.
+///
+/// It's empty for now: none of the problematic dumps have been
+/// checked yet. Until then MBC1 multicarts (Bomberman Collection,
+/// Mortal Kombat I & II...) are only detected by looking for the
+/// Nintendo logo at the start of the 256KB sub-ROMs and carts with
+/// a wrong RAM size in their header still get the wrong RAM size.
+///
+/// Entries look like:
+///
+/// ```ignore
+/// KnownCartridge {
+///     key:            DatabaseKey::Crc32(0x12345678),
+///     name:           "Some Game (World)",
+///     cartridge_type: Some(0x03),
+///     ram:            Some((1, 8 * 1024)),
+///     multicart:      None,
+/// },
+/// ```
+#[cfg(not(test))]
+static DATABASE: &'static [KnownCartridge] = &[];
+
+/// Test builds use a made up entry instead so that the overrides can
+/// be checked through the regular cartridge loading code
+#[cfg(test)]
+static DATABASE: &'static [KnownCartridge] = &[
+    KnownCartridge {
+        key:            DatabaseKey::GlobalChecksum(0xdb01),
+        name:           "Database Test",
+        cartridge_type: Some(0x01),
+        ram:            Some((4, 8 * 1024)),
+        multicart:      Some(true),
+    },
+];
+
+#[cfg(test)]
+mod tests {
+    use super::{find, KnownCartridge, DatabaseKey};
+    use cartridge::Cartridge;
+    use crc::crc32;
+
+    #[test]
+    fn lookup() {
+        let mut rom = vec![0; 0x8000];
+
+        rom[0x14e] = 0x12;
+        rom[0x14f] = 0x34;
+
+        let crc = crc32(&rom);
+
+        let entries = [
+            KnownCartridge {
+                key:            DatabaseKey::Crc32(!crc),
+                name:           "Other",
+                cartridge_type: None,
+                ram:            None,
+                multicart:      None,
+            },
+            KnownCartridge {
+                key:            DatabaseKey::Crc32(crc),
+                name:           "By CRC",
+                cartridge_type: Some(0x01),
+                ram:            None,
+                multicart:      Some(true),
+            },
+            KnownCartridge {
+                key:            DatabaseKey::GlobalChecksum(0x1234),
+                name:           "By checksum",
+                cartridge_type: None,
+                ram:            Some((1, 2048)),
+                multicart:      None,
+            },
+        ];
+
+        assert!(find(&entries, &rom).unwrap().name == "By CRC");
+        assert!(find(&entries[2..], &rom).unwrap().name == "By checksum");
+
+        rom[0x14f] = 0x35;
+
+        assert!(find(&entries[2..], &rom).is_none());
+    }
+
+    /// The database entry wins over the header when loading a
+    /// cartridge
+    #[test]
+    fn override_header() {
+        // 1MB ROM-only cart without any RAM nor second header
+        let mut rom = vec![0; 64 * 0x4000];
+
+        rom[0x148] = 0x05;
+        rom[0x14e] = 0xdb;
+        rom[0x14f] = 0x01;
+
+        let cart = Cartridge::from_vec(rom.clone()).unwrap();
+
+        assert!(cart.model.name == "MBC1");
+        assert!(cart.ram.len() == 4 * 8 * 1024);
+        assert!(cart.multicart());
+
+        // Same image without a matching entry
+        rom[0x14f] = 0x02;
+
+        let cart = Cartridge::from_vec(rom).unwrap();
+
+        assert!(cart.model.name == "MBC0");
+        assert!(cart.ram.is_empty());
+        assert!(!cart.multicart());
+    }
+}
@@ -0,0 +1,184 @@
+//! Errors returned when loading a cartridge
+
+use std::fmt;
+use std::io;
+use std::error;
+use std::path::PathBuf;
+
+/// Reasons why a cartridge couldn't be loaded
+#[derive(Debug)]
+pub enum CartridgeError {
+    /// Error while accessing the ROM or save file
+    Io(io::Error),
+    /// The ROM is too small to contain a cartridge header or isn't
+    /// made of whole 16KB banks. Contains the size of the ROM image
+    /// in bytes.
+    Truncated(usize),
+    /// The ROM is too big to be addressed by any mapper. Contains
+    /// the size of the ROM image in bytes.
+    TooLarge(usize),
+    /// Unsupported cartridge type in the header
+    UnknownModel(u8),
+    /// Unknown RAM size code in the header
+    UnknownRamSize(u8),
+    /// The archive containing the ROM is corrupted or doesn't contain
+    /// a ROM
+    InvalidArchive(String),
+    /// The patch file is corrupted or in an unknown format
+    InvalidPatch(&'static str),
+    /// CRC mismatch while applying a UPS or BPS patch. `what` says
+    /// which of the source, target or patch CRC didn't match.
+    PatchChecksum {
+        /// Checksum that didn't match
+        what:     &'static str,
+        /// CRC stored in the patch
+        expected: u32,
+        /// Actual CRC
+        got:      u32,
+    },
+    /// The save file doesn't match the cartridge's RAM
+    BadSaveSize {
+        /// Path to the save file
+        path:     PathBuf,
+        /// Size of the cartridge RAM in bytes
+        expected: u64,
+        /// Actual size of the save file
+        got:      u64,
+    },
+}
+
+impl fmt::Display for CartridgeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CartridgeError::Io(ref e) =>
+                write!(f, "{}", e),
+            CartridgeError::Truncated(size) =>
+                write!(f, "truncated ROM image ({} bytes)", size),
+            CartridgeError::TooLarge(size) =>
+                write!(f, "ROM image too large ({} bytes)", size),
+            CartridgeError::UnknownModel(id) =>
+                write!(f, "unknown cartridge type 0x{:02x}", id),
+            CartridgeError::UnknownRamSize(id) =>
+                write!(f, "unknown RAM size code 0x{:02x}", id),
+            CartridgeError::InvalidArchive(ref msg) =>
+                write!(f, "invalid archive: {}", msg),
+            CartridgeError::InvalidPatch(msg) =>
+                write!(f, "invalid patch: {}", msg),
+            CartridgeError::PatchChecksum { what, expected, got } =>
+                write!(f, "patch {} CRC mismatch: expected {:08x} got {:08x}",
+                       what, expected, got),
+            CartridgeError::BadSaveSize { ref path, expected, got } =>
+                write!(f, "unexpected save file size for {}: \
+                           expected {} got {}",
+                       path.display(), expected, got),
+        }
+    }
+}
+
+impl error::Error for CartridgeError {
+    fn description(&self) -> &str {
+        match *self {
+            CartridgeError::Io(ref e)             => e.description(),
+            CartridgeError::Truncated(_)          => "truncated ROM image",
+            CartridgeError::TooLarge(_)           => "ROM image too large",
+            CartridgeError::UnknownModel(_)       => "unknown cartridge type",
+            CartridgeError::UnknownRamSize(_)     => "unknown RAM size",
+            CartridgeError::InvalidArchive(_)     => "invalid archive",
+            CartridgeError::InvalidPatch(_)       => "invalid patch",
+            CartridgeError::PatchChecksum { .. }  => "patch CRC mismatch",
+            CartridgeError::BadSaveSize { .. }    => "unexpected save file size",
+        }
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            CartridgeError::Io(ref e) => Some(e),
+            _                         => None,
+        }
+    }
+}
+
+impl From<io::Error> for CartridgeError {
+    fn from(e: io::Error) -> CartridgeError {
+        CartridgeError::Io(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{File, remove_file};
+    use std::io::Write;
+    use std::path::PathBuf;
+    use std::env::temp_dir;
+
+    use cartridge::Cartridge;
+    use super::CartridgeError;
+
+    fn rom(id: u8, ram_size: u8) -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+
+        rom[0x147] = id;
+        rom[0x149] = ram_size;
+
+        rom
+    }
+
+    #[test]
+    fn truncated() {
+        match Cartridge::from_vec(vec![0; 0x14f]) {
+            Err(CartridgeError::Truncated(0x14f)) => (),
+            _ => panic!("ROM without a header accepted"),
+        }
+
+        match Cartridge::from_vec(vec![0; 0x6000]) {
+            Err(CartridgeError::Truncated(0x6000)) => (),
+            _ => panic!("ROM with a partial bank accepted"),
+        }
+    }
+
+    #[test]
+    fn unknown_model() {
+        match Cartridge::from_vec(rom(0xaa, 0)) {
+            Err(CartridgeError::UnknownModel(0xaa)) => (),
+            _ => panic!("unknown cartridge type accepted"),
+        }
+    }
+
+    #[test]
+    fn unknown_ram_size() {
+        match Cartridge::from_vec(rom(0x03, 0x42)) {
+            Err(CartridgeError::UnknownRamSize(0x42)) => (),
+            _ => panic!("unknown RAM size accepted"),
+        }
+    }
+
+    #[test]
+    fn io() {
+        let path = temp_dir().join("gb-rs-error-test-missing.gb");
+
+        match Cartridge::from_path(&path) {
+            Err(CartridgeError::Io(_)) => (),
+            _ => panic!("missing ROM accepted"),
+        }
+    }
+
+    #[test]
+    fn bad_save_size() {
+        let path: PathBuf = temp_dir().join("gb-rs-error-test-save.gb");
+        let save_path = path.with_extension("sav");
+
+        // MBC1+RAM+BATTERY with 8KB of RAM and a 100 byte save
+        File::create(&path).unwrap().write_all(&rom(0x03, 0x02)).unwrap();
+        File::create(&save_path).unwrap().write_all(&[0; 100]).unwrap();
+
+        let result = Cartridge::from_path(&path);
+
+        remove_file(&path).unwrap();
+        remove_file(&save_path).unwrap();
+
+        match result {
+            Err(CartridgeError::BadSaveSize { expected: 0x2000, got: 100, .. }) => (),
+            _ => panic!("save file of the wrong size accepted"),
+        }
+    }
+}
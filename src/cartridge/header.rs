@@ -0,0 +1,311 @@
+//! Cartridge header, located at [0x100, 0x14f] in the ROM.
+
+use std::fmt;
+use std::path::Path;
+
+use super::{offsets, models, database, CartridgeError, LoadOptions};
+use super::{KnownCartridge, NINTENDO_LOGO};
+
+/// Game Boy Color support advertised in the header
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum CgbSupport {
+    /// Original Game Boy game
+    None,
+    /// Works on both the Game Boy and the Game Boy Color
+    Compatible,
+    /// Game Boy Color only
+    Only,
+}
+
+/// Region the cartridge was sold in
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum Destination {
+    /// Japanese release (code 0x00)
+    Japan,
+    /// Released anywhere else (code 0x01)
+    Overseas,
+    /// Invalid destination code
+    Unknown(u8),
+}
+
+/// Parsed cartridge header
+#[derive(Debug,Clone)]
+pub struct Header {
+    /// Game title, if it's valid ASCII
+    pub title:                Option<String>,
+    /// 4 character manufacturer code found in newer cartridges
+    pub manufacturer:         Option<String>,
+    /// Game Boy Color support
+    pub cgb:                  CgbSupport,
+    /// `true` if the game supports the Super Game Boy functions
+    pub sgb:                  bool,
+    /// Old licensee code. If it's 0x33 the `new_licensee` is used
+    /// instead
+    pub old_licensee:         u8,
+    /// Two character licensee code used by newer cartridges
+    pub new_licensee:         Option<String>,
+    /// Cartridge type (MBC and additional hardware)
+    pub cartridge_type:       u8,
+    /// ROM size code
+    pub rom_size:             u8,
+    /// RAM size code
+    pub ram_size:             u8,
+    /// Region
+    pub destination:          Destination,
+    /// Version number of the game
+    pub version:              u8,
+    /// Header checksum, checked by the bootrom
+    pub header_checksum:      u8,
+    /// `true` if the header checksum is valid
+    pub header_checksum_ok:   bool,
+    /// Checksum of the whole ROM. Not checked by the hardware.
+    pub global_checksum:      u16,
+    /// `true` if the global checksum is valid
+    pub global_checksum_ok:   bool,
+    /// `true` if the Nintendo logo is valid. The bootrom won't start
+    /// the game otherwise.
+    pub logo_ok:              bool,
+    /// Entry in the cartridge database, if the ROM is known
+    pub known:                Option<&'static KnownCartridge>,
+}
+
+impl Header {
+    /// Parse the header of `rom`. The global checksum is computed
+    /// over the whole image.
+    pub fn new(rom: &[u8]) -> Result<Header, CartridgeError> {
+        if rom.len() < offsets::HEADER_END {
+            return Err(CartridgeError::Truncated(rom.len()));
+        }
+
+        let cgb =
+            match rom[offsets::CGB_FLAG] {
+                0xc0 => CgbSupport::Only,
+                0x80 => CgbSupport::Compatible,
+                _    => CgbSupport::None,
+            };
+
+        // On CGB cartridges the end of the title area is used for the
+        // manufacturer code and the CGB flag
+        let title_len =
+            match cgb {
+                CgbSupport::None => 16,
+                _                => 11,
+            };
+
+        let title = ascii_string(&rom[offsets::TITLE..][..title_len], true);
+
+        let manufacturer =
+            match cgb {
+                CgbSupport::None => None,
+                _                =>
+                    ascii_string(&rom[offsets::MANUFACTURER..][..4], false),
+            };
+
+        let old_licensee = rom[offsets::OLD_LICENSEE];
+
+        let new_licensee =
+            match old_licensee {
+                0x33 => ascii_string(&rom[offsets::NEW_LICENSEE..][..2], false),
+                _    => None,
+            };
+
+        let destination =
+            match rom[offsets::DESTINATION] {
+                0x00 => Destination::Japan,
+                0x01 => Destination::Overseas,
+                n    => Destination::Unknown(n),
+            };
+
+        let header_checksum = rom[offsets::HEADER_CHECKSUM];
+        let global_checksum =
+            ((rom[offsets::GLOBAL_CHECKSUM] as u16) << 8) |
+            rom[offsets::GLOBAL_CHECKSUM + 1] as u16;
+
+        let logo = &rom[offsets::LOGO..][..NINTENDO_LOGO.len()];
+
+        Ok(Header {
+            title:              title,
+            manufacturer:       manufacturer,
+            cgb:                cgb,
+            sgb:                rom[offsets::SGB_FLAG] == 0x03,
+            old_licensee:       old_licensee,
+            new_licensee:       new_licensee,
+            cartridge_type:     rom[offsets::TYPE],
+            rom_size:           rom[offsets::ROM_SIZE],
+            ram_size:           rom[offsets::RAM_SIZE],
+            destination:        destination,
+            version:            rom[offsets::VERSION],
+            header_checksum:    header_checksum,
+            header_checksum_ok: header_checksum == compute_header_checksum(rom),
+            global_checksum:    global_checksum,
+            global_checksum_ok: global_checksum == compute_global_checksum(rom),
+            logo_ok:            logo == &NINTENDO_LOGO[..],
+            known:              database::lookup(rom),
+        })
+    }
+
+    /// Read the header of the ROM image at `path` without loading
+    /// the cartridge.
+    pub fn from_path(path: &Path,
+                     options: &LoadOptions) -> Result<Header, CartridgeError> {
+        let rom = try!(super::load_rom(path, options));
+
+        Header::new(&rom)
+    }
+}
+
+impl fmt::Display for Header {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let model = match models::from_id(self.cartridge_type) {
+            Some(m) => m.name,
+            None    => "unsupported",
+        };
+
+        let title = match self.title {
+            Some(ref t) => &t[..],
+            None        => "<INVALID>",
+        };
+
+        let manufacturer = match self.manufacturer {
+            Some(ref m) => &m[..],
+            None        => "-",
+        };
+
+        try!(writeln!(f, "Title:           {}", title));
+        try!(writeln!(f, "Manufacturer:    {}", manufacturer));
+        try!(writeln!(f, "CGB support:     {:?}", self.cgb));
+        try!(writeln!(f, "SGB support:     {}", self.sgb));
+
+        match self.new_licensee {
+            Some(ref l) => try!(writeln!(f, "Licensee:        {}", l)),
+            None        => try!(writeln!(f, "Licensee:        0x{:02x}",
+                                         self.old_licensee)),
+        }
+
+        try!(writeln!(f, "Cartridge type:  0x{:02x} ({})",
+                      self.cartridge_type, model));
+        try!(writeln!(f, "ROM size code:   0x{:02x}", self.rom_size));
+        try!(writeln!(f, "RAM size code:   0x{:02x}", self.ram_size));
+        try!(writeln!(f, "Destination:     {:?}", self.destination));
+        try!(writeln!(f, "Version:         {}", self.version));
+        try!(writeln!(f, "Header checksum: 0x{:02x} ({})",
+                      self.header_checksum, status(self.header_checksum_ok)));
+        try!(writeln!(f, "Global checksum: 0x{:04x} ({})",
+                      self.global_checksum, status(self.global_checksum_ok)));
+        try!(write!(f,   "Nintendo logo:   {}", status(self.logo_ok)));
+
+        if let Some(k) = self.known {
+            try!(write!(f, "\nDatabase:        {}", k.name));
+
+            if let Some(t) = k.cartridge_type {
+                let model = match models::from_id(t) {
+                    Some(m) => m.name,
+                    None    => "unsupported",
+                };
+
+                try!(write!(f, "\n  Real type:     0x{:02x} ({})", t, model));
+            }
+
+            if let Some((banks, size)) = k.ram {
+                try!(write!(f, "\n  Real RAM:      {} x {}B", banks, size));
+            }
+
+            if let Some(m) = k.multicart {
+                try!(write!(f, "\n  Multicart:     {}", m));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn status(ok: bool) -> &'static str {
+    match ok {
+        true  => "OK",
+        false => "BAD",
+    }
+}
+
+/// Convert `bytes` into a String. If `nul_terminated` is true the
+/// string stops at the first 0. Return `None` if the string contains
+/// non-printable characters.
+fn ascii_string(bytes: &[u8], nul_terminated: bool) -> Option<String> {
+    let mut s = String::with_capacity(bytes.len());
+
+    for &b in bytes {
+        if b == 0 && nul_terminated {
+            break;
+        }
+
+        // Only uppercase ASCII is valid, but let's be a little
+        // more lenient
+        if b < 0x20 || b > 0x7e {
+            return None;
+        }
+
+        s.push(b as char);
+    }
+
+    Some(s)
+}
+
+/// Compute the checksum of the header bytes at [0x134, 0x14c], as
+/// done by the bootrom
+fn compute_header_checksum(rom: &[u8]) -> u8 {
+    rom[offsets::TITLE..offsets::HEADER_CHECKSUM]
+        .iter()
+        .fold(0u8, |x, &b| x.wrapping_sub(b).wrapping_sub(1))
+}
+
+/// Compute the sum of all the bytes in the ROM except the global
+/// checksum itself
+fn compute_global_checksum(rom: &[u8]) -> u16 {
+    let sum = rom.iter().fold(0u16, |s, &b| s.wrapping_add(b as u16));
+
+    let cs = &rom[offsets::GLOBAL_CHECKSUM..][..2];
+
+    sum.wrapping_sub(cs[0] as u16).wrapping_sub(cs[1] as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Header, CgbSupport, Destination};
+    use super::super::NINTENDO_LOGO;
+
+    #[test]
+    fn checksums() {
+        let mut rom = vec![0; 0x8000];
+
+        for (i, &b) in NINTENDO_LOGO.iter().enumerate() {
+            rom[0x104 + i] = b;
+        }
+
+        for (i, &b) in b"TEST".iter().enumerate() {
+            rom[0x134 + i] = b;
+        }
+
+        rom[0x14a] = 0x01;
+        rom[0x14d] = super::compute_header_checksum(&rom);
+
+        let global = super::compute_global_checksum(&rom);
+
+        rom[0x14e] = (global >> 8) as u8;
+        rom[0x14f] = global as u8;
+
+        let header = Header::new(&rom).unwrap();
+
+        assert!(header.title == Some("TEST".to_string()));
+        assert!(header.cgb == CgbSupport::None);
+        assert!(header.destination == Destination::Overseas);
+        assert!(header.logo_ok);
+        assert!(header.header_checksum_ok);
+        assert!(header.global_checksum_ok);
+
+        rom[0x7fff] = 0xff;
+
+        let header = Header::new(&rom).unwrap();
+
+        assert!(header.header_checksum_ok);
+        assert!(!header.global_checksum_ok);
+    }
+}
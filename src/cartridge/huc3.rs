@@ -0,0 +1,298 @@
+//! Hudson HuC3 clock and command interface.
+//!
+//! The HuC3 replaces the RAM enable register with a mode register
+//! which selects what's mapped at [0xa000, 0xbfff]: cartridge RAM,
+//! the command and response registers used to talk to the clock, a
+//! semaphore or the infrared port.
+//!
+//! The clock counts minutes and days. It's accessed through 4bit
+//! commands: the upper nibble of the value written in command mode is
+//! the command, the lower nibble its argument. The commands access a
+//! small nibble-addressed memory containing the time (minutes at
+//! [0x00, 0x02], days at [0x03, 0x05]) and the alarm.
+
+use cartridge::rtc::{now, push_le, read_le};
+use savestate::{Writer, Reader, SaveStateError};
+
+/// HuC3 state
+pub struct Huc3 {
+    /// Current mode, selects what's mapped in the RAM range
+    mode:          u8,
+    /// Minutes elapsed since the beginning of the day [0, 1439]
+    minutes:       u16,
+    /// Day counter (12 bits)
+    days:          u16,
+    /// Alarm minutes
+    alarm_minutes: u16,
+    /// Alarm day
+    alarm_days:    u16,
+    /// `true` if the alarm is enabled
+    alarm_enabled: bool,
+    /// Current address in the nibble memory
+    address:       u8,
+    /// Last command received
+    command:       u8,
+    /// Value returned by the last read command
+    value:         u8,
+    /// Counter of system clock ticks within the current minute
+    ticks:         u32,
+}
+
+impl Huc3 {
+    pub fn new() -> Huc3 {
+        Huc3 {
+            mode:          0,
+            minutes:       0,
+            days:          0,
+            alarm_minutes: 0,
+            alarm_days:    0,
+            alarm_enabled: false,
+            address:       0,
+            command:       0,
+            value:         0,
+            ticks:         0,
+        }
+    }
+
+    /// Called at each tick of the system clock
+    pub fn step(&mut self) {
+        self.ticks += 1;
+
+        if self.ticks == TICKS_PER_MINUTE {
+            self.ticks = 0;
+            self.advance(1);
+        }
+    }
+
+    /// Return the current mode
+    pub fn mode(&self) -> u8 {
+        self.mode
+    }
+
+    /// Set the current mode
+    pub fn set_mode(&mut self, mode: u8) {
+        self.mode = mode;
+    }
+
+    /// Handle a write to the command register
+    pub fn command(&mut self, val: u8) {
+        let arg = val & 0xf;
+
+        self.command = (val >> 4) & 0x7;
+
+        match self.command {
+            // Read value and increment address
+            0x1 => {
+                self.value = self.nibble(self.address);
+                self.address = self.address.wrapping_add(1);
+            }
+            // Write value, the address is only incremented by command
+            // 0x3
+            0x2 | 0x3 => {
+                let addr = self.address;
+
+                self.set_nibble(addr, arg);
+
+                if self.command == 0x3 {
+                    self.address = self.address.wrapping_add(1);
+                }
+            }
+            // Set address low nibble
+            0x4 => self.address = (self.address & 0xf0) | arg,
+            // Set address high nibble
+            0x5 => self.address = (self.address & 0x0f) | (arg << 4),
+            // Extended command. Only acknowledged for now.
+            0x6 => debug!("HuC3 extended command {:x}", arg),
+            c   => debug!("Unhandled HuC3 command {:x} ({:x})", c, arg),
+        }
+    }
+
+    /// Return the value of the response register
+    pub fn response(&self) -> u8 {
+        0x80 | (self.command << 4) | self.value
+    }
+
+    /// Advance the clock by `minutes`
+    pub fn advance(&mut self, minutes: u64) {
+        let total = self.minutes as u64 + minutes;
+
+        self.minutes = (total % MINUTES_PER_DAY) as u16;
+        self.days    =
+            ((self.days as u64 + total / MINUTES_PER_DAY) & 0xfff) as u16;
+    }
+
+    /// Serialize the clock state into the 17-byte footer appended to
+    /// the RAM image in save files. This is the layout used by
+    /// SameBoy: a 64bit UNIX timestamp followed by the minutes, days,
+    /// alarm minutes and alarm days as 16bit words and the alarm
+    /// enable flag, all little endian.
+    pub fn footer(&self) -> Vec<u8> {
+        let mut footer = Vec::with_capacity(FOOTER_SIZE);
+
+        push_le(&mut footer, now(), 8);
+        push_le(&mut footer, self.minutes       as u64, 2);
+        push_le(&mut footer, self.days          as u64, 2);
+        push_le(&mut footer, self.alarm_minutes as u64, 2);
+        push_le(&mut footer, self.alarm_days    as u64, 2);
+        push_le(&mut footer, self.alarm_enabled as u64, 1);
+
+        footer
+    }
+
+    /// Restore the clock state from a save file footer and catch up
+    /// with the time elapsed since it was written.
+    pub fn load_footer(&mut self, footer: &[u8]) {
+        let timestamp = read_le(&footer[0..], 8);
+
+        self.minutes       = read_le(&footer[8..],  2) as u16;
+        self.days          = read_le(&footer[10..], 2) as u16;
+        self.alarm_minutes = read_le(&footer[12..], 2) as u16;
+        self.alarm_days    = read_le(&footer[14..], 2) as u16;
+        self.alarm_enabled = footer[16] != 0;
+
+        let now = now();
+
+        if now > timestamp {
+            self.advance((now - timestamp) / 60);
+        }
+    }
+
+    /// Serialize the complete state for a save state, without
+    /// involving the wall clock
+    pub fn save_state(&self, w: &mut Writer) {
+        w.u8(self.mode);
+        w.u16(self.minutes);
+        w.u16(self.days);
+        w.u16(self.alarm_minutes);
+        w.u16(self.alarm_days);
+        w.bool(self.alarm_enabled);
+        w.u8(self.address);
+        w.u8(self.command);
+        w.u8(self.value);
+        w.u32(self.ticks);
+    }
+
+    /// Restore a state serialized by `save_state`
+    pub fn load_state(&mut self, r: &mut Reader) -> Result<(), SaveStateError> {
+        self.mode          = try!(r.u8());
+        self.minutes       = try!(r.u16());
+        self.days          = try!(r.u16());
+        self.alarm_minutes = try!(r.u16());
+        self.alarm_days    = try!(r.u16());
+        self.alarm_enabled = try!(r.bool());
+        self.address       = try!(r.u8());
+        self.command       = try!(r.u8());
+        self.value         = try!(r.u8());
+        self.ticks         = try!(r.u32());
+
+        if self.ticks >= TICKS_PER_MINUTE {
+            return Err(SaveStateError::Invalid("bad HuC3 tick counter"));
+        }
+
+        Ok(())
+    }
+
+    /// Return the value of the nibble at `addr`
+    fn nibble(&self, addr: u8) -> u8 {
+        let v = match addr {
+            0x00...0x02 => self.minutes >> (addr * 4),
+            0x03...0x05 => self.days >> ((addr - 0x03) * 4),
+            _           => {
+                debug!("HuC3 read from unsupported address {:02x}", addr);
+                0
+            }
+        };
+
+        (v & 0xf) as u8
+    }
+
+    /// Set the value of the nibble at `addr`
+    fn set_nibble(&mut self, addr: u8, val: u8) {
+        let val = val as u16;
+
+        match addr {
+            0x00...0x02 =>
+                set_nibble(&mut self.minutes, addr, val),
+            0x03...0x05 =>
+                set_nibble(&mut self.days, addr - 0x03, val),
+            0x58...0x5a =>
+                set_nibble(&mut self.alarm_minutes, addr - 0x58, val),
+            0x5b...0x5d =>
+                set_nibble(&mut self.alarm_days, addr - 0x5b, val),
+            0x5e =>
+                self.alarm_enabled = val & 1 != 0,
+            _ => debug!("HuC3 write to unsupported address {:02x}", addr),
+        }
+    }
+}
+
+/// Replace the nibble number `n` of `word` with `val`
+fn set_nibble(word: &mut u16, n: u8, val: u16) {
+    let shift = n * 4;
+
+    *word = (*word & !(0xfu16 << shift)) | (val << shift);
+}
+
+/// Size of the clock footer in save files
+pub const FOOTER_SIZE: usize = 17;
+
+/// Number of minutes in a day
+const MINUTES_PER_DAY: u64 = 24 * 60;
+
+/// Number of system clock ticks in one minute
+const TICKS_PER_MINUTE: u32 = 0x400000 * 60;
+
+#[cfg(test)]
+mod tests {
+    use super::Huc3;
+
+    /// Read the current time through the command interface
+    #[test]
+    fn read_time() {
+        let mut huc3 = Huc3::new();
+
+        // Day 2, 10:30
+        huc3.advance(2 * 24 * 60 + 10 * 60 + 30);
+
+        // Set address to 0
+        huc3.command(0x40);
+        huc3.command(0x50);
+
+        let mut minutes = 0;
+
+        for i in 0..3 {
+            huc3.command(0x10);
+            minutes |= ((huc3.response() & 0xf) as u16) << (i * 4);
+        }
+
+        assert!(minutes == 10 * 60 + 30);
+
+        huc3.command(0x10);
+        assert!(huc3.response() == 0x92);
+    }
+
+    /// Set the time through the command interface
+    #[test]
+    fn write_time() {
+        let mut huc3 = Huc3::new();
+
+        // Set address to 3 and write day 0x123
+        huc3.command(0x43);
+        huc3.command(0x50);
+        huc3.command(0x33);
+        huc3.command(0x32);
+        huc3.command(0x31);
+
+        assert!(huc3.days == 0x123);
+
+        let footer = huc3.footer();
+
+        assert!(footer.len() == super::FOOTER_SIZE);
+
+        let mut loaded = Huc3::new();
+
+        loaded.load_footer(&footer);
+
+        assert!(loaded.days == 0x123);
+    }
+}
@@ -0,0 +1,541 @@
+//! MBC7 accelerometer and 93LC56 serial EEPROM.
+//!
+//! The MBC7 has no SRAM, instead the [0xa000, 0xafff] range contains
+//! a set of registers repeated every 16 bytes: the latched X and Y
+//! values of the 2-axis accelerometer and a bit-banged interface to
+//! a 256 byte EEPROM used to store the save data.
+//!
+//! The RAM range is only accessible when both RAM enable registers
+//! are set (0x0a written at [0x0000, 0x1fff] and 0x40 written at
+//! [0x4000, 0x5fff]).
+
+use ui::Tilt;
+use savestate::{Writer, Reader, SaveStateError};
+
+/// MBC7 state
+pub struct Mbc7 {
+    /// First RAM enable register
+    enable1:     bool,
+    /// Second RAM enable register
+    enable2:     bool,
+    /// Current tilt of the cartridge, as reported by the frontend
+    tilt:        Tilt,
+    /// Latched X acceleration
+    x:           u16,
+    /// Latched Y acceleration
+    y:           u16,
+    /// `true` if the latch has been erased and is waiting for the
+    /// latch command
+    latch_armed: bool,
+    /// EEPROM interface
+    eeprom:      Eeprom,
+}
+
+impl Mbc7 {
+    pub fn new() -> Mbc7 {
+        Mbc7 {
+            enable1:     false,
+            enable2:     false,
+            tilt:        Tilt::new(),
+            x:           LATCH_ERASED,
+            y:           LATCH_ERASED,
+            latch_armed: false,
+            eeprom:      Eeprom::new(),
+        }
+    }
+
+    /// Set the value of the first RAM enable register
+    pub fn set_enable1(&mut self, enable: bool) {
+        self.enable1 = enable;
+    }
+
+    /// Set the value of the second RAM enable register
+    pub fn set_enable2(&mut self, enable: bool) {
+        self.enable2 = enable;
+    }
+
+    /// Update the current tilt of the cartridge. The value will be
+    /// sampled the next time the game latches the accelerometer.
+    pub fn set_tilt(&mut self, tilt: Tilt) {
+        self.tilt = tilt;
+    }
+
+    /// Serialize the mapper state. The tilt is input from the
+    /// frontend and the EEPROM contents live in the cartridge RAM so
+    /// neither is included.
+    pub fn save_state(&self, w: &mut Writer) {
+        w.bool(self.enable1);
+        w.bool(self.enable2);
+        w.u16(self.x);
+        w.u16(self.y);
+        w.bool(self.latch_armed);
+        self.eeprom.save_state(w);
+    }
+
+    /// Restore a state serialized by `save_state`
+    pub fn load_state(&mut self, r: &mut Reader) -> Result<(), SaveStateError> {
+        self.enable1     = try!(r.bool());
+        self.enable2     = try!(r.bool());
+        self.x           = try!(r.u16());
+        self.y           = try!(r.u16());
+        self.latch_armed = try!(r.bool());
+
+        self.eeprom.load_state(r)
+    }
+
+    /// Read register at `addr` (offset in the RAM range)
+    pub fn read(&self, addr: u32) -> u8 {
+        if !self.enabled() || addr >= REGISTERS_END {
+            return 0xff;
+        }
+
+        match (addr >> 4) & 0xf {
+            0x2 => self.x as u8,
+            0x3 => (self.x >> 8) as u8,
+            0x4 => self.y as u8,
+            0x5 => (self.y >> 8) as u8,
+            0x6 => 0x00,
+            0x8 => self.eeprom.register(),
+            _   => 0xff,
+        }
+    }
+
+    /// Write `val` to the register at `addr` (offset in the RAM
+    /// range). `data` is the EEPROM contents.
+    pub fn write(&mut self, addr: u32, val: u8, data: &mut [u8]) {
+        if !self.enabled() || addr >= REGISTERS_END {
+            return;
+        }
+
+        match (addr >> 4) & 0xf {
+            0x0 =>
+                if val == 0x55 {
+                    // Erase the latched values
+                    self.x           = LATCH_ERASED;
+                    self.y           = LATCH_ERASED;
+                    self.latch_armed = true;
+                },
+            0x1 =>
+                if val == 0xaa && self.latch_armed {
+                    self.latch();
+                },
+            0x8 => self.eeprom.set_register(val, data),
+            r   => debug!("Unhandled MBC7 register write {:x}: {:02x}", r, val),
+        }
+    }
+
+    /// Sample the accelerometer
+    fn latch(&mut self) {
+        // The X axis is reversed: the value decreases when the
+        // cartridge is tilted to the right
+        self.x = accel_value(-(self.tilt.x as i32));
+        self.y = accel_value(self.tilt.y as i32);
+
+        self.latch_armed = false;
+    }
+
+    fn enabled(&self) -> bool {
+        self.enable1 && self.enable2
+    }
+}
+
+/// Convert a tilt axis value into the accelerometer's register
+/// value. The accelerometer returns about 0x81d0 when flat and moves
+/// by roughly 0x70 for 1g.
+fn accel_value(axis: i32) -> u16 {
+    let delta = (axis * ACCEL_1G) / 0x8000;
+
+    (ACCEL_CENTER + delta) as u16
+}
+
+/// 93LC56 serial EEPROM in 16bit organization (128 words). Commands
+/// are shifted in one bit at a time on the rising edge of the clock
+/// while chip select is high: a start bit, a 2bit opcode and an 8bit
+/// address (the MSB is ignored), followed by 16 bits of data for
+/// write commands.
+struct Eeprom {
+    /// Chip select
+    cs:            bool,
+    /// Serial clock
+    clk:           bool,
+    /// Data in, as last written by the game
+    di:            bool,
+    /// Data out
+    dout:          bool,
+    /// `true` if erase/write commands are allowed
+    write_enabled: bool,
+    /// Bits shifted in so far
+    shift:         u16,
+    /// Number of valid bits in `shift`
+    nbits:         u8,
+    /// Write command waiting for its data word, if any
+    pending:       Option<Pending>,
+    /// Word being shifted out by a read command
+    read_word:     u16,
+    /// Number of bits of `read_word` left to shift out. 0 if no read
+    /// is in progress.
+    read_bits:     u8,
+    /// Address of the word being read, incremented for sequential
+    /// reads
+    read_addr:     u8,
+}
+
+/// Commands which need to receive a data word before executing
+#[derive(Clone,Copy)]
+enum Pending {
+    /// Write a single word
+    Write(u8),
+    /// Write the same word at all addresses
+    WriteAll,
+}
+
+impl Eeprom {
+    fn new() -> Eeprom {
+        Eeprom {
+            cs:            false,
+            clk:           false,
+            di:            false,
+            dout:          true,
+            write_enabled: false,
+            shift:         0,
+            nbits:         0,
+            pending:       None,
+            read_word:     0,
+            read_bits:     0,
+            read_addr:     0,
+        }
+    }
+
+    fn save_state(&self, w: &mut Writer) {
+        w.bool(self.cs);
+        w.bool(self.clk);
+        w.bool(self.di);
+        w.bool(self.dout);
+        w.bool(self.write_enabled);
+        w.u16(self.shift);
+        w.u8(self.nbits);
+
+        // Pending command type (0: none, 1: write, 2: write all)
+        // followed by the write address
+        match self.pending {
+            None                    => { w.u8(0); w.u8(0) }
+            Some(Pending::Write(a)) => { w.u8(1); w.u8(a) }
+            Some(Pending::WriteAll) => { w.u8(2); w.u8(0) }
+        }
+
+        w.u16(self.read_word);
+        w.u8(self.read_bits);
+        w.u8(self.read_addr);
+    }
+
+    fn load_state(&mut self, r: &mut Reader) -> Result<(), SaveStateError> {
+        self.cs            = try!(r.bool());
+        self.clk           = try!(r.bool());
+        self.di            = try!(r.bool());
+        self.dout          = try!(r.bool());
+        self.write_enabled = try!(r.bool());
+        self.shift         = try!(r.u16());
+        self.nbits         = try!(r.u8());
+
+        let pending = try!(r.u8());
+        let addr    = try!(r.u8());
+
+        self.pending = match pending {
+            0 => None,
+            1 => Some(Pending::Write(addr & ADDR_MASK)),
+            2 => Some(Pending::WriteAll),
+            _ => return Err(SaveStateError::Invalid("bad EEPROM command")),
+        };
+
+        self.read_word = try!(r.u16());
+        self.read_bits = try!(r.u8());
+        self.read_addr = try!(r.u8()) & ADDR_MASK;
+
+        Ok(())
+    }
+
+    /// Return the value of the EEPROM register: CS in bit 7, CLK in
+    /// bit 6, DI in bit 1 and DO in bit 0.
+    fn register(&self) -> u8 {
+        ((self.cs   as u8) << 7) |
+        ((self.clk  as u8) << 6) |
+        ((self.di   as u8) << 1) |
+        (self.dout  as u8)
+    }
+
+    fn set_register(&mut self, val: u8, data: &mut [u8]) {
+        let cs  = val & 0x80 != 0;
+        let clk = val & 0x40 != 0;
+
+        self.di = val & 0x02 != 0;
+
+        if !cs {
+            // Deselecting the chip aborts any command in progress
+            self.reset();
+        } else if clk && !self.clk {
+            self.clock(data);
+        }
+
+        self.cs  = cs;
+        self.clk = clk;
+    }
+
+    /// Handle a rising edge of the clock
+    fn clock(&mut self, data: &mut [u8]) {
+        if self.read_bits > 0 {
+            self.dout = self.read_word & 0x8000 != 0;
+
+            self.read_word <<= 1;
+            self.read_bits -= 1;
+
+            if self.read_bits == 0 {
+                // Sequential read: keep going with the next word
+                self.read_addr = (self.read_addr + 1) & ADDR_MASK;
+                self.read_word = word(data, self.read_addr);
+                self.read_bits = 16;
+            }
+
+            return;
+        }
+
+        if self.pending.is_none() && self.nbits == 0 && !self.di {
+            // Waiting for the start bit
+            return;
+        }
+
+        self.shift = (self.shift << 1) | (self.di as u16);
+        self.nbits += 1;
+
+        let pending = self.pending;
+
+        match pending {
+            Some(p) =>
+                if self.nbits == 16 {
+                    let w = self.shift;
+
+                    match p {
+                        Pending::Write(addr) => self.write(data, addr, w),
+                        Pending::WriteAll    =>
+                            for addr in 0..(ADDR_MASK + 1) {
+                                self.write(data, addr, w);
+                            },
+                    }
+
+                    self.reset();
+                },
+            // Start bit + opcode + address
+            None => if self.nbits == 11 {
+                self.command(data);
+            },
+        }
+    }
+
+    /// Decode the command in `shift`
+    fn command(&mut self, data: &mut [u8]) {
+        let cmd    = self.shift;
+        let opcode = (cmd >> 8) & 3;
+        let addr   = (cmd as u8) & ADDR_MASK;
+
+        self.shift = 0;
+        self.nbits = 0;
+
+        match opcode {
+            // READ
+            0b10 => {
+                // A dummy 0 bit precedes the data
+                self.dout      = false;
+                self.read_addr = addr;
+                self.read_word = word(data, addr);
+                self.read_bits = 16;
+            }
+            // WRITE
+            0b01 => self.pending = Some(Pending::Write(addr)),
+            // ERASE
+            0b11 => {
+                self.write(data, addr, 0xffff);
+                self.reset();
+            }
+            // Extended commands, selected by the upper address bits
+            _ => {
+                match (cmd >> 6) & 3 {
+                    // EWDS
+                    0b00 => self.write_enabled = false,
+                    // WRAL
+                    0b01 => {
+                        self.pending = Some(Pending::WriteAll);
+                        return;
+                    }
+                    // ERAL
+                    0b10 =>
+                        for a in 0..(ADDR_MASK + 1) {
+                            self.write(data, a, 0xffff);
+                        },
+                    // EWEN
+                    _    => self.write_enabled = true,
+                }
+
+                self.reset();
+            }
+        }
+    }
+
+    /// Write `w` at `addr` if writes are enabled
+    fn write(&mut self, data: &mut [u8], addr: u8, w: u16) {
+        if !self.write_enabled {
+            debug!("EEPROM write while protected");
+            return;
+        }
+
+        let off = addr as usize * 2;
+
+        data[off]     = w as u8;
+        data[off + 1] = (w >> 8) as u8;
+    }
+
+    /// Abort the current command and go back to waiting for a start
+    /// bit. Writes complete instantly so we always signal that we're
+    /// ready.
+    fn reset(&mut self) {
+        self.shift     = 0;
+        self.nbits     = 0;
+        self.pending   = None;
+        self.read_bits = 0;
+        self.dout      = true;
+    }
+}
+
+/// Return the EEPROM word at `addr`. Words are stored little endian.
+fn word(data: &[u8], addr: u8) -> u16 {
+    let off = addr as usize * 2;
+
+    data[off] as u16 | ((data[off + 1] as u16) << 8)
+}
+
+/// Size of the EEPROM in bytes
+pub const EEPROM_SIZE: usize = 256;
+
+/// Mask for the valid bits of an EEPROM word address
+const ADDR_MASK: u8 = 0x7f;
+
+/// The registers are only mapped in the first half of the RAM range
+const REGISTERS_END: u32 = 0x1000;
+
+/// Value of the accelerometer registers after a latch erase
+const LATCH_ERASED: u16 = 0x8000;
+
+/// Accelerometer value at rest
+const ACCEL_CENTER: i32 = 0x81d0;
+
+/// Approximate variation of the accelerometer value for 1g
+const ACCEL_1G: i32 = 0x70;
+
+#[cfg(test)]
+mod tests {
+    use super::{Mbc7, EEPROM_SIZE};
+    use ui::Tilt;
+
+    fn enabled() -> Mbc7 {
+        let mut mbc7 = Mbc7::new();
+
+        mbc7.set_enable1(true);
+        mbc7.set_enable2(true);
+
+        mbc7
+    }
+
+    /// Shift `nbits` of `val` into the EEPROM, MSB first
+    fn send(mbc7: &mut Mbc7, data: &mut [u8], val: u32, nbits: u8) {
+        for i in (0..nbits).rev() {
+            let di = (((val >> i) & 1) as u8) << 1;
+
+            mbc7.write(0x80, 0x80 | di, data);
+            mbc7.write(0x80, 0xc0 | di, data);
+        }
+    }
+
+    /// Clock `nbits` out of the EEPROM
+    fn receive(mbc7: &mut Mbc7, data: &mut [u8], nbits: u8) -> u32 {
+        let mut v = 0;
+
+        for _ in 0..nbits {
+            mbc7.write(0x80, 0x80, data);
+            mbc7.write(0x80, 0xc0, data);
+
+            v = (v << 1) | (mbc7.read(0x80) & 1) as u32;
+        }
+
+        v
+    }
+
+    fn deselect(mbc7: &mut Mbc7, data: &mut [u8]) {
+        mbc7.write(0x80, 0x00, data);
+    }
+
+    #[test]
+    fn accelerometer() {
+        let mut mbc7 = enabled();
+        let mut data = [0; EEPROM_SIZE];
+
+        // Scripted tilt sequence: flat, then fully tilted
+        let script = [(0,       0,       0x81d0, 0x81d0),
+                      (-0x8000, 0,       0x8240, 0x81d0),
+                      (0,       -0x8000, 0x81d0, 0x8160)];
+
+        for &(x, y, ex, ey) in script.iter() {
+            mbc7.set_tilt(Tilt { x: x, y: y });
+
+            mbc7.write(0x00, 0x55, &mut data);
+            mbc7.write(0x10, 0xaa, &mut data);
+
+            let rx = mbc7.read(0x20) as u16 | (mbc7.read(0x30) as u16) << 8;
+            let ry = mbc7.read(0x40) as u16 | (mbc7.read(0x50) as u16) << 8;
+
+            assert!(rx == ex);
+            assert!(ry == ey);
+        }
+    }
+
+    #[test]
+    fn disabled() {
+        let mut mbc7 = Mbc7::new();
+
+        mbc7.set_enable1(true);
+
+        assert!(mbc7.read(0x20) == 0xff);
+    }
+
+    #[test]
+    fn eeprom() {
+        let mut mbc7 = enabled();
+        let mut data = [0; EEPROM_SIZE];
+
+        // EWEN
+        send(&mut mbc7, &mut data, 0b1_00_11000000, 11);
+        deselect(&mut mbc7, &mut data);
+
+        // WRITE 0xbeef at address 5
+        send(&mut mbc7, &mut data, 0b1_01_00000101, 11);
+        send(&mut mbc7, &mut data, 0xbeef, 16);
+        deselect(&mut mbc7, &mut data);
+
+        assert!(data[10] == 0xef);
+        assert!(data[11] == 0xbe);
+
+        // READ address 5
+        send(&mut mbc7, &mut data, 0b1_10_00000101, 11);
+
+        // Dummy bit
+        assert!(mbc7.read(0x80) & 1 == 0);
+
+        assert!(receive(&mut mbc7, &mut data, 16) == 0xbeef);
+        deselect(&mut mbc7, &mut data);
+
+        // EWDS then attempt to ERASE
+        send(&mut mbc7, &mut data, 0b1_00_00000000, 11);
+        deselect(&mut mbc7, &mut data);
+        send(&mut mbc7, &mut data, 0b1_11_00000101, 11);
+        deselect(&mut mbc7, &mut data);
+
+        assert!(data[10] == 0xef);
+    }
+}
@@ -3,13 +3,27 @@
 
 use std::fmt::{Debug, Formatter, Error};
 use std::path::{Path, PathBuf};
-use std::fs::{File, OpenOptions};
-use std::iter::repeat;
+use std::fs::{File, metadata, rename, copy, create_dir_all};
 use ascii::AsciiCast;
-use std::io::{SeekFrom, Read, Write, Seek};
+use std::io::{Read, Write};
 use std::io::Result as IoResult;
 
+use savestate::{Writer, Reader, SaveStateError};
+
+pub use self::error::CartridgeError;
+pub use self::header::{Header, CgbSupport, Destination};
+pub use self::database::{KnownCartridge, DatabaseKey};
+
 mod models;
+mod error;
+mod header;
+mod patch;
+mod database;
+mod archive;
+mod rtc;
+mod huc3;
+mod mbc7;
+mod camera;
 
 /// Common state for all cartridge types
 pub struct Cartridge {
@@ -18,13 +32,16 @@ pub struct Cartridge {
     /// Cartridge RAM data
     ram:        Vec<u8>,
     /// Total number of ROM banks in this cart
-    rom_banks:  u8,
+    rom_banks:  u16,
     /// Current number of the rom bank mapped at [0x4000, 0x7fff]
-    rom_bank:   u8,
+    rom_bank:   u16,
     /// Current bank offset for the bank mapped at [0x4000, 0x7fff].
     /// This value is added to ROM register addresses when they're in
     /// that range.
     rom_offset: i32,
+    /// Offset of the bank mapped at [0x0000, 0x3fff]. Always 0 except
+    /// for MBC1 carts in RAM banking mode.
+    rom0_offset: i32,
     /// Current bank offset for the RAM
     ram_offset: u32,
     /// If `true` RAM is write protected
@@ -32,135 +49,441 @@ pub struct Cartridge {
     /// Certain cartridges allow banking either the RAM or ROM
     /// depending on the value of this flag.
     bank_ram:   bool,
+    /// `true` for MBC1 multicarts (MBC1M) which wire the upper bank
+    /// bits differently
+    multicart:  bool,
     /// struct used to handle model specific functions
     model:      models::Model,
+    /// Database entry overriding the header, if any
+    known:      Option<&'static KnownCartridge>,
+    /// State of the rumble motor on cartridges that have one
+    rumble:     bool,
+    /// Real time clock, only present on MBC3+TIMER cartridges
+    rtc:        Option<rtc::Rtc>,
+    /// RTC register currently mapped in the RAM range instead of a
+    /// RAM bank (if any)
+    rtc_register: Option<u8>,
+    /// Clock and command interface of HuC3 cartridges
+    huc3:       Option<huc3::Huc3>,
+    /// Accelerometer and EEPROM of MBC7 cartridges
+    mbc7:       Option<mbc7::Mbc7>,
+    /// Camera ASIC of Game Boy Camera cartridges
+    camera:     Option<camera::Camera>,
+    /// `true` when the infrared port is mapped in the RAM range
+    /// instead of the RAM (HuC1)
+    ir_mode:    bool,
     /// Path to the ROM image for this cartridge
     path:       PathBuf,
-    /// optional save file used to store non-volatile RAM on emulator
-    /// shutdown
-    save_file:  Option<File>,
+    /// optional save file used to store non-volatile RAM
+    save_path:  Option<PathBuf>,
+    /// `true` if the RAM has been modified since the last save
+    dirty:      bool,
+    /// Number of system clock ticks since the last RAM write
+    idle_ticks: u32,
+    /// Number of idle system clock ticks after the last RAM write
+    /// before flushing the save file. `None` if we only save when
+    /// the cartridge is dropped.
+    flush_delay: Option<u32>,
+    /// If `true` the previous save is kept as a ".sav.bak" file
+    /// every time we save
+    backup:     bool,
 }
 
 impl Cartridge {
+    /// Load a Cartridge ROM from `path` with the default options.
+    pub fn from_path(rom_path: &Path) -> Result<Cartridge, CartridgeError> {
+        Cartridge::from_path_with_options(rom_path, &LoadOptions::new())
+    }
+
     /// Load a Cartridge ROM from `path`.
-    pub fn from_path(rom_path: &Path) -> IoResult<Cartridge> {
-        let mut source = try!(File::open(rom_path));
+    pub fn from_path_with_options(rom_path: &Path,
+                                  options: &LoadOptions)
+                                  -> Result<Cartridge, CartridgeError> {
+        let rom = try!(load_rom(rom_path, options));
+
+        Cartridge::from_rom(rom, rom_path, options)
+    }
 
-        let mut rom = Vec::new();
+    /// Build a cartridge around the ROM image loaded from `rom_path`
+    fn from_rom(rom: Vec<u8>,
+                rom_path: &Path,
+                options: &LoadOptions) -> Result<Cartridge, CartridgeError> {
+        let rombanks = try!(rom_banks_for_size(rom.len()));
 
-        // There must always be at least two ROM banks
-        try!((&mut source).take(2 * ROM_BANK_SIZE as u64)
-             .read_to_end(&mut rom));
+        let known = database::lookup(&rom);
 
-        let model = models::from_id(rom[offsets::TYPE]);
+        if let Some(k) = known {
+            info!("Found in cartridge database: {}", k.name);
+        }
+
+        let id = match known.and_then(|k| k.cartridge_type) {
+            Some(id) => id,
+            None     => rom[offsets::TYPE],
+        };
+
+        let model = match models::from_id(id) {
+            Some(m) => m,
+            None    => return Err(CartridgeError::UnknownModel(id)),
+        };
+
+        let rtc = match models::has_rtc(id) {
+            true  => Some(rtc::Rtc::new()),
+            false => None,
+        };
+
+        let huc3 = match model.name == "HuC3" {
+            true  => Some(huc3::Huc3::new()),
+            false => None,
+        };
+
+        let mbc7 = match model.name == "MBC7" {
+            true  => Some(mbc7::Mbc7::new()),
+            false => None,
+        };
+
+        let camera = match model.name == "POCKET CAMERA" {
+            true  => {
+                let source = Box::new(::ui::camera::TestPattern);
+
+                Some(camera::Camera::new(source))
+            }
+            false => None,
+        };
 
         let mut cartridge = Cartridge {
             rom:        rom,
             ram:        Vec::new(),
-            rom_banks:  2,
+            rom_banks:  rombanks,
             // Default to bank 1 for bankable region
             rom_bank:   1,
             rom_offset: 0,
+            rom0_offset: 0,
             ram_offset: 0,
             ram_wp:     true,
             bank_ram:   false,
+            multicart:  false,
             model:      model,
+            known:      known,
+            rumble:     false,
+            rtc:        rtc,
+            rtc_register: None,
+            huc3:       huc3,
+            mbc7:       mbc7,
+            camera:     camera,
+            ir_mode:    false,
             path:       PathBuf::from(rom_path),
-            save_file:  None,
+            save_path:  None,
+            dirty:      false,
+            idle_ticks: 0,
+            flush_delay: options.flush_delay_ms.map(|ms| {
+                (ms as u64 * SYSCLK_FREQ / 1000) as u32
+            }),
+            backup:     options.backup,
         };
 
-        let rombanks = match cartridge.parse_rom_banks() {
-            Some(n) => n,
-            None    => panic!("Can't determine ROM size"),
-        };
+        // Mirror the first bank if the image only has one
+        cartridge.set_rom_offset(0);
+
+        // The header is not always right (overdumps, homebrew...),
+        // the size of the image wins.
+        match cartridge.parse_rom_banks() {
+            Some(n) if n == rombanks => (),
+            Some(n) =>
+                warn!("Header declares {} ROM banks, the image contains {}",
+                      n, rombanks),
+            None =>
+                warn!("Unknown ROM size code 0x{:02x}, the image \
+                       contains {} banks",
+                      cartridge.rom[offsets::ROM_SIZE], rombanks),
+        }
 
-        cartridge.rom_banks = rombanks;
+        cartridge.multicart =
+            match known.and_then(|k| k.multicart) {
+                Some(m) => m,
+                None    => cartridge.detect_multicart(),
+            };
 
-        // Read the remaining roms banks
-        if rombanks > 2 {
-            let remb      = (rombanks - 2) as usize;
-            let mut off   = 2    * ROM_BANK_SIZE as usize;
-            let mut remsz = remb * ROM_BANK_SIZE as usize;
+        if cartridge.multicart {
+            info!("MBC1 multicart detected");
+        }
 
-            // Reserve space for the remaining banks
-            cartridge.rom.extend(repeat(0).take(remsz));
+        try!(cartridge.init_ram(options));
 
-            while remsz > 0 {
-                let r = try!(source.read(&mut cartridge.rom[off..]));
+        Ok(cartridge)
+    }
 
-                remsz -= r;
-                off   += r;
-            }
+    /// MBC1 multicarts are 1MB collections of 256KB games. There's
+    /// nothing in the header to tell them apart from regular MBC1
+    /// carts but each game has its own header so we can look for the
+    /// Nintendo logo at the beginning of the second game (bank 0x10).
+    fn detect_multicart(&self) -> bool {
+        if self.model.name != "MBC1" || self.rom_banks != 64 {
+            return false;
         }
 
-        try!(cartridge.init_ram());
+        let logo = 0x10 * ROM_BANK_SIZE as usize + offsets::LOGO;
 
-        Ok(cartridge)
+        &self.rom[logo..logo + NINTENDO_LOGO.len()] == &NINTENDO_LOGO[..]
     }
 
-    /// Init cartridge RAM and tie it with a `File` for saving if
-    /// necessary.
-    fn init_ram(&mut self) -> IoResult<()> {
+    /// Init cartridge RAM and load the save file if necessary.
+    fn init_ram(&mut self,
+                options: &LoadOptions) -> Result<(), CartridgeError> {
         let (rambanks, banksize) = match self.parse_ram_banks() {
             Some(v) => v,
-            None    => panic!("Can't determine RAM size"),
+            None    => {
+                let id = self.rom[offsets::RAM_SIZE];
+
+                return Err(CartridgeError::UnknownRamSize(id));
+            }
         };
 
         let ramsize = rambanks * banksize;
 
-        if ramsize == 0 {
+        if ramsize == 0 && !self.has_clock() {
             // No RAM on this cartridge, we're done
             return Ok(());
         }
 
-        // We have some RAM, open the save file or create it if it
-        // doesn't exist yet
-        let mut savepath = self.path.clone();
-        savepath.set_extension("sav");
+        if options.no_save {
+            // Blank RAM, never written back
+            self.ram = vec![0; ramsize];
+            return Ok(());
+        }
+
+        // We have some RAM or a clock, open the save file or create it
+        // if it doesn't exist yet
+        let savepath = try!(self.save_path_for(options));
 
-        let mut save_file = try!(OpenOptions::new()
-                                 .read(true)
-                                 .write(true)
-                                 .create(true)
-                                 .open(savepath.clone()));
+        let save_size = match metadata(&savepath) {
+            Ok(m)  => m.len(),
+            // No save yet
+            Err(_) => 0,
+        };
 
-        let save_size = try!(save_file.metadata()).len();
+        // Size of the optional clock footer following the RAM image
+        let footer_size = save_size.wrapping_sub(ramsize as u64) as usize;
 
         if save_size == 0 {
-            // The file is empty (probably new). initialize
-            // the RAM with 0s.
+            // The file is empty or doesn't exist (probably
+            // new). initialize the RAM with 0s.
             self.ram = vec![0; ramsize];
-            // Then fill the file with the right amount of 0s
-            // to reserve enough space for saving later.
-            try!(save_file.write_all(&self.ram));
+            // Then create the file right away so that we notice
+            // early if we can't write there.
+            try!(self.write_save(&savepath));
         } else if save_size == (ramsize as u64) {
-            // The file contains a RAM image
+            // The file contains a RAM image. If the cartridge has a
+            // clock the save was made without one, just start the
+            // clock from scratch.
+            let save_file = try!(File::open(&savepath));
+
+            try!(save_file.take(ramsize as u64).read_to_end(&mut self.ram));
+        } else if self.valid_clock_footer_size(footer_size) {
+            // The file contains a RAM image followed by the clock
+            // state
+            let mut save_file = try!(File::open(&savepath));
+
             try!((&mut save_file).take(ramsize as u64).read_to_end(&mut self.ram));
+
+            let mut footer = Vec::with_capacity(footer_size);
+
+            try!(save_file.read_to_end(&mut footer));
+
+            self.load_clock_footer(&footer);
         } else {
-            panic!("Unexpected save file size for {}: expected {} got {}",
-                   savepath.display(), ramsize, save_size);
+            return Err(CartridgeError::BadSaveSize {
+                path:     savepath,
+                expected: ramsize as u64,
+                got:      save_size,
+            });
         }
 
-        // Store the file handle to save progress later
-        self.save_file = Some(save_file);
+        // Store the path to save progress later
+        self.save_path = Some(savepath);
 
         Ok(())
     }
 
+    /// Return the path of the save file for this cartridge. If
+    /// `options` contains a save directory it's created if it doesn't
+    /// exist yet.
+    fn save_path_for(&self, options: &LoadOptions) -> IoResult<PathBuf> {
+        let name =
+            match options.save_naming {
+                // For compressed ROMs the save is named after the
+                // archive (foo.zip -> foo.sav).
+                SaveNaming::RomName =>
+                    self.path.with_extension("sav")
+                    .file_name()
+                    .map(PathBuf::from)
+                    .unwrap_or(PathBuf::from("rom.sav")),
+                SaveNaming::GlobalChecksum => {
+                    let header = self.header();
+
+                    // Keep the title around so that humans can tell
+                    // which game the save belongs to
+                    let title: String =
+                        header.title.unwrap_or(String::new())
+                        .chars()
+                        .map(|c| if c.is_alphanumeric() { c } else { '_' })
+                        .collect();
+
+                    PathBuf::from(format!("{}-{:04x}.sav",
+                                          title, header.global_checksum))
+                }
+            };
+
+        match options.save_dir {
+            Some(ref dir) => {
+                try!(create_dir_all(dir));
+
+                Ok(dir.join(name))
+            }
+            None =>
+                // Put the save next to the ROM
+                Ok(self.path.with_file_name(name)),
+        }
+    }
+
+    /// Return the path of the save state file for `slot`. It's put
+    /// next to the save file (or next to the ROM if the cartridge
+    /// doesn't have any battery backed RAM).
+    pub fn state_path(&self, slot: u8) -> PathBuf {
+        let base = self.save_path.as_ref().unwrap_or(&self.path);
+
+        base.with_extension(format!("ss{}", slot))
+    }
+
+    /// Return `true` if the cartridge has a clock whose state must
+    /// be stored in the save file
+    fn has_clock(&self) -> bool {
+        self.rtc.is_some() || self.huc3.is_some()
+    }
+
+    /// Return the serialized clock state to be appended to the RAM
+    /// image in the save file, if the cartridge has a clock
+    fn clock_footer(&self) -> Option<Vec<u8>> {
+        if let Some(ref rtc) = self.rtc {
+            return Some(rtc.footer());
+        }
+
+        if let Some(ref huc3) = self.huc3 {
+            return Some(huc3.footer());
+        }
+
+        None
+    }
+
+    /// Return `true` if `size` is a valid clock footer size for this
+    /// cartridge
+    fn valid_clock_footer_size(&self, size: usize) -> bool {
+        if self.rtc.is_some() {
+            size == rtc::FOOTER_SIZE || size == rtc::FOOTER_SIZE_OLD
+        } else if self.huc3.is_some() {
+            size == huc3::FOOTER_SIZE
+        } else {
+            false
+        }
+    }
+
+    /// Restore the clock state from a save file footer
+    fn load_clock_footer(&mut self, footer: &[u8]) {
+        if let Some(ref mut rtc) = self.rtc {
+            rtc.load_footer(footer);
+        }
+
+        if let Some(ref mut huc3) = self.huc3 {
+            huc3.load_footer(footer);
+        }
+    }
+
+    /// Called at each tick of the system clock. Move the emulated
+    /// state one step forward.
+    pub fn step(&mut self) {
+        if let Some(ref mut rtc) = self.rtc {
+            rtc.step();
+        }
+
+        if let Some(ref mut huc3) = self.huc3 {
+            huc3.step();
+        }
+
+        if let Some(ref mut camera) = self.camera {
+            if camera.step(&mut self.ram) {
+                // A new picture has been stored in RAM
+                self.dirty      = true;
+                self.idle_ticks = 0;
+            }
+        }
+
+        if self.dirty {
+            self.flush_step();
+        }
+    }
+
+    /// Flush the save file once the RAM hasn't been written to for
+    /// `flush_delay` ticks. That way we don't lose the progress if
+    /// the emulator crashes or gets killed without hammering the disk
+    /// while the game is busy writing.
+    fn flush_step(&mut self) {
+        let delay = match self.flush_delay {
+            Some(d) => d,
+            None    => return,
+        };
+
+        self.idle_ticks += 1;
+
+        if self.idle_ticks >= delay {
+            self.idle_ticks = 0;
+
+            if let Err(e) = self.save_ram() {
+                // We'll try again after another delay
+                warn!("Couldn't save: {}", e);
+            }
+        }
+    }
+
     /// Update the save file
     pub fn save_ram(&mut self) -> IoResult<()> {
-        if let Some(mut f) = self.save_file.as_mut() {
-            // Rewind to the beginning of the file and update its
-            // contents
-            println!("Saving non-volatile memory");
+        if let Some(path) = self.save_path.clone() {
+            debug!("Saving non-volatile memory");
 
-            try!(f.seek(SeekFrom::Start(0)));
-            try!(f.write_all(&self.ram));
+            try!(self.write_save(&path));
+
+            self.dirty = false;
         }
 
         Ok(())
     }
 
+    /// Write the RAM image to `path` followed by the clock state (if
+    /// any). The data is written to a temporary file which is then
+    /// renamed over the previous save so that we never end up with a
+    /// half-written save file.
+    fn write_save(&self, path: &Path) -> IoResult<()> {
+        let tmp_path = path.with_extension("sav.tmp");
+
+        {
+            let mut f = try!(File::create(&tmp_path));
+
+            try!(f.write_all(&self.ram));
+
+            if let Some(footer) = self.clock_footer() {
+                // Store the clock state along with the current time
+                // to catch up when we're reloaded
+                try!(f.write_all(&footer));
+            }
+
+            try!(f.sync_all());
+        }
+
+        if self.backup && metadata(path).is_ok() {
+            try!(copy(path, path.with_extension("sav.bak")));
+        }
+
+        rename(&tmp_path, path)
+    }
+
     /// Attempt to retreive the rom's name
     pub fn name(&self) -> Option<String> {
         let mut name = String::with_capacity(16);
@@ -190,9 +513,16 @@ impl Cartridge {
         Some(name)
     }
 
+    /// Return the parsed cartridge header
+    pub fn header(&self) -> Header {
+        // The ROM is always at least as big as the header, this is
+        // checked when the cartridge is loaded
+        Header::new(&self.rom).unwrap()
+    }
+
     /// Return the number of ROM banks declared in the header. Each
     /// bank is 16KB.
-    fn parse_rom_banks(&self) -> Option<u8> {
+    fn parse_rom_banks(&self) -> Option<u16> {
         let id = self.rom_byte(offsets::ROM_SIZE as u16);
 
         let nbanks =
@@ -204,6 +534,8 @@ impl Cartridge {
                 0x04 => 32,
                 0x05 => 64,
                 0x06 => 128,
+                0x07 => 256,
+                0x08 => 512,
                 0x52 => 72,
                 0x53 => 80,
                 0x54 => 96,
@@ -217,15 +549,25 @@ impl Cartridge {
     /// Return the number of RAM banks for this ROM along with the
     /// size of each bank in bytes.
     pub fn parse_ram_banks(&self) -> Option<(usize, usize)> {
+        let model = self.model;
 
-        let model = models::from_id(self.rom_byte(offsets::TYPE as u16));
+        if let Some(ram) = self.known.and_then(|k| k.ram) {
+            return Some(ram);
+        }
 
         // Special case for MBC2, the RAM_SIZE field is not
         // trustworthy here (it advertises 0 banks but there's still
         // some RAM on the cartridge).
         if model.name == "MBC2" {
-            // MBC2 contains 1 "bank" of 256bytes
-            return Some((1, 256));
+            // MBC2 contains 512 half-bytes, each stored in a full
+            // byte
+            return Some((1, 512));
+        }
+
+        // MBC7 has no RAM but we store the EEPROM contents in its
+        // place so that it ends up in the save file
+        if model.name == "MBC7" {
+            return Some((1, mbc7::EEPROM_SIZE));
         }
 
         let id = self.rom_byte(offsets::RAM_SIZE as u16);
@@ -244,16 +586,20 @@ impl Cartridge {
         Some((nbanks, bank_size_kb * 1024))
     }
 
+    /// Return the ROM byte mapped at `offset` in the [0x0000,
+    /// 0x7fff] range
     pub fn rom_byte(&self, offset: u16) -> u8 {
         let off = offset as i32;
 
         if off < ROM_BANK_SIZE {
-            self.rom[off as usize]
+            self.rom[(self.rom0_offset + off) as usize]
         } else {
             self.rom[(self.rom_offset + off) as usize]
         }
     }
 
+    /// Handle a write in the ROM range. The ROM itself can't be
+    /// modified, these writes configure the mapper.
     pub fn set_rom_byte(&mut self, offset: u16, val: u8) {
         (self.model.write_rom)(self, offset, val)
     }
@@ -286,27 +632,47 @@ impl Cartridge {
         }
 
         (self.model.write_ram)(self, addr, val);
+
+        self.dirty      = true;
+        self.idle_ticks = 0;
     }
 
     /// Retreive the number of ROM banks in the cartridge
-    pub fn rom_banks(&self) -> u8 {
+    pub fn rom_banks(&self) -> u16 {
         self.rom_banks
     }
 
     /// Retrieve current ROM bank number for the bankable range at
     /// [0x4000, 0x7fff]
-    pub fn rom_bank(&self) -> u8 {
+    pub fn rom_bank(&self) -> u16 {
         self.rom_bank
     }
 
     /// Set new ROM bank number for the bankable range at
     /// [0x4000, 0x7fff]
-    pub fn set_rom_bank(&mut self, bank: u8) {
+    pub fn set_rom_bank(&mut self, bank: u16) {
         self.rom_bank = bank;
     }
 
+    /// Set the offset of the bank mapped at [0x4000, 0x7fff]. Banks
+    /// past the end of the ROM are mirrored.
     pub fn set_rom_offset(&mut self, offset: i32) {
-        self.rom_offset = offset;
+        // The offset is added to the CPU address which already
+        // contains a one bank offset
+        let start = (offset + ROM_BANK_SIZE) as usize % self.rom.len();
+
+        self.rom_offset = start as i32 - ROM_BANK_SIZE;
+    }
+
+    /// Set the offset of the bank mapped at [0x0000, 0x3fff]. Banks
+    /// past the end of the ROM are mirrored.
+    pub fn set_rom0_offset(&mut self, offset: i32) {
+        self.rom0_offset = (offset as usize % self.rom.len()) as i32;
+    }
+
+    /// Return `true` if the cartridge is an MBC1 multicart
+    pub fn multicart(&self) -> bool {
+        self.multicart
     }
 
     /// Enable or disable RAM write protect
@@ -330,34 +696,338 @@ impl Cartridge {
         self.ram_offset = bank as u32 * 8 * 1024;
     }
 
+    /// Return the CRC-32 of the ROM image, used to make sure save
+    /// states are loaded with the right game
+    pub fn rom_crc32(&self) -> u32 {
+        ::crc::crc32(&self.rom)
+    }
+
+    /// Serialize the mapper state, the RAM and the state of the
+    /// optional hardware (clock, accelerometer, camera...)
+    pub fn save_state(&self, w: &mut Writer) {
+        w.bytes(&self.ram);
+        w.u16(self.rom_bank);
+        w.u32(self.rom_offset as u32);
+        w.u32(self.rom0_offset as u32);
+        w.u32(self.ram_offset);
+        w.bool(self.ram_wp);
+        w.bool(self.bank_ram);
+        w.bool(self.rumble);
+        w.bool(self.rtc_register.is_some());
+        w.u8(self.rtc_register.unwrap_or(0));
+        w.bool(self.ir_mode);
+
+        if let Some(ref rtc) = self.rtc {
+            rtc.save_state(w);
+        }
+
+        if let Some(ref huc3) = self.huc3 {
+            huc3.save_state(w);
+        }
+
+        if let Some(ref mbc7) = self.mbc7 {
+            mbc7.save_state(w);
+        }
+
+        if let Some(ref camera) = self.camera {
+            camera.save_state(w);
+        }
+    }
+
+    /// Restore a state serialized by `save_state`. The state must
+    /// have been made with the same ROM.
+    pub fn load_state(&mut self, r: &mut Reader) -> Result<(), SaveStateError> {
+        try!(r.bytes_into(&mut self.ram));
+
+        self.rom_bank    = try!(r.u16());
+        self.rom_offset  = try!(r.u32()) as i32;
+        self.rom0_offset = try!(r.u32()) as i32;
+        self.ram_offset  = try!(r.u32());
+
+        let rom_len = self.rom.len() as i32;
+
+        if self.rom_offset < -ROM_BANK_SIZE ||
+            self.rom_offset + 2 * ROM_BANK_SIZE > rom_len ||
+            self.rom0_offset < 0 ||
+            self.rom0_offset + ROM_BANK_SIZE > rom_len {
+            return Err(SaveStateError::Invalid("bad ROM bank offset"));
+        }
+
+        self.ram_wp   = try!(r.bool());
+        self.bank_ram = try!(r.bool());
+        self.rumble   = try!(r.bool());
+
+        let has_rtc_register = try!(r.bool());
+        let rtc_register     = try!(r.u8());
+
+        self.rtc_register = match has_rtc_register {
+            true  => Some(rtc_register),
+            false => None,
+        };
+
+        // Only MBC3 carts with a clock map its registers (0x08 to
+        // 0x0c) in the RAM area
+        match self.rtc_register {
+            Some(0x08...0x0c) if self.rtc.is_some() => (),
+            Some(_) => return Err(SaveStateError::Invalid("bad RTC register")),
+            None    => (),
+        }
+
+        self.ir_mode = try!(r.bool());
+
+        if let Some(ref mut rtc) = self.rtc {
+            try!(rtc.load_state(r));
+        }
+
+        if let Some(ref mut huc3) = self.huc3 {
+            try!(huc3.load_state(r));
+        }
+
+        if let Some(ref mut mbc7) = self.mbc7 {
+            try!(mbc7.load_state(r));
+        }
+
+        if let Some(ref mut camera) = self.camera {
+            try!(camera.load_state(r));
+        }
+
+        // The RAM changed under the game's feet, make sure it ends
+        // up in the save file
+        self.dirty      = true;
+        self.idle_ticks = 0;
+
+        Ok(())
+    }
+
+    /// Return `true` if the rumble motor is currently running
+    pub fn rumble(&self) -> bool {
+        self.rumble
+    }
+
+    /// Turn the rumble motor on or off
+    pub fn set_rumble(&mut self, on: bool) {
+        self.rumble = on;
+    }
+
+    /// Return the RTC register currently mapped in the RAM range, if
+    /// any
+    pub fn rtc_register(&self) -> Option<u8> {
+        self.rtc_register
+    }
+
+    /// Map RTC register `reg` in the RAM range, or go back to RAM if
+    /// `reg` is `None`.
+    pub fn set_rtc_register(&mut self, reg: Option<u8>) {
+        self.rtc_register = reg;
+    }
+
+    /// Return the cartridge's real time clock, if it has one
+    pub fn rtc(&self) -> Option<&rtc::Rtc> {
+        self.rtc.as_ref()
+    }
+
+    /// Return a mutable reference to the cartridge's real time
+    /// clock, if it has one
+    pub fn rtc_mut(&mut self) -> Option<&mut rtc::Rtc> {
+        self.rtc.as_mut()
+    }
+
+    /// Return the HuC3 clock and command interface, if present
+    pub fn huc3(&self) -> Option<&huc3::Huc3> {
+        self.huc3.as_ref()
+    }
+
+    /// Return a mutable reference to the HuC3 clock and command
+    /// interface, if present
+    pub fn huc3_mut(&mut self) -> Option<&mut huc3::Huc3> {
+        self.huc3.as_mut()
+    }
+
+    /// Return the MBC7 state along with the EEPROM contents, if
+    /// present
+    pub fn mbc7_mut(&mut self) -> Option<(&mut mbc7::Mbc7, &mut [u8])> {
+        match self.mbc7 {
+            Some(ref mut m) => Some((m, &mut self.ram)),
+            None            => None,
+        }
+    }
+
+    /// Return the MBC7 state, if present
+    pub fn mbc7(&self) -> Option<&mbc7::Mbc7> {
+        self.mbc7.as_ref()
+    }
+
+    /// Return the camera ASIC, if present
+    pub fn camera(&self) -> Option<&camera::Camera> {
+        self.camera.as_ref()
+    }
+
+    /// Return a mutable reference to the camera ASIC, if present
+    pub fn camera_mut(&mut self) -> Option<&mut camera::Camera> {
+        self.camera.as_mut()
+    }
+
+    /// Return `true` if this is a Game Boy Camera cartridge
+    pub fn has_camera(&self) -> bool {
+        self.camera.is_some()
+    }
+
+    /// Replace the image source used by the Game Boy Camera. Does
+    /// nothing for other cartridges.
+    pub fn set_image_source(&mut self, source: Box<::ui::ImageSource>) {
+        if let Some(ref mut camera) = self.camera {
+            camera.set_source(source);
+        }
+    }
+
+    /// Update the console tilt for cartridges with an accelerometer
+    pub fn set_tilt(&mut self, tilt: ::ui::Tilt) {
+        if let Some(ref mut m) = self.mbc7 {
+            m.set_tilt(tilt);
+        }
+    }
+
+    /// Return `true` if the infrared port is mapped in the RAM range
+    pub fn ir_mode(&self) -> bool {
+        self.ir_mode
+    }
+
+    /// Map the infrared port in the RAM range instead of the RAM
+    pub fn set_ir_mode(&mut self, ir: bool) {
+        self.ir_mode = ir;
+    }
+
     /// Create a Cartridge instance from a ROM provided in a
-    /// Vec<u8>. Usefull for tests. Creates a MBC0 model without
-    /// banking.
+    /// Vec<u8>. Usefull for tests. The model is taken from the
+    /// header, the RAM starts blank and is never saved.
     #[cfg(test)]
-    pub fn from_vec(rom: Vec<u8>) -> Cartridge {
-        Cartridge {
-            rom:        rom,
-            ram:        Vec::new(),
-            rom_bank:   1,
-            rom_banks:  2,
-            rom_offset: 0,
-            ram_offset: 0,
-            ram_wp:     true,
-            bank_ram:   false,
-            model:      models::from_id(0x00),
-            path:       PathBuf::from("dummy"),
-            save_file:  None,
+    pub fn from_vec(rom: Vec<u8>) -> Result<Cartridge, CartridgeError> {
+        let mut options = LoadOptions::new();
+
+        options.no_save = true;
+
+        Cartridge::from_rom(rom, Path::new("dummy"), &options)
+    }
+}
+
+/// Return the number of ROM banks in an image of `size` bytes.
+/// Images which are not made of whole banks are considered
+/// truncated.
+fn rom_banks_for_size(size: usize) -> Result<u16, CartridgeError> {
+    let bank_size = ROM_BANK_SIZE as usize;
+
+    if size < offsets::HEADER_END || size % bank_size != 0 {
+        return Err(CartridgeError::Truncated(size));
+    }
+
+    let nbanks = size / bank_size;
+
+    if nbanks > 0xffff {
+        return Err(CartridgeError::TooLarge(size));
+    }
+
+    Ok(nbanks as u16)
+}
+
+/// Options used when loading a cartridge
+#[derive(Clone,Debug)]
+pub struct LoadOptions {
+    /// IPS, UPS or BPS patch to apply to the ROM. If `None` we look
+    /// for a patch with the same name as the ROM and an ".ips",
+    /// ".ups" or ".bps" extension.
+    pub patch:          Option<PathBuf>,
+    /// Delay in milliseconds of emulated time after the last write
+    /// to the cartridge RAM before flushing the save file. If `None`
+    /// the save is only written when the cartridge is dropped.
+    pub flush_delay_ms: Option<u32>,
+    /// Keep a copy of the previous save in a ".sav.bak" file every
+    /// time the save is written
+    pub backup:         bool,
+    /// Directory where the save files are stored. If `None` they're
+    /// stored next to the ROM.
+    pub save_dir:       Option<PathBuf>,
+    /// How the save file is named
+    pub save_naming:    SaveNaming,
+    /// Don't load or write any save file, the RAM starts blank. Used
+    /// to play movies which bring their own RAM contents.
+    pub no_save:        bool,
+}
+
+/// Naming scheme for save files
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub enum SaveNaming {
+    /// Same name as the ROM file with a ".sav" extension
+    RomName,
+    /// Named after the title and global checksum in the header, that
+    /// way the same game found at different paths shares the same
+    /// save. Best used with a save directory.
+    GlobalChecksum,
+}
+
+impl LoadOptions {
+    /// Default options
+    pub fn new() -> LoadOptions {
+        LoadOptions {
+            patch:          None,
+            flush_delay_ms: Some(DEFAULT_FLUSH_DELAY_MS),
+            backup:         false,
+            save_dir:       None,
+            save_naming:    SaveNaming::RomName,
+            no_save:        false,
         }
     }
 }
 
+/// Default delay before flushing the save file after a RAM write
+pub const DEFAULT_FLUSH_DELAY_MS: u32 = 3000;
+
+/// Load the ROM image at `path` and apply the patch selected by
+/// `options`, if any. The file itself is never modified. ROMs can be
+/// stored in zip or gzip archives.
+pub fn load_rom(path: &Path,
+                options: &LoadOptions) -> Result<Vec<u8>, CartridgeError> {
+    let mut rom = try!(archive::read_image(path));
+
+    let patch_path = match options.patch {
+        Some(ref p) => Some(p.clone()),
+        None        => find_patch(path),
+    };
+
+    if let Some(patch_path) = patch_path {
+        info!("Applying patch {}", patch_path.display());
+
+        let mut patch = Vec::new();
+
+        try!(try!(File::open(&patch_path)).read_to_end(&mut patch));
+
+        rom = try!(patch::apply(rom, &patch));
+    }
+
+    Ok(rom)
+}
+
+/// Look for a patch next to the ROM at `rom_path`
+fn find_patch(rom_path: &Path) -> Option<PathBuf> {
+    for ext in ["ips", "ups", "bps"].iter() {
+        let mut path = rom_path.to_path_buf();
+
+        path.set_extension(ext);
+
+        if metadata(&path).is_ok() {
+            return Some(path);
+        }
+    }
+
+    None
+}
+
 impl Drop for Cartridge {
     fn drop(&mut self) {
         // Update save file when Cartridge is dropped
         if let Err(e) = self.save_ram() {
             // Display the error but don't panic since we might
             // already be in the middle of a panic unwinding
-            println!("Couldn't save: {}", e);
+            warn!("Couldn't save: {}", e);
         }
     }
 }
@@ -383,6 +1053,8 @@ impl Debug for Cartridge {
                            RAM bank size: {}B)",
                     name, self.model.name, rombanks, rambanks, rambanksize));
 
+        try!(write!(f, "\n{}", self.header()));
+
         Ok(())
     }
 }
@@ -390,13 +1062,415 @@ impl Debug for Cartridge {
 // Each ROM bank is always 16KB
 const ROM_BANK_SIZE: i32 = 16 * 1024;
 
+/// Gameboy sysclk frequency: 4.19Mhz
+const SYSCLK_FREQ: u64 = 0x400000;
+
+/// Nintendo logo present in the header of all licensed cartridges
+static NINTENDO_LOGO: [u8; 48] = [
+    0xce, 0xed, 0x66, 0x66, 0xcc, 0x0d, 0x00, 0x0b,
+    0x03, 0x73, 0x00, 0x83, 0x00, 0x0c, 0x00, 0x0d,
+    0x00, 0x08, 0x11, 0x1f, 0x88, 0x89, 0x00, 0x0e,
+    0xdc, 0xcc, 0x6e, 0xe6, 0xdd, 0xdd, 0xd9, 0x99,
+    0xbb, 0xbb, 0x67, 0x63, 0x6e, 0x0e, 0xec, 0xcc,
+    0xdd, 0xdc, 0x99, 0x9f, 0xbb, 0xb9, 0x33, 0x3e,
+];
+
 mod offsets {
     //! Various offset values to access special memory locations within the ROM
 
+    /// Nintendo logo, checked by the bootrom
+    pub const LOGO:            usize = 0x104;
     /// Title. Upper case ASCII 16bytes long, padded with 0s if shorter
-    pub const TITLE:    usize = 0x134;
+    pub const TITLE:           usize = 0x134;
+    /// Manufacturer code, 4 characters. Newer cartridges only.
+    pub const MANUFACTURER:    usize = 0x13f;
+    /// Game Boy Color support
+    pub const CGB_FLAG:        usize = 0x143;
+    /// New licensee code, 2 characters
+    pub const NEW_LICENSEE:    usize = 0x144;
+    /// Super Game Boy support
+    pub const SGB_FLAG:        usize = 0x146;
     /// Cartridge type
-    pub const TYPE:     usize = 0x147;
-    pub const ROM_SIZE: usize = 0x148;
-    pub const RAM_SIZE: usize = 0x149;
+    pub const TYPE:            usize = 0x147;
+    pub const ROM_SIZE:        usize = 0x148;
+    pub const RAM_SIZE:        usize = 0x149;
+    /// Destination code: 0 for Japan, 1 for the rest of the world
+    pub const DESTINATION:     usize = 0x14a;
+    /// Old licensee code
+    pub const OLD_LICENSEE:    usize = 0x14b;
+    /// Version number of the game
+    pub const VERSION:         usize = 0x14c;
+    /// Checksum of the header bytes at [0x134, 0x14c]
+    pub const HEADER_CHECKSUM: usize = 0x14d;
+    /// Checksum of the whole ROM, big endian
+    pub const GLOBAL_CHECKSUM: usize = 0x14e;
+    /// End of the cartridge header
+    pub const HEADER_END:      usize = 0x150;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{File, remove_file, remove_dir_all, metadata};
+    use std::io::{Read, Write};
+    use std::path::{Path, PathBuf};
+    use std::env::temp_dir;
+
+    use super::{Cartridge, CartridgeError, LoadOptions, SaveNaming};
+    use super::ROM_BANK_SIZE;
+    use savestate::{Writer, Reader, SaveStateError};
+
+    fn temp_path(name: &str) -> PathBuf {
+        temp_dir().join(format!("gb-rs-cartridge-test-{}", name))
+    }
+
+    /// Write an MBC1+RAM+BATTERY ROM with 8KB of RAM at `path`
+    fn write_rom(path: &Path) {
+        let mut rom = vec![0; 0x8000];
+
+        rom[0x147] = 0x03;
+        rom[0x149] = 0x02;
+
+        File::create(path).unwrap().write_all(&rom).unwrap();
+    }
+
+    fn read(path: &Path) -> Vec<u8> {
+        let mut data = Vec::new();
+
+        File::open(path).unwrap().read_to_end(&mut data).unwrap();
+
+        data
+    }
+
+    #[test]
+    fn save_flush() {
+        let path = temp_path("flush.gb");
+        let save = path.with_extension("sav");
+        let bak  = path.with_extension("sav.bak");
+
+        write_rom(&path);
+        let _ = remove_file(&save);
+        let _ = remove_file(&bak);
+
+        let mut options = LoadOptions::new();
+
+        options.flush_delay_ms = Some(1);
+        options.backup         = true;
+
+        // 1ms of emulated time
+        let delay = (super::SYSCLK_FREQ / 1000) as usize;
+
+        {
+            let mut cart =
+                Cartridge::from_path_with_options(&path, &options).unwrap();
+
+            // The save is created right away
+            assert!(read(&save) == vec![0; 0x2000]);
+
+            cart.set_rom_byte(0x0000, 0x0a);
+            cart.set_ram_byte(0x0000, 0x12);
+
+            // Nothing happens until the RAM has been left alone for
+            // the whole delay
+            for _ in 1..delay {
+                cart.step();
+            }
+
+            assert!(read(&save)[0] == 0x00);
+
+            cart.step();
+
+            assert!(read(&save)[0] == 0x12);
+            // The previous save was kept and the temporary file
+            // renamed over the save
+            assert!(read(&bak)[0] == 0x00);
+            assert!(metadata(save.with_extension("sav.tmp")).is_err());
+
+            // Not dirty anymore: no new flush
+            for _ in 0..delay * 2 {
+                cart.step();
+            }
+
+            assert!(read(&bak)[0] == 0x00);
+
+            cart.set_ram_byte(0x0000, 0x34);
+        }
+
+        // Saved on drop, the backup rotates
+        assert!(read(&save)[0] == 0x34);
+        assert!(read(&bak)[0] == 0x12);
+
+        // And loaded back
+        {
+            let cart = Cartridge::from_path_with_options(&path, &options).unwrap();
+
+            assert!(cart.ram_byte(0x0000) == 0x34);
+        }
+
+        remove_file(&path).unwrap();
+        remove_file(&save).unwrap();
+        remove_file(&bak).unwrap();
+    }
+
+    #[test]
+    fn save_naming() {
+        let path = temp_path("naming.gb");
+        let dir  = temp_path("saves");
+
+        write_rom(&path);
+        let _ = remove_dir_all(&dir);
+
+        let mut options = LoadOptions::new();
+
+        options.save_dir = Some(dir.clone());
+
+        // The save directory is created if needed and the save is
+        // named after the ROM
+        {
+            let cart = Cartridge::from_path_with_options(&path, &options);
+
+            assert!(cart.unwrap().save_path ==
+                    Some(dir.join("gb-rs-cartridge-test-naming.sav")));
+        }
+
+        assert!(read(&dir.join("gb-rs-cartridge-test-naming.sav")).len() ==
+                0x2000);
+
+        // Title and global checksum from the header
+        let mut rom = read(&path);
+
+        rom[0x134..0x13e].copy_from_slice(b"GB-RS TEST");
+        rom[0x14e] = 0x12;
+        rom[0x14f] = 0x34;
+
+        File::create(&path).unwrap().write_all(&rom).unwrap();
+
+        options.save_naming = SaveNaming::GlobalChecksum;
+
+        {
+            let cart = Cartridge::from_path_with_options(&path, &options);
+
+            assert!(cart.unwrap().save_path ==
+                    Some(dir.join("GB_RS_TEST-1234.sav")));
+        }
+
+        // Without a save directory the save goes next to the ROM
+        options.save_dir = None;
+
+        {
+            let cart = Cartridge::from_path_with_options(&path, &options);
+
+            assert!(cart.unwrap().save_path ==
+                    Some(path.with_file_name("GB_RS_TEST-1234.sav")));
+        }
+
+        remove_file(&path).unwrap();
+        remove_file(path.with_file_name("GB_RS_TEST-1234.sav")).unwrap();
+        remove_dir_all(&dir).unwrap();
+    }
+
+    /// Build an RTC save file footer holding the `live` and `latched`
+    /// registers followed by a null timestamp of `timestamp_size`
+    /// bytes
+    fn rtc_footer(live: [u8; 5],
+                  latched: [u8; 5],
+                  timestamp_size: usize) -> Vec<u8> {
+        let mut footer = Vec::new();
+
+        for &r in live.iter().chain(latched.iter()) {
+            footer.extend_from_slice(&[r, 0, 0, 0]);
+        }
+
+        footer.extend(vec![0; timestamp_size]);
+
+        footer
+    }
+
+    /// Select the RTC register `reg` and read it through the MBC3
+    fn read_rtc(cart: &mut Cartridge, reg: u8) -> u8 {
+        cart.set_rom_byte(0x4000, reg);
+
+        cart.ram_byte(0x0000)
+    }
+
+    /// Write `val` to the RTC register `reg` through the MBC3
+    fn write_rtc(cart: &mut Cartridge, reg: u8, val: u8) {
+        cart.set_rom_byte(0x4000, reg);
+        cart.set_ram_byte(0x0000, val);
+    }
+
+    /// Load an MBC3+TIMER+RAM+BATTERY cartridge whose save is an 8KB
+    /// RAM image followed by `footer` and check that the RAM and the
+    /// clock registers are restored, then that new values survive a
+    /// save and reload.
+    fn rtc_save(name: &str,
+                footer: &[u8],
+                live: [u8; 5],
+                latched: [u8; 5]) {
+        let path = temp_path(name);
+        let save = path.with_extension("sav");
+
+        let mut rom = vec![0; 0x8000];
+
+        rom[0x147] = 0x10;
+        rom[0x149] = 0x02;
+
+        File::create(&path).unwrap().write_all(&rom).unwrap();
+
+        let mut ram = vec![0; 0x2000];
+
+        ram[0] = 0x42;
+        ram.extend_from_slice(footer);
+
+        File::create(&save).unwrap().write_all(&ram).unwrap();
+
+        let mut options = LoadOptions::new();
+
+        // Only save when the cartridge is dropped
+        options.flush_delay_ms = None;
+
+        {
+            let mut cart =
+                Cartridge::from_path_with_options(&path, &options).unwrap();
+
+            cart.set_rom_byte(0x0000, 0x0a);
+
+            assert!(cart.ram_byte(0x0000) == 0x42);
+
+            for (i, reg) in (0x08..0x0d).enumerate() {
+                assert!(read_rtc(&mut cart, reg) == latched[i]);
+            }
+
+            cart.set_rom_byte(0x6000, 0x00);
+            cart.set_rom_byte(0x6000, 0x01);
+
+            for (i, reg) in (0x08..0x0d).enumerate() {
+                assert!(read_rtc(&mut cart, reg) == live[i]);
+            }
+
+            // Halt the clock so that it doesn't catch up with the
+            // wall clock when it's loaded back
+            write_rtc(&mut cart, 0x0c, 0x40);
+            write_rtc(&mut cart, 0x08, 56);
+            write_rtc(&mut cart, 0x00, 0x24);
+        }
+
+        // Always saved with the 48-byte footer
+        assert!(read(&save).len() == 0x2000 + 48);
+
+        {
+            let mut cart =
+                Cartridge::from_path_with_options(&path, &options).unwrap();
+
+            cart.set_rom_byte(0x0000, 0x0a);
+            cart.set_rom_byte(0x6000, 0x00);
+            cart.set_rom_byte(0x6000, 0x01);
+
+            assert!(read_rtc(&mut cart, 0x08) == 56);
+            assert!(read_rtc(&mut cart, 0x09) == live[1]);
+            assert!(read_rtc(&mut cart, 0x0c) == 0x40);
+            assert!(read_rtc(&mut cart, 0x00) == 0x24);
+        }
+
+        remove_file(&path).unwrap();
+        remove_file(&save).unwrap();
+    }
+
+    #[test]
+    fn rtc_save_footer() {
+        // Halted clock so that loading doesn't catch up
+        let live    = [12, 34, 5, 0x42, 0x41];
+        let latched = [1, 2, 3, 4, 0x01];
+
+        rtc_save("rtc-footer.gb", &rtc_footer(live, latched, 8),
+                 live, latched);
+    }
+
+    #[test]
+    fn rtc_save_old_footer() {
+        let live    = [12, 34, 5, 0x42, 0x41];
+        let latched = [1, 2, 3, 4, 0x01];
+
+        rtc_save("rtc-old-footer.gb", &rtc_footer(live, latched, 4),
+                 live, latched);
+    }
+
+    #[test]
+    fn rtc_save_no_footer() {
+        // Saved by an emulator without RTC support, the clock starts
+        // from scratch
+        rtc_save("rtc-no-footer.gb", &[], [0; 5], [0; 5]);
+    }
+
+    /// Save `cart`'s state with `reg` as the selected RTC register
+    /// and load it back
+    fn load_rtc_register(cart: &mut Cartridge,
+                         reg: Option<u8>) -> Result<(), SaveStateError> {
+        let mut w = Writer::new();
+
+        cart.set_rtc_register(reg);
+        cart.save_state(&mut w);
+
+        let state = w.into_bytes();
+
+        cart.load_state(&mut Reader::new(&state))
+    }
+
+    #[test]
+    fn rtc_register_state() {
+        // MBC3+TIMER+RAM+BATTERY
+        let mut rom = vec![0; 0x8000];
+
+        rom[0x147] = 0x10;
+        rom[0x149] = 0x02;
+
+        let mut cart = Cartridge::from_vec(rom).unwrap();
+
+        assert!(load_rtc_register(&mut cart, None).is_ok());
+        assert!(load_rtc_register(&mut cart, Some(0x08)).is_ok());
+        assert!(load_rtc_register(&mut cart, Some(0x0c)).is_ok());
+
+        match load_rtc_register(&mut cart, Some(0x0d)) {
+            Err(SaveStateError::Invalid(_)) => (),
+            _ => panic!("bad RTC register accepted"),
+        }
+
+        // No RTC at all on MBC1
+        let mut rom = vec![0; 0x8000];
+
+        rom[0x147] = 0x01;
+
+        let mut cart = Cartridge::from_vec(rom).unwrap();
+
+        match load_rtc_register(&mut cart, Some(0x08)) {
+            Err(SaveStateError::Invalid(_)) => (),
+            _ => panic!("RTC register accepted without an RTC"),
+        }
+    }
+
+    #[test]
+    fn rom_size() {
+        // 3 banks, each filled with its own number
+        let rom = (0..3 * ROM_BANK_SIZE)
+            .map(|i| (i / ROM_BANK_SIZE) as u8)
+            .collect();
+
+        let mut cart = Cartridge::from_vec(rom).unwrap();
+
+        assert!(cart.rom_banks() == 3);
+
+        cart.set_rom_offset(ROM_BANK_SIZE);
+        assert!(cart.rom_byte(0x4000) == 2);
+
+        // Bank 4 mirrors bank 1
+        cart.set_rom_offset(3 * ROM_BANK_SIZE);
+        assert!(cart.rom_byte(0x4000) == 1);
+
+        cart.set_rom0_offset(3 * ROM_BANK_SIZE);
+        assert!(cart.rom_byte(0x0000) == 0);
+
+        match Cartridge::from_vec(vec![0; 0x4100]) {
+            Err(CartridgeError::Truncated(0x4100)) => (),
+            _ => panic!("truncated ROM accepted"),
+        }
+    }
 }
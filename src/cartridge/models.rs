@@ -39,7 +39,7 @@ fn read_ram(cart: &Cartridge, addr: u32) -> u8 {
 }
 
 /// Default implementation of bank reconfiguration
-fn set_rom_bank(cart: &mut Cartridge, bank: u8) {
+fn set_rom_bank(cart: &mut Cartridge, bank: u16) {
     cart.set_rom_bank(bank);
 
     let rom_offset = ROM_BANK_SIZE *
@@ -73,6 +73,13 @@ mod mbc0 {
 }
 
 mod mbc1 {
+    //! The MBC1 has two bank registers: a 5bit register selecting
+    //! the low bits of the ROM bank and a 2bit register which
+    //! depending on the banking mode either selects the RAM bank or
+    //! the upper bits of the ROM bank. Both are stored in the
+    //! cartridge's `rom_bank` (bits [4:0] and [6:5] respectively) and
+    //! the mode is the `bank_ram` flag.
+
     use super::Model;
     use cartridge::{Cartridge, ROM_BANK_SIZE};
 
@@ -87,61 +94,72 @@ mod mbc1 {
                 // Select a new ROM bank, bits [4:0]
                 let cur_bank = cart.rom_bank() & !0x1f;
 
-                let bank = cur_bank | (val & 0x1f);
+                let bank = cur_bank | (val & 0x1f) as u16;
 
-                set_rom_bank(cart, bank);
+                cart.set_rom_bank(bank);
+                remap(cart);
             }
-            0x4000...0x5fff =>
-                if cart.bank_ram() {
-                    // Select a new RAM bank
-                    cart.set_ram_bank(val & 0x3);
-                } else {
-                    // Select a new ROM bank, bits [6:5]
-                    let cur_bank = cart.rom_bank() & !0x60;
+            0x4000...0x5fff => {
+                // Select the RAM bank or ROM bank bits [6:5]
+                let cur_bank = cart.rom_bank() & !0x60;
 
-                    let bank = cur_bank | ((val << 5) & 0x60);
+                let bank = cur_bank | (((val as u16) << 5) & 0x60);
 
-                    set_rom_bank(cart, bank);
-                },
-            0x6000...0x7fff =>
+                cart.set_rom_bank(bank);
+                remap(cart);
+            }
+            0x6000...0x7fff => {
                 // Switch RAM/ROM banking mode
-                cart.set_bank_ram(val & 1 != 0),
+                cart.set_bank_ram(val & 1 != 0);
+                remap(cart);
+            }
             _ => debug!("Unhandled ROM write: {:04x} {:02x}", offset, val),
         }
     }
 
-    /// MBC1 implementation of bank reconfiguration. I couldn't find
-    /// that documented anywhere but The Legend of Zelda crashes at
-    /// certain points if this is not accurate. I took the algorithm
-    /// from gambatte.
-    fn set_rom_bank(cart: &mut Cartridge, bank: u8) {
-        cart.set_rom_bank(bank);
-
-        // I don't really understand this part, I know that bank can't
-        // be 0 (since bank 0 is always mapped at the begining of the
-        // address space) but this would also rewrite bank 32 to 33
-        // for instance. Maybe a quirck of MBC1?
-        let bank =
-            if bank & 0x1f != 0 {
-                bank
-            } else {
-                bank | 1
+    /// Recompute the ROM and RAM mappings from the bank registers
+    /// and banking mode.
+    fn remap(cart: &mut Cartridge) {
+        let reg = cart.rom_bank();
+
+        let low  = reg & 0x1f;
+        let high = reg >> 5;
+
+        // The low register can't be 0: the MBC only checks those 5
+        // bits so writing 0x00 selects bank 0x01 but it also means
+        // that bank 0x20 maps 0x21 etc...
+        let low = if low == 0 { 1 } else { low };
+
+        // Multicarts only connect 4 bits of the low register, the
+        // high register selects one of the 4 games of the collection.
+        let (low, high) =
+            match cart.multicart() {
+                true  => (low & 0xf, high << 4),
+                false => (low,       high << 5),
             };
 
-        // If the bank overflows we wrap it around. This assumes that
-        // MBC1 cart can only have a power of two number of banks.
-        let bank = bank & (cart.rom_banks() - 1);
+        // If the bank overflows we wrap it around. Images which aren't
+        // a power of two banks long are further mirrored by
+        // `set_rom_offset`.
+        let mask = ((cart.rom_banks() as u32).next_power_of_two() - 1) as u16;
+
+        let bank = (high | low) & mask;
 
         // Same as super::set_rom_bank: we already have a one bank
         // offset in the CPU address when accessing bankable ROM.
-        let bank = (bank as i32) - 1;
-
-        let rom_offset = ROM_BANK_SIZE * bank;
-
-        cart.set_rom_offset(rom_offset);
+        cart.set_rom_offset(ROM_BANK_SIZE * (bank as i32 - 1));
+
+        if cart.bank_ram() {
+            // In RAM banking mode the high register also selects the
+            // bank mapped at [0x0000, 0x3fff] and the RAM bank
+            cart.set_rom0_offset(ROM_BANK_SIZE * (high & mask) as i32);
+            cart.set_ram_bank((reg >> 5) as u8);
+        } else {
+            cart.set_rom0_offset(0);
+            cart.set_ram_bank(0);
+        }
     }
 
-
     pub static MODEL: Model =
         Model { name:      "MBC1",
                 write_rom: write_rom,
@@ -151,28 +169,45 @@ mod mbc1 {
 }
 
 mod mbc2 {
+    //! The MBC2 contains 512 half-bytes of RAM directly in the
+    //! controller. Only the low 4 bits of each RAM byte are
+    //! connected, the upper nibble reads as 1s.
+
     use super::Model;
     use cartridge::Cartridge;
 
     fn write_rom(cart: &mut Cartridge, offset: u16, val: u8) {
         match offset {
-            0x0000...0x1fff =>
-                // Writing a low nibble 0xa to anywhere in that
-                // address range removes RAM write protect, All other
-                // values enable it.
-                cart.set_ram_wp(val & 0xf != 0xa),
-            0x2000...0x3fff => {
-                super::set_rom_bank(cart, val & 0xf);
-            }
+            // Bit 8 of the address selects between the RAM enable and
+            // ROM bank registers
+            0x0000...0x3fff =>
+                if offset & 0x100 == 0 {
+                    // Writing a low nibble 0xa removes RAM write
+                    // protect, All other values enable it.
+                    cart.set_ram_wp(val & 0xf != 0xa);
+                } else {
+                    super::set_rom_bank(cart, (val & 0xf) as u16);
+                },
             _ => debug!("Unhandled ROM write: {:04x} {:02x}", offset, val),
         }
     }
 
+    fn write_ram(cart: &mut Cartridge, addr: u32, val: u8) {
+        // The 512 bytes of RAM are mirrored across the whole RAM
+        // range. We store the unconnected bits as 1s like other
+        // emulators do in their save files.
+        super::write_ram(cart, addr & 0x1ff, val | 0xf0)
+    }
+
+    fn read_ram(cart: &Cartridge, addr: u32) -> u8 {
+        super::read_ram(cart, addr & 0x1ff) | 0xf0
+    }
+
     pub static MODEL: Model =
         Model { name:      "MBC2",
                 write_rom: write_rom,
-                write_ram: super::write_ram,
-                read_ram:  super::read_ram,
+                write_ram: write_ram,
+                read_ram:  read_ram,
         };
 }
 
@@ -185,34 +220,714 @@ mod mbc3 {
             0x0000...0x1fff =>
                 // Writing a low nibble 0xa to anywhere in that
                 // address range removes RAM write protect, All other
-                // values enable it.
+                // values enable it. This also controls the access to
+                // the RTC registers.
                 cart.set_ram_wp(val & 0xf != 0xa),
             0x2000...0x3fff =>
                 // Select a new ROM bank
-                super::set_rom_bank(cart, val & 0x7f),
+                super::set_rom_bank(cart, (val & 0x7f) as u16),
             0x4000...0x5fff =>
-                // Select a new RAM bank
-                cart.set_ram_bank(val),
-            0x6000...0x7fff => debug!("Unhandled RTC access"),
+                match val {
+                    0x00...0x07 => {
+                        // Select a new RAM bank
+                        cart.set_rtc_register(None);
+                        cart.set_ram_bank(val);
+                    }
+                    0x08...0x0c if cart.rtc().is_some() =>
+                        // Map an RTC register in the RAM range
+                        cart.set_rtc_register(Some(val)),
+                    _ => debug!("Unhandled RAM bank select: {:02x}", val),
+                },
+            0x6000...0x7fff =>
+                if let Some(rtc) = cart.rtc_mut() {
+                    rtc.latch(val);
+                },
             _ => debug!("Unhandled ROM write: {:04x} {:02x}", offset, val),
         }
     }
 
+    fn write_ram(cart: &mut Cartridge, addr: u32, val: u8) {
+        match cart.rtc_register() {
+            Some(reg) =>
+                if let Some(rtc) = cart.rtc_mut() {
+                    rtc.set_register(reg, val);
+                },
+            None => super::write_ram(cart, addr, val),
+        }
+    }
+
+    fn read_ram(cart: &Cartridge, addr: u32) -> u8 {
+        match (cart.rtc_register(), cart.rtc()) {
+            (Some(reg), Some(rtc)) => rtc.register(reg),
+            _                      => super::read_ram(cart, addr),
+        }
+    }
+
     pub static MODEL: Model =
         Model { name:     "MBC3",
+                write_rom: write_rom,
+                write_ram: write_ram,
+                read_ram:  read_ram,
+        };
+}
+
+mod mbc5 {
+    use super::Model;
+    use cartridge::{Cartridge, ROM_BANK_SIZE};
+
+    fn write_rom(cart: &mut Cartridge, offset: u16, val: u8) {
+        match offset {
+            0x0000...0x1fff =>
+                // Writing a low nibble 0xa to anywhere in that
+                // address range removes RAM write protect, All other
+                // values enable it.
+                cart.set_ram_wp(val & 0xf != 0xa),
+            0x2000...0x2fff => {
+                // Select a new ROM bank, bits [7:0]
+                let bank = (cart.rom_bank() & 0x100) | val as u16;
+
+                set_rom_bank(cart, bank);
+            }
+            0x3000...0x3fff => {
+                // Select a new ROM bank, bit 8
+                let bank = (cart.rom_bank() & 0xff) | ((val as u16 & 1) << 8);
+
+                set_rom_bank(cart, bank);
+            }
+            0x4000...0x5fff =>
+                // Select a new RAM bank
+                cart.set_ram_bank(val & 0xf),
+            _ => debug!("Unhandled ROM write: {:04x} {:02x}", offset, val),
+        }
+    }
+
+    /// Same as `write_rom` but bit 3 of the RAM bank register
+    /// controls the rumble motor instead of selecting a RAM bank.
+    fn write_rom_rumble(cart: &mut Cartridge, offset: u16, val: u8) {
+        match offset {
+            0x4000...0x5fff => {
+                cart.set_rumble(val & 0x8 != 0);
+                cart.set_ram_bank(val & 0x7);
+            }
+            _ => write_rom(cart, offset, val),
+        }
+    }
+
+    /// Unlike other MBCs the MBC5 can map bank 0 in the bankable
+    /// range.
+    fn set_rom_bank(cart: &mut Cartridge, bank: u16) {
+        cart.set_rom_bank(bank);
+
+        // Wrap around if the bank number is greater than the number
+        // of banks in the cart
+        let bank = bank % cart.rom_banks();
+
+        // Same as super::set_rom_bank: we already have a one bank
+        // offset in the CPU address when accessing bankable ROM.
+        let bank = (bank as i32) - 1;
+
+        cart.set_rom_offset(ROM_BANK_SIZE * bank);
+    }
+
+    pub static MODEL: Model =
+        Model { name:      "MBC5",
                 write_rom: write_rom,
                 write_ram: super::write_ram,
                 read_ram:  super::read_ram,
         };
+
+    pub static MODEL_RUMBLE: Model =
+        Model { name:      "MBC5+RUMBLE",
+                write_rom: write_rom_rumble,
+                write_ram: super::write_ram,
+                read_ram:  super::read_ram,
+        };
 }
 
-/// Return a cartridge instance for a given cartridge type
-pub fn from_id(id: u8) -> Model {
-    match id {
+mod mbc7 {
+    //! MBC7: accelerometer and EEPROM, see `cartridge::mbc7`
+
+    use super::Model;
+    use cartridge::Cartridge;
+
+    fn write_rom(cart: &mut Cartridge, offset: u16, val: u8) {
+        match offset {
+            0x0000...0x1fff => {
+                if let Some((m, _)) = cart.mbc7_mut() {
+                    m.set_enable1(val == 0x0a);
+                }
+                // Access is controlled by the MBC7 itself
+                cart.set_ram_wp(false);
+            }
+            0x2000...0x3fff =>
+                // Select a new ROM bank
+                super::set_rom_bank(cart, (val & 0x7f) as u16),
+            0x4000...0x5fff =>
+                if let Some((m, _)) = cart.mbc7_mut() {
+                    m.set_enable2(val == 0x40);
+                },
+            _ => debug!("Unhandled ROM write: {:04x} {:02x}", offset, val),
+        }
+    }
+
+    fn write_ram(cart: &mut Cartridge, addr: u32, val: u8) {
+        if let Some((m, eeprom)) = cart.mbc7_mut() {
+            m.write(addr, val, eeprom);
+        }
+    }
+
+    fn read_ram(cart: &Cartridge, addr: u32) -> u8 {
+        cart.mbc7().map(|m| m.read(addr)).unwrap_or(0xff)
+    }
+
+    pub static MODEL: Model =
+        Model { name:      "MBC7",
+                write_rom: write_rom,
+                write_ram: write_ram,
+                read_ram:  read_ram,
+        };
+}
+
+mod camera {
+    //! Game Boy Camera, see `cartridge::camera`
+
+    use super::Model;
+    use cartridge::Cartridge;
+
+    fn write_rom(cart: &mut Cartridge, offset: u16, val: u8) {
+        match offset {
+            0x0000...0x1fff => {
+                if let Some(c) = cart.camera_mut() {
+                    c.set_ram_enable(val & 0xf == 0xa);
+                }
+                // The camera registers can be written even when the
+                // RAM is protected, `write_ram` deals with it.
+                cart.set_ram_wp(false);
+            }
+            0x2000...0x3fff =>
+                // Select a new ROM bank
+                super::set_rom_bank(cart, (val & 0x3f) as u16),
+            0x4000...0x5fff => {
+                // Bit 4 maps the camera registers, otherwise select a
+                // new RAM bank
+                let mapped = val & 0x10 != 0;
+
+                if let Some(c) = cart.camera_mut() {
+                    c.set_mapped(mapped);
+                }
+
+                if !mapped {
+                    cart.set_ram_bank(val & 0xf);
+                }
+            }
+            _ => debug!("Unhandled ROM write: {:04x} {:02x}", offset, val),
+        }
+    }
+
+    fn write_ram(cart: &mut Cartridge, addr: u32, val: u8) {
+        let (mapped, ram_enable) = match cart.camera() {
+            Some(c) => (c.mapped(), c.ram_enable()),
+            None    => (false, true),
+        };
+
+        if mapped {
+            if let Some(c) = cart.camera_mut() {
+                c.set_register(addr, val);
+            }
+        } else if ram_enable {
+            super::write_ram(cart, addr, val);
+        }
+    }
+
+    fn read_ram(cart: &Cartridge, addr: u32) -> u8 {
+        match cart.camera() {
+            Some(c) if c.mapped() => c.register(addr),
+            _                     => super::read_ram(cart, addr),
+        }
+    }
+
+    pub static MODEL: Model =
+        Model { name:      "POCKET CAMERA",
+                write_rom: write_rom,
+                write_ram: write_ram,
+                read_ram:  read_ram,
+        };
+}
+
+mod huc1 {
+    //! Hudson HuC1. Banking works like a simplified MBC1 but the RAM
+    //! enable register can also map an infrared port in the RAM
+    //! range.
+
+    use super::Model;
+    use cartridge::Cartridge;
+
+    fn write_rom(cart: &mut Cartridge, offset: u16, val: u8) {
+        match offset {
+            0x0000...0x1fff => {
+                // 0xe maps the IR port, anything else maps the
+                // RAM. Unlike the MBCs the RAM is never write
+                // protected.
+                cart.set_ir_mode(val & 0xf == 0xe);
+                cart.set_ram_wp(false);
+            }
+            0x2000...0x3fff =>
+                // Select a new ROM bank
+                super::set_rom_bank(cart, (val & 0x3f) as u16),
+            0x4000...0x5fff =>
+                // Select a new RAM bank
+                cart.set_ram_bank(val & 0x3),
+            _ => debug!("Unhandled ROM write: {:04x} {:02x}", offset, val),
+        }
+    }
+
+    fn write_ram(cart: &mut Cartridge, addr: u32, val: u8) {
+        if cart.ir_mode() {
+            // Bit 0 drives the IR LED, we don't emulate the link
+            debug!("HuC1 IR LED {}", val & 1);
+        } else {
+            super::write_ram(cart, addr, val);
+        }
+    }
+
+    fn read_ram(cart: &Cartridge, addr: u32) -> u8 {
+        if cart.ir_mode() {
+            // Bit 0 is set when light is received. Nobody's ever
+            // talking to us.
+            0xc0
+        } else {
+            super::read_ram(cart, addr)
+        }
+    }
+
+    pub static MODEL: Model =
+        Model { name:      "HuC1",
+                write_rom: write_rom,
+                write_ram: write_ram,
+                read_ram:  read_ram,
+        };
+}
+
+mod huc3 {
+    //! Hudson HuC3. The RAM enable register is replaced by a mode
+    //! register selecting what's mapped in the RAM range, see
+    //! `cartridge::huc3` for the clock itself.
+
+    use super::Model;
+    use cartridge::Cartridge;
+
+    /// RAM mapped, read only
+    const MODE_RAM_RO:   u8 = 0x0;
+    /// RAM mapped, read/write
+    const MODE_RAM:      u8 = 0xa;
+    /// Clock command register mapped
+    const MODE_COMMAND:  u8 = 0xb;
+    /// Clock response register mapped
+    const MODE_RESPONSE: u8 = 0xc;
+    /// Semaphore mapped
+    const MODE_READY:    u8 = 0xd;
+    /// IR port mapped
+    const MODE_IR:       u8 = 0xe;
+
+    fn write_rom(cart: &mut Cartridge, offset: u16, val: u8) {
+        match offset {
+            0x0000...0x1fff => {
+                let mode = val & 0xf;
+
+                if let Some(huc3) = cart.huc3_mut() {
+                    huc3.set_mode(mode);
+                }
+
+                // Let writes through for the modes which accept
+                // them, `write_ram` dispatches them
+                cart.set_ram_wp(match mode {
+                    MODE_RAM | MODE_COMMAND | MODE_READY | MODE_IR => false,
+                    _ => true,
+                });
+            }
+            0x2000...0x3fff =>
+                // Select a new ROM bank
+                super::set_rom_bank(cart, (val & 0x7f) as u16),
+            0x4000...0x5fff =>
+                // Select a new RAM bank
+                cart.set_ram_bank(val & 0xf),
+            _ => debug!("Unhandled ROM write: {:04x} {:02x}", offset, val),
+        }
+    }
+
+    fn mode(cart: &Cartridge) -> u8 {
+        cart.huc3().map(|h| h.mode()).unwrap_or(MODE_RAM_RO)
+    }
+
+    fn write_ram(cart: &mut Cartridge, addr: u32, val: u8) {
+        match mode(cart) {
+            MODE_RAM     => super::write_ram(cart, addr, val),
+            MODE_COMMAND =>
+                if let Some(huc3) = cart.huc3_mut() {
+                    huc3.command(val);
+                },
+            // Writing to the semaphore requests the execution of the
+            // command, which we already did.
+            MODE_READY   => (),
+            MODE_IR      => debug!("HuC3 IR LED {}", val & 1),
+            m            => debug!("HuC3 RAM write in mode {:x}", m),
+        }
+    }
+
+    fn read_ram(cart: &Cartridge, addr: u32) -> u8 {
+        match mode(cart) {
+            MODE_RESPONSE =>
+                cart.huc3().map(|h| h.response()).unwrap_or(0xff),
+            // Commands complete instantly
+            MODE_READY    => 0x01,
+            // No IR light received
+            MODE_IR       => 0xc0,
+            _             => super::read_ram(cart, addr),
+        }
+    }
+
+    pub static MODEL: Model =
+        Model { name:      "HuC3",
+                write_rom: write_rom,
+                write_ram: write_ram,
+                read_ram:  read_ram,
+        };
+}
+
+/// Return a cartridge instance for a given cartridge type or `None`
+/// if the type is not supported
+pub fn from_id(id: u8) -> Option<Model> {
+    let model = match id {
         0           => mbc0::MODEL,
         0x01...0x03 => mbc1::MODEL,
         0x05...0x06 => mbc2::MODEL,
         0x0f...0x13 => mbc3::MODEL,
-        _           => panic!("Unknown cartridge model 0x{:02x}", id),
+        0x19...0x1b => mbc5::MODEL,
+        0x1c...0x1e => mbc5::MODEL_RUMBLE,
+        0x22        => mbc7::MODEL,
+        0xfc        => camera::MODEL,
+        0xfe        => huc3::MODEL,
+        0xff        => huc1::MODEL,
+        _           => return None,
+    };
+
+    Some(model)
+}
+
+/// Return `true` if the cartridge type `id` contains a real time
+/// clock
+pub fn has_rtc(id: u8) -> bool {
+    match id {
+        // MBC3+TIMER+BATTERY and MBC3+TIMER+RAM+BATTERY
+        0x0f | 0x10 => true,
+        _           => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use GameBoy;
+    use cartridge::{Cartridge, ROM_BANK_SIZE, NINTENDO_LOGO};
+
+    /// Build a `banks` * 16KB ROM of type `id` where each bank starts
+    /// with its number (little endian)
+    fn rom(id: u8, banks: usize, ram_size: u8) -> Vec<u8> {
+        let bank_size = ROM_BANK_SIZE as usize;
+
+        let mut rom = vec![0; banks * bank_size];
+
+        for bank in 0..banks {
+            rom[bank * bank_size]     = bank as u8;
+            rom[bank * bank_size + 1] = (bank >> 8) as u8;
+        }
+
+        rom[0x147] = id;
+        // ROM size code: 32KB << n
+        rom[0x148] = (banks / 2).trailing_zeros() as u8;
+        rom[0x149] = ram_size;
+
+        rom
+    }
+
+    /// Return the number of the bank mapped at `addr`
+    fn bank_at(cart: &Cartridge, addr: u16) -> u16 {
+        cart.rom_byte(addr) as u16 | ((cart.rom_byte(addr + 1) as u16) << 8)
+    }
+
+    #[test]
+    fn mbc1_banking_mode() {
+        // 1MB, no second header so it's not a multicart
+        let mut cart = Cartridge::from_vec(rom(0x01, 64, 0)).unwrap();
+
+        assert!(!cart.multicart());
+
+        cart.set_rom_byte(0x2000, 0x05);
+        cart.set_rom_byte(0x4000, 0x01);
+        assert!(bank_at(&cart, 0x4000) == 0x25);
+        assert!(bank_at(&cart, 0x0000) == 0);
+
+        // Writing 0 to the low register selects 1 but the high bits
+        // still apply
+        cart.set_rom_byte(0x2000, 0x00);
+        assert!(bank_at(&cart, 0x4000) == 0x21);
+
+        // In mode 1 the high register also remaps the bank 0 window
+        cart.set_rom_byte(0x6000, 0x01);
+        assert!(bank_at(&cart, 0x0000) == 0x20);
+        assert!(bank_at(&cart, 0x4000) == 0x21);
+
+        cart.set_rom_byte(0x4000, 0x02);
+        assert!(bank_at(&cart, 0x0000) == 0x40 % 64);
+
+        cart.set_rom_byte(0x6000, 0x00);
+        assert!(bank_at(&cart, 0x0000) == 0);
+    }
+
+    #[test]
+    fn mbc1_multicart() {
+        let mut rom = rom(0x01, 64, 0);
+
+        // Header of the second game in bank 0x10
+        for (i, &b) in NINTENDO_LOGO.iter().enumerate() {
+            rom[0x40104 + i] = b;
+        }
+
+        let mut cart = Cartridge::from_vec(rom).unwrap();
+
+        assert!(cart.multicart());
+
+        // Only 4 bits of the low register are used, the high
+        // register selects the game
+        cart.set_rom_byte(0x2000, 0x12);
+        cart.set_rom_byte(0x4000, 0x01);
+        assert!(bank_at(&cart, 0x4000) == 0x12);
+        assert!(bank_at(&cart, 0x0000) == 0);
+
+        cart.set_rom_byte(0x4000, 0x03);
+        assert!(bank_at(&cart, 0x4000) == 0x32);
+
+        // Mode 1 maps the first bank of the selected game at 0x0000
+        cart.set_rom_byte(0x6000, 0x01);
+        assert!(bank_at(&cart, 0x0000) == 0x30);
+
+        // The 0 -> 1 fixup looks at all 5 bits so this maps the
+        // first bank of the game in the bankable window as well
+        cart.set_rom_byte(0x2000, 0x10);
+        assert!(bank_at(&cart, 0x4000) == 0x30);
+    }
+
+
+    #[test]
+    fn mbc2_registers() {
+        let mut cart = Cartridge::from_vec(rom(0x06, 16, 0)).unwrap();
+
+        // Address bit 8 clear: RAM enable
+        cart.set_rom_byte(0x0000, 0x0a);
+        cart.set_ram_byte(0x0000, 0x05);
+        assert!(cart.ram_byte(0x0000) == 0xf5);
+
+        // Bit 8 set: ROM bank, doesn't touch the RAM enable
+        cart.set_rom_byte(0x0100, 0x03);
+        assert!(bank_at(&cart, 0x4000) == 3);
+
+        cart.set_rom_byte(0x2100, 0x0a);
+        assert!(bank_at(&cart, 0x4000) == 10);
+
+        cart.set_ram_byte(0x0000, 0x06);
+        assert!(cart.ram_byte(0x0000) == 0xf6);
+
+        // Bit 8 clear anywhere in the range disables the RAM
+        cart.set_rom_byte(0x2000, 0x00);
+        assert!(bank_at(&cart, 0x4000) == 10);
+
+        cart.set_ram_byte(0x0000, 0x07);
+        assert!(cart.ram_byte(0x0000) == 0xf6);
+
+        // Only 4 bits of bank number, bank 0 maps bank 1
+        cart.set_rom_byte(0x0100, 0xf0);
+        assert!(bank_at(&cart, 0x4000) == 1);
+    }
+
+    #[test]
+    fn mbc2_ram() {
+        let mut cart = Cartridge::from_vec(rom(0x06, 16, 0)).unwrap();
+
+        cart.set_rom_byte(0x0000, 0x0a);
+
+        for i in 0..0x200 {
+            cart.set_ram_byte(i, i as u8);
+        }
+
+        // The upper nibble isn't connected and reads as 1s
+        assert!(cart.ram_byte(0x0000) == 0xf0);
+        assert!(cart.ram_byte(0x01ff) == 0xff);
+        assert!(cart.ram_byte(0x0123) == 0xf3);
+
+        // The 512 half-bytes are mirrored across 0xa000-0xbfff
+        for &offset in [0x0200, 0x0523, 0x1000, 0x1fff].iter() {
+            assert!(cart.ram_byte(offset) == cart.ram_byte(offset & 0x1ff));
+        }
+
+        cart.set_ram_byte(0x1e05, 0x0c);
+        assert!(cart.ram_byte(0x0005) == 0xfc);
+    }
+
+    #[test]
+    fn huc1_banking() {
+        // HuC1+RAM+BATTERY, 1MB of ROM and 4 banks of RAM
+        let mut cart = Cartridge::from_vec(rom(0xff, 64, 0x03)).unwrap();
+
+        cart.set_rom_byte(0x2000, 0x25);
+        assert!(bank_at(&cart, 0x4000) == 0x25);
+
+        // Only 6 bits are connected
+        cart.set_rom_byte(0x2000, 0xc3);
+        assert!(bank_at(&cart, 0x4000) == 0x03);
+
+        cart.set_rom_byte(0x2000, 0x00);
+        assert!(bank_at(&cart, 0x4000) == 1);
+        assert!(bank_at(&cart, 0x0000) == 0);
+
+        // Anything but 0xe maps the RAM, it's never write protected
+        cart.set_rom_byte(0x0000, 0x00);
+        cart.set_ram_byte(0x0000, 0x11);
+        assert!(cart.ram_byte(0x0000) == 0x11);
+
+        cart.set_rom_byte(0x4000, 0x02);
+        assert!(cart.ram_byte(0x0000) == 0x00);
+
+        cart.set_ram_byte(0x0000, 0x22);
+
+        // Only 2 bits are connected
+        cart.set_rom_byte(0x4000, 0x04);
+        assert!(cart.ram_byte(0x0000) == 0x11);
+
+        cart.set_rom_byte(0x4000, 0x02);
+        assert!(cart.ram_byte(0x0000) == 0x22);
+    }
+
+    #[test]
+    fn huc1_ir_mode() {
+        let mut cart = Cartridge::from_vec(rom(0xff, 4, 0x02)).unwrap();
+
+        cart.set_rom_byte(0x0000, 0x0a);
+        cart.set_ram_byte(0x0100, 0x33);
+
+        // 0xe maps the IR port: nothing is ever received and writes
+        // don't reach the RAM
+        cart.set_rom_byte(0x0000, 0x0e);
+        assert!(cart.ir_mode());
+        assert!(cart.ram_byte(0x0100) == 0xc0);
+
+        cart.set_ram_byte(0x0100, 0x01);
+
+        // Any other value maps the RAM back
+        cart.set_rom_byte(0x1fff, 0x00);
+        assert!(!cart.ir_mode());
+        assert!(cart.ram_byte(0x0100) == 0x33);
+    }
+
+    #[test]
+    fn mbc5_rom_bank() {
+        let mut cart = Cartridge::from_vec(rom(0x19, 512, 0)).unwrap();
+
+        assert!(bank_at(&cart, 0x4000) == 1);
+
+        cart.set_rom_byte(0x2000, 0x42);
+        assert!(bank_at(&cart, 0x4000) == 0x42);
+
+        // Bit 8 comes from 0x3000, the low byte is kept
+        cart.set_rom_byte(0x3000, 0x01);
+        assert!(bank_at(&cart, 0x4000) == 0x142);
+
+        cart.set_rom_byte(0x2fff, 0xff);
+        assert!(bank_at(&cart, 0x4000) == 0x1ff);
+
+        // Only bit 0 is connected
+        cart.set_rom_byte(0x3fff, 0xfe);
+        assert!(bank_at(&cart, 0x4000) == 0xff);
+
+        // Unlike other MBCs bank 0 can be mapped at 0x4000
+        cart.set_rom_byte(0x2000, 0x00);
+        assert!(bank_at(&cart, 0x4000) == 0);
+        assert!(bank_at(&cart, 0x0000) == 0);
+    }
+
+    #[test]
+    fn mbc5_rumble_ram_bank() {
+        // MBC5+RUMBLE+RAM, 4 banks of RAM
+        let mut cart = Cartridge::from_vec(rom(0x1d, 4, 0x03)).unwrap();
+
+        cart.set_rom_byte(0x0000, 0x0a);
+
+        cart.set_rom_byte(0x4000, 0x02);
+        cart.set_ram_byte(0x0000, 0x55);
+
+        // Bit 3 starts the motor and doesn't change the RAM bank
+        cart.set_rom_byte(0x4000, 0x0a);
+        assert!(cart.rumble());
+        assert!(cart.ram_byte(0x0000) == 0x55);
+
+        cart.set_rom_byte(0x4000, 0x02);
+        assert!(!cart.rumble());
+        assert!(cart.ram_byte(0x0000) == 0x55);
+
+        cart.set_rom_byte(0x4000, 0x00);
+        assert!(cart.ram_byte(0x0000) == 0x00);
+    }
+
+    /// Run `gb` until the next `LD B, B`
+    fn run_to_breakpoint(gb: &mut GameBoy) {
+        // The bootrom alone takes a bit less than 6 seconds
+        for _ in 0..600 {
+            if gb.run_until_breakpoint() {
+                return;
+            }
+        }
+
+        panic!("breakpoint not reached");
+    }
+
+    /// The motor state set by the game is what the frontend gets
+    #[test]
+    fn mbc5_rumble() {
+        // MBC5+RUMBLE
+        let mut rom = rom(0x1c, 2, 0);
+
+        rom[0x104..0x134].copy_from_slice(&NINTENDO_LOGO);
+
+        // The bootrom refuses to start without a valid header
+        // checksum
+        rom[0x14d] = rom[0x134..0x14d].iter()
+            .fold(0u8, |sum, &b| sum.wrapping_sub(b).wrapping_sub(1));
+
+        let code = [
+            0x00,              // 0x100: NOP
+            0xc3, 0x50, 0x01,  //        JP 0x150
+        ];
+
+        rom[0x100..0x104].copy_from_slice(&code);
+
+        let code = [
+            0x3e, 0x08,        // 0x150: LD A, 0x08
+            0xea, 0x00, 0x40,  //        LD (0x4000), A
+            0x40,              //        LD B, B
+            0xaf,              //        XOR A
+            0xea, 0x00, 0x40,  //        LD (0x4000), A
+            0x40,              //        LD B, B
+            0x18, 0xfe,        //        JR -2
+        ];
+
+        rom[0x150..0x150 + code.len()].copy_from_slice(&code);
+
+        let mut gb = GameBoy::new(Cartridge::from_vec(rom).unwrap());
+
+        assert!(!gb.rumble());
+
+        run_to_breakpoint(&mut gb);
+        assert!(gb.rumble());
+
+        run_to_breakpoint(&mut gb);
+        assert!(!gb.rumble());
     }
 }
@@ -0,0 +1,442 @@
+//! IPS, UPS and BPS patch support. Patches are applied to the ROM
+//! image in memory, the original file is never modified.
+
+use super::CartridgeError;
+use crc::crc32;
+
+/// Apply `patch` to `rom` and return the patched image. The format
+/// is detected from the patch's magic number.
+pub fn apply(rom: Vec<u8>, patch: &[u8]) -> Result<Vec<u8>, CartridgeError> {
+    if patch.starts_with(b"PATCH") {
+        ips(rom, patch)
+    } else if patch.starts_with(b"UPS1") {
+        ups(&rom, patch)
+    } else if patch.starts_with(b"BPS1") {
+        bps(&rom, patch)
+    } else {
+        Err(CartridgeError::InvalidPatch("unknown patch format"))
+    }
+}
+
+/// Sequential reader over the patch data
+struct Reader<'a> {
+    data: &'a [u8],
+    pos:  usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8], pos: usize) -> Reader<'a> {
+        Reader {
+            data: data,
+            pos:  pos,
+        }
+    }
+
+    fn byte(&mut self) -> Result<u8, CartridgeError> {
+        match self.data.get(self.pos) {
+            Some(&b) => {
+                self.pos += 1;
+                Ok(b)
+            }
+            None => Err(CartridgeError::InvalidPatch("truncated patch")),
+        }
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], CartridgeError> {
+        match self.pos.checked_add(len) {
+            Some(end) if end <= self.data.len() => (),
+            _ => return Err(CartridgeError::InvalidPatch("truncated patch")),
+        }
+
+        let b = &self.data[self.pos..self.pos + len];
+
+        self.pos += len;
+
+        Ok(b)
+    }
+
+    /// Read a `len` bytes big endian value
+    fn be(&mut self, len: usize) -> Result<usize, CartridgeError> {
+        let b = try!(self.bytes(len));
+
+        Ok(b.iter().fold(0, |v, &b| (v << 8) | b as usize))
+    }
+
+    /// Read a variable length integer as used by UPS and BPS
+    fn varint(&mut self) -> Result<usize, CartridgeError> {
+        let mut v     = 0usize;
+        let mut shift = 1usize;
+
+        loop {
+            let b = try!(self.byte()) as usize;
+
+            let next = (b & 0x7f).checked_mul(shift)
+                .and_then(|n| v.checked_add(n));
+
+            v = try!(next.ok_or(CartridgeError::InvalidPatch("bad varint")));
+
+            if b & 0x80 != 0 {
+                return Ok(v);
+            }
+
+            shift = try!(shift.checked_mul(0x80)
+                         .ok_or(CartridgeError::InvalidPatch("bad varint")));
+
+            v = try!(v.checked_add(shift)
+                     .ok_or(CartridgeError::InvalidPatch("bad varint")));
+        }
+    }
+}
+
+fn ips(mut rom: Vec<u8>, patch: &[u8]) -> Result<Vec<u8>, CartridgeError> {
+    let mut r = Reader::new(patch, 5);
+
+    loop {
+        let offset = try!(r.be(3));
+
+        if offset == IPS_EOF {
+            break;
+        }
+
+        let size = try!(r.be(2));
+
+        // A size of 0 denotes an RLE record
+        let (len, rle) =
+            match size {
+                0 => (try!(r.be(2)), Some(try!(r.byte()))),
+                n => (n, None),
+            };
+
+        if rom.len() < offset + len {
+            rom.resize(offset + len, 0);
+        }
+
+        match rle {
+            Some(v) =>
+                for b in &mut rom[offset..offset + len] {
+                    *b = v;
+                },
+            None => {
+                let data = try!(r.bytes(len));
+
+                for (b, &v) in rom[offset..].iter_mut().zip(data) {
+                    *b = v;
+                }
+            }
+        }
+    }
+
+    // Some patches add a 3 byte truncation offset after the EOF
+    // marker
+    if let Ok(size) = r.be(3) {
+        rom.truncate(size);
+    }
+
+    Ok(rom)
+}
+
+/// Check the 12 byte footer of UPS and BPS patches: CRC-32 of the
+/// source, of the target and of the patch itself.
+fn check_crcs(source: &[u8],
+              target: &[u8],
+              patch:  &[u8]) -> Result<(), CartridgeError> {
+    let footer = &patch[patch.len() - 12..];
+
+    let read = |off: usize| {
+        footer[off..off + 4]
+            .iter()
+            .rev()
+            .fold(0u32, |v, &b| (v << 8) | b as u32)
+    };
+
+    let checks = [("patch",  read(8), crc32(&patch[..patch.len() - 4])),
+                  ("source", read(0), crc32(source)),
+                  ("target", read(4), crc32(target))];
+
+    for &(what, expected, got) in checks.iter() {
+        if expected != got {
+            return Err(CartridgeError::PatchChecksum {
+                what:     what,
+                expected: expected,
+                got:      got,
+            });
+        }
+    }
+
+    Ok(())
+}
+
+fn ups(source: &[u8], patch: &[u8]) -> Result<Vec<u8>, CartridgeError> {
+    if patch.len() < 4 + 12 {
+        return Err(CartridgeError::InvalidPatch("truncated patch"));
+    }
+
+    let end = patch.len() - 12;
+
+    let mut r = Reader::new(&patch[..end], 4);
+
+    let _source_size = try!(r.varint());
+    let target_size  = try!(r.varint());
+
+    if target_size > MAX_TARGET_SIZE {
+        return Err(CartridgeError::InvalidPatch("target too large"));
+    }
+
+    let mut target: Vec<u8> =
+        source.iter().cloned().chain(::std::iter::repeat(0))
+        .take(target_size)
+        .collect();
+
+    let mut pos = 0usize;
+
+    while r.pos < end {
+        let skip = try!(r.varint());
+
+        pos = try!(pos.checked_add(skip)
+                   .ok_or(CartridgeError::InvalidPatch("bad offset")));
+
+        // XOR the source with the patch until we reach a 0 byte
+        loop {
+            let x = try!(r.byte());
+
+            if let Some(b) = target.get_mut(pos) {
+                *b ^= x;
+            }
+
+            pos = pos.saturating_add(1);
+
+            if x == 0 {
+                break;
+            }
+        }
+    }
+
+    try!(check_crcs(source, &target, patch));
+
+    Ok(target)
+}
+
+fn bps(source: &[u8], patch: &[u8]) -> Result<Vec<u8>, CartridgeError> {
+    if patch.len() < 4 + 12 {
+        return Err(CartridgeError::InvalidPatch("truncated patch"));
+    }
+
+    let end = patch.len() - 12;
+
+    let mut r = Reader::new(&patch[..end], 4);
+
+    let _source_size  = try!(r.varint());
+    let target_size   = try!(r.varint());
+    let metadata_size = try!(r.varint());
+
+    try!(r.bytes(metadata_size));
+
+    if target_size > MAX_TARGET_SIZE {
+        return Err(CartridgeError::InvalidPatch("target too large"));
+    }
+
+    let mut target = Vec::with_capacity(target_size);
+
+    let mut source_offset: isize = 0;
+    let mut target_offset: isize = 0;
+
+    let invalid = CartridgeError::InvalidPatch;
+
+    while r.pos < end {
+        let data = try!(r.varint());
+        let len  = (data >> 2) + 1;
+
+        if len > target_size - target.len() {
+            return Err(invalid("target overflow"));
+        }
+
+        match data & 3 {
+            // SourceRead
+            0 => {
+                let start = target.len();
+
+                match start.checked_add(len) {
+                    Some(end) if end <= source.len() => (),
+                    _ => return Err(invalid("source read out of range")),
+                }
+
+                target.extend(source[start..start + len].iter().cloned());
+            }
+            // TargetRead
+            1 => target.extend(try!(r.bytes(len)).iter().cloned()),
+            // SourceCopy
+            2 => {
+                let offset = try!(relative_offset(&mut r));
+
+                source_offset =
+                    match source_offset.checked_add(offset) {
+                        Some(o) if o >= 0 => o,
+                        _ => return Err(invalid("source copy out of range")),
+                    };
+
+                match (source_offset as usize).checked_add(len) {
+                    Some(end) if end <= source.len() => (),
+                    _ => return Err(invalid("source copy out of range")),
+                }
+
+                let start = source_offset as usize;
+
+                target.extend(source[start..start + len].iter().cloned());
+
+                source_offset += len as isize;
+            }
+            // TargetCopy
+            _ => {
+                let offset = try!(relative_offset(&mut r));
+
+                target_offset =
+                    match target_offset.checked_add(offset) {
+                        Some(o) if o >= 0 => o,
+                        _ => return Err(invalid("target copy out of range")),
+                    };
+
+                // The copy can overlap the bytes we're writing so it
+                // has to be done one byte at a time
+                for _ in 0..len {
+                    let b = match target.get(target_offset as usize) {
+                        Some(&b) => b,
+                        None => return Err(invalid("target copy out of range")),
+                    };
+
+                    target.push(b);
+
+                    target_offset += 1;
+                }
+            }
+        }
+    }
+
+    if target.len() != target_size {
+        return Err(invalid("unexpected target size"));
+    }
+
+    try!(check_crcs(source, &target, patch));
+
+    Ok(target)
+}
+
+/// Decode the signed relative offset used by BPS copy commands
+fn relative_offset(r: &mut Reader) -> Result<isize, CartridgeError> {
+    let data = try!(r.varint());
+
+    let offset = (data >> 1) as isize;
+
+    if data & 1 != 0 {
+        Ok(-offset)
+    } else {
+        Ok(offset)
+    }
+}
+
+/// Offset used as an end of file marker in IPS patches ("EOF")
+const IPS_EOF: usize = 0x454f46;
+
+/// Largest image a UPS or BPS patch is allowed to produce, way more
+/// than any Game Boy ROM. Protects against corrupted sizes.
+const MAX_TARGET_SIZE: usize = 64 * 1024 * 1024;
+
+#[cfg(test)]
+mod tests {
+    use super::apply;
+    use crc::crc32;
+
+    fn push_crc(v: &mut Vec<u8>, crc: u32) {
+        for i in 0..4 {
+            v.push((crc >> (i * 8)) as u8);
+        }
+    }
+
+    #[test]
+    fn ips() {
+        let rom = vec![0; 8];
+
+        let patch = b"PATCH\
+                      \x00\x00\x01\x00\x02\xaa\xbb\
+                      \x00\x00\x06\x00\x00\x00\x04\xcc\
+                      EOF";
+
+        let rom = apply(rom, patch).unwrap();
+
+        assert!(rom == [0, 0xaa, 0xbb, 0, 0, 0, 0xcc, 0xcc, 0xcc, 0xcc]);
+    }
+
+    #[test]
+    fn ups() {
+        let source = vec![1, 2, 3, 4];
+        let target = vec![1, 5, 3, 4, 6];
+
+        // Sizes 4 and 5, skip 1 byte, XOR 2 ^ 5 then terminate, skip
+        // 2 bytes, XOR 0 ^ 6 then terminate
+        let mut patch = b"UPS1\x84\x85\x81\x07\x00\x81\x06\x00".to_vec();
+
+        push_crc(&mut patch, crc32(&source));
+        push_crc(&mut patch, crc32(&target));
+
+        let crc = crc32(&patch);
+        push_crc(&mut patch, crc);
+
+        assert!(apply(source.clone(), &patch).unwrap() == target);
+
+        // Corrupt the patch
+        patch[6] = 0x08;
+
+        assert!(apply(source, &patch).is_err());
+    }
+
+    #[test]
+    fn bps() {
+        let source = vec![1, 2, 3, 4];
+        let target = vec![1, 2, 9, 1, 2, 1, 2];
+
+        // Sizes 4 and 7, no metadata. SourceRead 2, TargetRead 1,
+        // SourceCopy 2 from 0, TargetCopy 2 from 3
+        let mut patch = b"BPS1\x84\x87\x80\
+                          \x84\
+                          \x81\x09\
+                          \x86\x80\
+                          \x87\x86".to_vec();
+
+        push_crc(&mut patch, crc32(&source));
+        push_crc(&mut patch, crc32(&target));
+
+        let crc = crc32(&patch);
+        push_crc(&mut patch, crc);
+
+        assert!(apply(source.clone(), &patch).unwrap() == target);
+
+        // Wrong source
+        assert!(apply(vec![0, 2, 3, 4], &patch).is_err());
+    }
+
+    #[test]
+    fn overflow() {
+        // Varints that don't fit in a usize
+        let huge = [0x7f; 16];
+
+        let mut ups = b"UPS1".to_vec();
+        ups.extend(huge.iter().cloned());
+        ups.extend([0; 12].iter().cloned());
+
+        assert!(apply(vec![0; 4], &ups).is_err());
+
+        let mut bps = b"BPS1\x84\x84\x80".to_vec();
+        bps.extend(huge.iter().cloned());
+        bps.push(0x80);
+        bps.extend([0; 12].iter().cloned());
+
+        assert!(apply(vec![0; 4], &bps).is_err());
+
+        // SourceCopy with a relative offset close to the maximum
+        let mut bps = b"BPS1\x84\x84\x80\x86".to_vec();
+        bps.extend([0x7e; 8].iter().cloned());
+        bps.push(0x80);
+        bps.extend([0; 12].iter().cloned());
+
+        assert!(apply(vec![0; 4], &bps).is_err());
+    }
+}
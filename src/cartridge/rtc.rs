@@ -0,0 +1,533 @@
+//! MBC3 Real Time Clock emulation.
+//!
+//! The RTC counts seconds, minutes, hours and days (9 bits) and keeps
+//! running while the console is off thanks to the cartridge
+//! battery. The game accesses the registers by mapping them in the
+//! RAM range (RAM bank values 0x08 to 0x0c) and can freeze a copy of
+//! the current time using the latch sequence (writing 0x00 then 0x01
+//! in the [0x6000, 0x7fff] ROM range).
+
+use savestate::{Writer, Reader, SaveStateError};
+
+/// RTC state
+pub struct Rtc {
+    /// Live clock registers
+    regs:        Registers,
+    /// Copy of the registers taken at the last latch. This is what
+    /// the game reads.
+    latched:     Registers,
+    /// `true` if the last value written to the latch register was 0,
+    /// in which case a write of 1 will latch the clock.
+    latch_armed: bool,
+    /// Counter of system clock ticks within the current second
+    ticks:       u32,
+}
+
+impl Rtc {
+    pub fn new() -> Rtc {
+        Rtc {
+            regs:        Registers::new(),
+            latched:     Registers::new(),
+            latch_armed: false,
+            ticks:       0,
+        }
+    }
+
+    /// Called at each tick of the system clock. Advance the clock by
+    /// one second every `TICKS_PER_SECOND` ticks unless it's halted.
+    pub fn step(&mut self) {
+        if self.regs.halted {
+            return;
+        }
+
+        self.ticks += 1;
+
+        if self.ticks == TICKS_PER_SECOND {
+            self.ticks = 0;
+            self.regs.tick();
+        }
+    }
+
+    /// Handle a write to the latch register. Writing 0x00 followed by
+    /// 0x01 copies the live registers into the latched ones.
+    pub fn latch(&mut self, val: u8) {
+        if self.latch_armed && val == 1 {
+            self.latched = self.regs;
+        }
+
+        self.latch_armed = val == 0;
+    }
+
+    /// Read the latched value of register `reg` (0x08 to 0x0c)
+    pub fn register(&self, reg: u8) -> u8 {
+        self.latched.register(reg)
+    }
+
+    /// Write `val` into the live register `reg` (0x08 to 0x0c)
+    pub fn set_register(&mut self, reg: u8, val: u8) {
+        if reg == SECONDS {
+            // Writing the seconds resets the sub-second divider
+            self.ticks = 0;
+        }
+
+        self.regs.set_register(reg, val);
+    }
+
+    /// Advance the live clock by `seconds`. Used to catch up with the
+    /// wall clock time elapsed since the RTC state was saved. Does
+    /// nothing if the clock is halted.
+    pub fn advance(&mut self, seconds: u64) {
+        if !self.regs.halted {
+            self.regs.advance(seconds);
+        }
+    }
+
+    /// Serialize the RTC state into the 48-byte footer appended to
+    /// the RAM image in save files. The format is the one used by
+    /// VBA, BGB and mGBA among others: the 5 live registers and the 5
+    /// latched registers each stored as a little endian 32bit word
+    /// followed by the current UNIX timestamp as a 64bit little
+    /// endian word.
+    pub fn footer(&self) -> Vec<u8> {
+        let mut footer = Vec::with_capacity(FOOTER_SIZE);
+
+        for regs in [self.regs, self.latched].iter() {
+            for reg in SECONDS..(DAYS_HIGH + 1) {
+                push_le(&mut footer, regs.register(reg) as u64, 4);
+            }
+        }
+
+        push_le(&mut footer, now(), 8);
+
+        footer
+    }
+
+    /// Restore the RTC state from a save file footer and catch up
+    /// with the time elapsed since it was written. Accepts both the
+    /// 48-byte format and the older 44-byte variant with a 32bit
+    /// timestamp.
+    pub fn load_footer(&mut self, footer: &[u8]) {
+        let timestamp_size = match footer.len() {
+            FOOTER_SIZE     => 8,
+            FOOTER_SIZE_OLD => 4,
+            n               => panic!("Invalid RTC footer size: {}", n),
+        };
+
+        for (i, reg) in (SECONDS..(DAYS_HIGH + 1)).enumerate() {
+            let live    = read_le(&footer[i * 4..], 4) as u8;
+            let latched = read_le(&footer[20 + i * 4..], 4) as u8;
+
+            self.regs.set_register(reg, live);
+            self.latched.set_register(reg, latched);
+        }
+
+        let timestamp = read_le(&footer[40..], timestamp_size);
+
+        self.catch_up(timestamp);
+    }
+
+    /// Serialize the complete clock state for a save state. Unlike
+    /// the save file footer this doesn't depend on the wall clock:
+    /// loading the state restores the emulated time exactly.
+    pub fn save_state(&self, w: &mut Writer) {
+        self.regs.save_state(w);
+        self.latched.save_state(w);
+        w.bool(self.latch_armed);
+        w.u32(self.ticks);
+    }
+
+    /// Restore a state serialized by `save_state`
+    pub fn load_state(&mut self, r: &mut Reader) -> Result<(), SaveStateError> {
+        self.regs        = try!(Registers::load_state(r));
+        self.latched     = try!(Registers::load_state(r));
+        self.latch_armed = try!(r.bool());
+        self.ticks       = try!(r.u32());
+
+        if self.ticks >= TICKS_PER_SECOND {
+            return Err(SaveStateError::Invalid("bad RTC tick counter"));
+        }
+
+        Ok(())
+    }
+
+    /// Catch up with the wall clock, `timestamp` is the UNIX time at
+    /// which the current RTC state was recorded.
+    pub fn catch_up(&mut self, timestamp: u64) {
+        let now = now();
+
+        if now > timestamp {
+            self.advance(now - timestamp);
+        }
+    }
+}
+
+/// Set of RTC registers
+#[derive(Clone,Copy)]
+struct Registers {
+    /// Seconds [0, 59], the game can write up to 63
+    seconds:   u8,
+    /// Minutes [0, 59], the game can write up to 63
+    minutes:   u8,
+    /// Hours [0, 23], the game can write up to 31
+    hours:     u8,
+    /// Day counter [0, 511]
+    days:      u16,
+    /// `true` if the clock is stopped
+    halted:    bool,
+    /// Set when the day counter overflows. Only cleared by an
+    /// explicit write.
+    day_carry: bool,
+}
+
+impl Registers {
+    fn new() -> Registers {
+        Registers {
+            seconds:   0,
+            minutes:   0,
+            hours:     0,
+            days:      0,
+            halted:    false,
+            day_carry: false,
+        }
+    }
+
+    fn save_state(&self, w: &mut Writer) {
+        w.u8(self.seconds);
+        w.u8(self.minutes);
+        w.u8(self.hours);
+        w.u16(self.days);
+        w.bool(self.halted);
+        w.bool(self.day_carry);
+    }
+
+    fn load_state(r: &mut Reader) -> Result<Registers, SaveStateError> {
+        let regs = Registers {
+            seconds:   try!(r.u8()),
+            minutes:   try!(r.u8()),
+            hours:     try!(r.u8()),
+            days:      try!(r.u16()),
+            halted:    try!(r.bool()),
+            day_carry: try!(r.bool()),
+        };
+
+        // The game can write out of range values (up to 63 seconds
+        // for instance) but nothing wider than the hardware
+        // registers
+        if regs.seconds > 0x3f || regs.minutes > 0x3f ||
+           regs.hours > 0x1f || regs.days > 0x1ff {
+            return Err(SaveStateError::Invalid("bad RTC register value"));
+        }
+
+        Ok(regs)
+    }
+
+    fn register(&self, reg: u8) -> u8 {
+        match reg {
+            SECONDS  => self.seconds,
+            MINUTES  => self.minutes,
+            HOURS    => self.hours,
+            DAYS_LOW => self.days as u8,
+            DAYS_HIGH => {
+                let mut r = 0;
+
+                r |= ((self.days >> 8) as u8) & 1;
+                r |= (self.halted    as u8) << 6;
+                r |= (self.day_carry as u8) << 7;
+
+                r
+            }
+            _ => panic!("Invalid RTC register 0x{:02x}", reg),
+        }
+    }
+
+    fn set_register(&mut self, reg: u8, val: u8) {
+        match reg {
+            SECONDS  => self.seconds = val & 0x3f,
+            MINUTES  => self.minutes = val & 0x3f,
+            HOURS    => self.hours   = val & 0x1f,
+            DAYS_LOW => self.days    = (self.days & 0x100) | val as u16,
+            DAYS_HIGH => {
+                self.days      = (self.days & 0xff) | ((val as u16 & 1) << 8);
+                self.halted    = val & 0x40 != 0;
+                self.day_carry = val & 0x80 != 0;
+            }
+            _ => panic!("Invalid RTC register 0x{:02x}", reg),
+        }
+    }
+
+    /// Advance the clock by one second. Registers set to out-of-range
+    /// values by the game keep counting until they overflow their
+    /// bit width without carrying, like on the real hardware.
+    fn tick(&mut self) {
+        self.seconds = (self.seconds + 1) & 0x3f;
+
+        if self.seconds != 60 {
+            return;
+        }
+
+        self.seconds = 0;
+        self.minutes = (self.minutes + 1) & 0x3f;
+
+        if self.minutes != 60 {
+            return;
+        }
+
+        self.minutes = 0;
+        self.hours = (self.hours + 1) & 0x1f;
+
+        if self.hours != 24 {
+            return;
+        }
+
+        self.hours = 0;
+        self.days += 1;
+
+        if self.days == 512 {
+            self.days      = 0;
+            self.day_carry = true;
+        }
+    }
+
+    /// Advance the clock by `seconds` at once
+    fn advance(&mut self, seconds: u64) {
+        let total =
+            self.seconds as u64 +
+            self.minutes as u64 * 60 +
+            self.hours   as u64 * 60 * 60 +
+            self.days    as u64 * 60 * 60 * 24 +
+            seconds;
+
+        let days = total / (60 * 60 * 24);
+
+        if days >= 512 {
+            self.day_carry = true;
+        }
+
+        self.seconds = (total % 60) as u8;
+        self.minutes = ((total / 60) % 60) as u8;
+        self.hours   = ((total / (60 * 60)) % 24) as u8;
+        self.days    = (days % 512) as u16;
+    }
+}
+
+/// Return the current UNIX time in seconds
+pub fn now() -> u64 {
+    let t = ::time::get_time().sec;
+
+    if t < 0 {
+        0
+    } else {
+        t as u64
+    }
+}
+
+/// Append the `size` low bytes of `val` to `v` in little endian
+pub fn push_le(v: &mut Vec<u8>, val: u64, size: usize) {
+    for i in 0..size {
+        v.push((val >> (i * 8)) as u8);
+    }
+}
+
+/// Read a `size` bytes little endian word at the beginning of `b`
+pub fn read_le(b: &[u8], size: usize) -> u64 {
+    let mut r = 0;
+
+    for i in 0..size {
+        r |= (b[i] as u64) << (i * 8);
+    }
+
+    r
+}
+
+/// Size of the RTC footer in save files
+pub const FOOTER_SIZE:     usize = 48;
+/// Size of the RTC footer in save files using a 32bit timestamp
+pub const FOOTER_SIZE_OLD: usize = 44;
+
+/// Number of system clock ticks in one second
+const TICKS_PER_SECOND: u32 = 0x400000;
+
+/// Seconds register
+pub const SECONDS:   u8 = 0x08;
+/// Minutes register
+pub const MINUTES:   u8 = 0x09;
+/// Hours register
+pub const HOURS:     u8 = 0x0a;
+/// Low 8 bits of the day counter
+pub const DAYS_LOW:  u8 = 0x0b;
+/// Bit 0: MSB of the day counter, bit 6: halt, bit 7: day carry
+pub const DAYS_HIGH: u8 = 0x0c;
+
+#[cfg(test)]
+mod tests {
+    use super::{Rtc, SECONDS, MINUTES, HOURS, DAYS_LOW, DAYS_HIGH};
+    use savestate::{Writer, Reader, SaveStateError};
+
+    /// Registers are only visible after a latch
+    #[test]
+    fn latch() {
+        let mut rtc = Rtc::new();
+
+        rtc.set_register(MINUTES, 42);
+
+        assert!(rtc.register(MINUTES) == 0);
+
+        // Latching requires a 0 -> 1 transition
+        rtc.latch(1);
+        assert!(rtc.register(MINUTES) == 0);
+
+        rtc.latch(0);
+        rtc.latch(1);
+        assert!(rtc.register(MINUTES) == 42);
+    }
+
+    /// Check that the clock counts emulated time and carries through
+    /// all the registers
+    #[test]
+    fn ticking() {
+        let mut rtc = Rtc::new();
+
+        rtc.set_register(SECONDS,   59);
+        rtc.set_register(MINUTES,   59);
+        rtc.set_register(HOURS,     23);
+        rtc.set_register(DAYS_LOW,  0xff);
+        rtc.set_register(DAYS_HIGH, 0x01);
+
+        for _ in 0..super::TICKS_PER_SECOND {
+            rtc.step();
+        }
+
+        rtc.latch(0);
+        rtc.latch(1);
+
+        assert!(rtc.register(SECONDS)   == 0);
+        assert!(rtc.register(MINUTES)   == 0);
+        assert!(rtc.register(HOURS)     == 0);
+        assert!(rtc.register(DAYS_LOW)  == 0);
+        // Day counter overflowed
+        assert!(rtc.register(DAYS_HIGH) == 0x80);
+    }
+
+    /// A halted clock doesn't move
+    #[test]
+    fn halt() {
+        let mut rtc = Rtc::new();
+
+        rtc.set_register(DAYS_HIGH, 0x40);
+
+        for _ in 0..super::TICKS_PER_SECOND {
+            rtc.step();
+        }
+
+        rtc.advance(3600);
+
+        rtc.latch(0);
+        rtc.latch(1);
+
+        assert!(rtc.register(SECONDS) == 0);
+        assert!(rtc.register(HOURS)   == 0);
+    }
+
+    /// Check that the save file footer round-trips
+    #[test]
+    fn footer() {
+        let mut rtc = Rtc::new();
+
+        rtc.set_register(SECONDS,   12);
+        rtc.set_register(MINUTES,   34);
+        rtc.latch(0);
+        rtc.latch(1);
+        rtc.set_register(HOURS,     5);
+        rtc.set_register(DAYS_LOW,  0x42);
+        // Halt the clock so that the catch up doesn't change anything
+        rtc.set_register(DAYS_HIGH, 0x41);
+
+        let footer = rtc.footer();
+
+        assert!(footer.len() == super::FOOTER_SIZE);
+
+        let mut loaded = Rtc::new();
+
+        loaded.load_footer(&footer);
+
+        assert!(loaded.register(SECONDS) == 12);
+        assert!(loaded.register(MINUTES) == 34);
+        assert!(loaded.register(HOURS)   == 0);
+
+        loaded.latch(0);
+        loaded.latch(1);
+
+        assert!(loaded.register(HOURS)     == 5);
+        assert!(loaded.register(DAYS_LOW)  == 0x42);
+        assert!(loaded.register(DAYS_HIGH) == 0x41);
+
+        // The old 44-byte format only has a 32bit timestamp
+        let mut loaded = Rtc::new();
+
+        loaded.load_footer(&footer[..super::FOOTER_SIZE_OLD]);
+
+        assert!(loaded.register(MINUTES) == 34);
+    }
+
+    /// Catching up with a large amount of time at once
+    #[test]
+    fn advance() {
+        let mut rtc = Rtc::new();
+
+        rtc.set_register(SECONDS, 30);
+
+        // 3 days, 2 hours, 1 minute and 40 seconds
+        rtc.advance(3 * 86400 + 2 * 3600 + 60 + 40);
+
+        rtc.latch(0);
+        rtc.latch(1);
+
+        assert!(rtc.register(SECONDS)  == 10);
+        assert!(rtc.register(MINUTES)  == 2);
+        assert!(rtc.register(HOURS)    == 2);
+        assert!(rtc.register(DAYS_LOW) == 3);
+    }
+
+    /// Save states with register values the hardware can't hold are
+    /// rejected
+    #[test]
+    fn load_state() {
+        let mut rtc = Rtc::new();
+
+        // Out of range but the game can write them
+        rtc.set_register(SECONDS, 63);
+        rtc.set_register(HOURS,   31);
+
+        let mut w = Writer::new();
+
+        rtc.save_state(&mut w);
+
+        let state = w.into_bytes();
+
+        let mut loaded = Rtc::new();
+
+        assert!(loaded.load_state(&mut Reader::new(&state)).is_ok());
+
+        loaded.latch(0);
+        loaded.latch(1);
+
+        assert!(loaded.register(SECONDS) == 63);
+        assert!(loaded.register(HOURS)   == 31);
+
+        // Seconds, minutes, hours and days high byte of the live
+        // then latched registers
+        let bad = [(0, 64), (1, 64), (2, 32), (4, 2),
+                   (7, 64), (8, 64), (9, 32), (11, 2)];
+
+        for &(offset, val) in bad.iter() {
+            let mut state = state.clone();
+
+            state[offset] = val;
+
+            match Rtc::new().load_state(&mut Reader::new(&state)) {
+                Err(SaveStateError::Invalid(_)) => (),
+                _ => panic!("bad RTC register value accepted"),
+            }
+        }
+    }
+}
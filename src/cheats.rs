@@ -0,0 +1,331 @@
+//! Game Genie and GameShark cheat codes.
+//!
+//! Game Genie codes patch the ROM: they replace the byte read at a
+//! given address, optionally only if the original value matches a
+//! compare byte (which is how they target a single ROM bank).
+//! GameShark codes write a value to RAM once per frame.
+
+use std::fs::File;
+use std::io::Read;
+use std::io::Result as IoResult;
+use std::path::Path;
+
+/// A decoded cheat code
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum Cheat {
+    /// Replace the ROM byte at `address` with `value`. If `compare`
+    /// is set the patch only applies when the original byte matches.
+    GameGenie {
+        /// ROM address to patch
+        address: u16,
+        /// Value returned instead of the ROM byte
+        value:   u8,
+        /// Original value of the ROM byte, if the code has one
+        compare: Option<u8>,
+    },
+    /// Write `value` at `address` every frame. `kind` is the code
+    /// type: 0x01 for regular codes, the others select a work RAM
+    /// bank on the Game Boy Color. We don't have banked work RAM so
+    /// it's ignored.
+    GameShark {
+        /// Code type
+        kind:    u8,
+        /// RAM address to write to
+        address: u16,
+        /// Value written every frame
+        value:   u8,
+    },
+}
+
+impl Cheat {
+    /// Decode a Game Genie ("ABC-DEF" or "ABC-DEF-GHI") or GameShark
+    /// ("TTVVLLHH") code
+    pub fn parse(code: &str) -> Result<Cheat, &'static str> {
+        let digits: Vec<u8> =
+            try!(code.chars()
+                 .filter(|&c| c != '-')
+                 .map(|c| c.to_digit(16)
+                      .map(|d| d as u8)
+                      .ok_or("invalid hex digit"))
+                 .collect());
+
+        let byte = |i: usize| (digits[i] << 4) | digits[i + 1];
+
+        match digits.len() {
+            6 | 9 => {
+                // The high nibble of the address is inverted
+                let address =
+                    ((digits[5] ^ 0xf) as u16) << 12 |
+                    (digits[2] as u16) << 8 |
+                    (digits[3] as u16) << 4 |
+                    digits[4] as u16;
+
+                if address >= 0x8000 {
+                    return Err("Game Genie address outside of ROM");
+                }
+
+                // The 8th digit isn't used, the compare value is
+                // scrambled in the two others
+                let compare = match digits.len() {
+                    9 => {
+                        let c = (digits[6] << 4) | digits[8];
+
+                        Some(c.rotate_right(2) ^ 0xba)
+                    }
+                    _ => None,
+                };
+
+                Ok(Cheat::GameGenie {
+                    address: address,
+                    value:   byte(0),
+                    compare: compare,
+                })
+            }
+            8 => {
+                // Little endian address
+                let address = (byte(6) as u16) << 8 | byte(4) as u16;
+
+                if address < 0x8000 {
+                    return Err("GameShark address outside of RAM");
+                }
+
+                Ok(Cheat::GameShark {
+                    kind:    byte(0),
+                    address: address,
+                    value:   byte(2),
+                })
+            }
+            _ => Err("unknown code format"),
+        }
+    }
+}
+
+/// A cheat code along with its state
+#[derive(Debug,Clone)]
+pub struct Code {
+    /// Code as entered by the user
+    pub code:        String,
+    /// Free form description, may be empty
+    pub description: String,
+    /// `false` if the code has been turned off
+    pub enabled:     bool,
+    /// Decoded code
+    pub cheat:       Cheat,
+}
+
+/// Set of cheats applied to the running game
+pub struct Cheats {
+    /// All the codes, including disabled ones
+    codes:   Vec<Code>,
+    /// Global switch
+    enabled: bool,
+    /// Active Game Genie patches: (address, value, compare). Kept
+    /// separately since they're looked up on every ROM access.
+    patches: Vec<(u16, u8, Option<u8>)>,
+    /// Active GameShark writes: (address, value)
+    writes:  Vec<(u16, u8)>,
+}
+
+impl Cheats {
+    /// Create an empty cheat set
+    pub fn new() -> Cheats {
+        Cheats {
+            codes:   Vec::new(),
+            enabled: true,
+            patches: Vec::new(),
+            writes:  Vec::new(),
+        }
+    }
+
+    /// Load the cheats in a `.cht` file. The file contains one code
+    /// per line, optionally followed by a description. Codes
+    /// prefixed with '!' start disabled and lines starting with '#'
+    /// are comments. Invalid codes are skipped with a warning.
+    pub fn from_path(path: &Path) -> IoResult<Cheats> {
+        let mut file = try!(File::open(path));
+
+        let mut text = String::new();
+
+        try!(file.read_to_string(&mut text));
+
+        let mut cheats = Cheats::new();
+
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (code, description) =
+                match line.find(char::is_whitespace) {
+                    Some(i) => (&line[..i], line[i..].trim()),
+                    None    => (line, ""),
+                };
+
+            let (code, enabled) =
+                match code.starts_with('!') {
+                    true  => (&code[1..], false),
+                    false => (code, true),
+                };
+
+            if let Err(e) = cheats.add(code, description, enabled) {
+                warn!("{}:{}: ignoring cheat {}: {}",
+                      path.display(), n + 1, code, e);
+            }
+        }
+
+        Ok(cheats)
+    }
+
+    /// Decode and add a new code
+    pub fn add(&mut self,
+               code: &str,
+               description: &str,
+               enabled: bool) -> Result<(), &'static str> {
+        let cheat = try!(Cheat::parse(code));
+
+        self.codes.push(Code {
+            code:        code.to_string(),
+            description: description.to_string(),
+            enabled:     enabled,
+            cheat:       cheat,
+        });
+
+        self.rebuild();
+
+        Ok(())
+    }
+
+    /// Return all the codes, including disabled ones
+    pub fn codes(&self) -> &[Code] {
+        &self.codes
+    }
+
+    /// Enable or disable the code at `index`
+    pub fn set_code_enabled(&mut self, index: usize, enabled: bool) {
+        if let Some(c) = self.codes.get_mut(index) {
+            c.enabled = enabled;
+        }
+
+        self.rebuild();
+    }
+
+    /// Return `true` if cheats are globally enabled
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Globally enable or disable cheats without losing the state of
+    /// the individual codes
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+
+        self.rebuild();
+    }
+
+    /// Flip the global switch and return the new state
+    pub fn toggle(&mut self) -> bool {
+        let enabled = !self.enabled;
+
+        self.set_enabled(enabled);
+
+        enabled
+    }
+
+    /// Apply the Game Genie codes to `val`, the byte read from the
+    /// ROM at `addr`
+    pub fn rom_byte(&self, addr: u16, val: u8) -> u8 {
+        for &(a, v, compare) in &self.patches {
+            if a == addr && compare.map_or(true, |c| c == val) {
+                return v;
+            }
+        }
+
+        val
+    }
+
+    /// Return the (address, value) pairs of the GameShark codes to
+    /// write at each frame
+    pub fn ram_writes(&self) -> &[(u16, u8)] {
+        &self.writes
+    }
+
+    /// Recompute the active patches after a change
+    fn rebuild(&mut self) {
+        self.patches.clear();
+        self.writes.clear();
+
+        if !self.enabled {
+            return;
+        }
+
+        for c in self.codes.iter().filter(|c| c.enabled) {
+            match c.cheat {
+                Cheat::GameGenie { address, value, compare } =>
+                    self.patches.push((address, value, compare)),
+                Cheat::GameShark { address, value, .. } =>
+                    self.writes.push((address, value)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Cheat, Cheats};
+
+    #[test]
+    fn parse() {
+        assert!(Cheat::parse("3E2-D5F") ==
+                Ok(Cheat::GameGenie {
+                    address: 0x02d5,
+                    value:   0x3e,
+                    compare: None,
+                }));
+
+        // Compare digits 0x9 and 0x4: 0x94 rotated right by 2 is
+        // 0x25, xored with 0xba gives 0x9f
+        assert!(Cheat::parse("004-A2B-9E4") ==
+                Ok(Cheat::GameGenie {
+                    address: 0x44a2,
+                    value:   0x00,
+                    compare: Some(0x9f),
+                }));
+
+        assert!(Cheat::parse("010238cd") ==
+                Ok(Cheat::GameShark {
+                    kind:    0x01,
+                    address: 0xcd38,
+                    value:   0x02,
+                }));
+
+        // GameShark codes can't target the ROM
+        assert!(Cheat::parse("01023845").is_err());
+        assert!(Cheat::parse("XYZ-123").is_err());
+        assert!(Cheat::parse("0123").is_err());
+    }
+
+    #[test]
+    fn apply() {
+        let mut cheats = Cheats::new();
+
+        cheats.add("004-A2B-9E4", "", true).unwrap();
+        cheats.add("010238cd", "", true).unwrap();
+
+        assert!(cheats.rom_byte(0x44a2, 0x9f) == 0x00);
+        // Compare mismatch, probably another bank
+        assert!(cheats.rom_byte(0x44a2, 0x12) == 0x12);
+        assert!(cheats.rom_byte(0x44a3, 0x9f) == 0x9f);
+        assert!(cheats.ram_writes() == [(0xcd38, 0x02)]);
+
+        assert!(!cheats.toggle());
+        assert!(cheats.rom_byte(0x44a2, 0x9f) == 0x9f);
+        assert!(cheats.ram_writes().is_empty());
+
+        cheats.toggle();
+        cheats.set_code_enabled(1, false);
+        assert!(cheats.rom_byte(0x44a2, 0x9f) == 0x00);
+        assert!(cheats.ram_writes().is_empty());
+    }
+}
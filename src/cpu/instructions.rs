@@ -522,8 +522,9 @@ fn ld_l_n(cpu: &mut Cpu) {
 fn ld_a_a(_: &mut Cpu) {
 }
 
-/// Load `B` into `B` (NOP)
-fn ld_b_b(_: &mut Cpu) {
+/// Load `B` into `B` (NOP). Used as a breakpoint by test ROMs.
+fn ld_b_b(cpu: &mut Cpu) {
+    cpu.breakpoint = true;
 }
 
 /// Load `C` into `C` (NOP)
@@ -2,13 +2,14 @@
 
 use std::fmt::{Debug, Formatter, Error};
 use io::{Interconnect, Interrupt};
+use savestate::{Writer, Reader, SaveStateError};
 
 use cpu::instructions::next_instruction;
 
 mod instructions;
 
 /// CPU state.
-pub struct Cpu<'a> {
+pub struct Cpu {
     /// CPU registers (except for `F` register)
     regs:                Registers,
     /// CPU flags (`F` register)
@@ -20,9 +21,37 @@ pub struct Cpu<'a> {
     /// CPU halted flag
     halted:              bool,
     /// Interconnect to access external ressources (RAM, ROM, peripherals...)
-    inter:               Interconnect<'a>,
+    inter:               Interconnect,
     /// Number of cycles elapsed running the current instruction
     instruction_cycles:  u8,
+    /// Set when `LD B, B` runs. Test ROMs use that instruction as a
+    /// software breakpoint.
+    breakpoint:          bool,
+}
+
+/// Copy of the CPU registers, for debugging and test ROMs
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub struct CpuRegisters {
+    /// Program Counter
+    pub pc: u16,
+    /// Stack Pointer
+    pub sp: u16,
+    /// Accumulator
+    pub a:  u8,
+    /// Flags
+    pub f:  u8,
+    /// `B` register
+    pub b:  u8,
+    /// `C` register
+    pub c:  u8,
+    /// `D` register
+    pub d:  u8,
+    /// `E` register
+    pub e:  u8,
+    /// `H` register
+    pub h:  u8,
+    /// `L` register
+    pub l:  u8,
 }
 
 /// CPU registers. They're 16bit wide but some of them can be accessed
@@ -65,9 +94,9 @@ struct Flags {
     c: bool,
 }
 
-impl<'a> Cpu<'a> {
+impl Cpu {
     /// Create a new Cpu instance and reset it
-    pub fn new<'n>(inter: Interconnect<'n>) -> Cpu<'n> {
+    pub fn new(inter: Interconnect) -> Cpu {
         // Default register values at startup. Taken from the
         // unofficial Game Boy CPU manual.
         let regs = Registers {
@@ -93,6 +122,7 @@ impl<'a> Cpu<'a> {
             iten_enable_next: true,
             halted:           false,
             instruction_cycles: 0,
+            breakpoint:       false,
         }
     }
 
@@ -145,6 +175,42 @@ impl<'a> Cpu<'a> {
         self.instruction_cycles
     }
 
+    /// Return `true` if `LD B, B` ran since the last call
+    pub fn take_breakpoint(&mut self) -> bool {
+        let hit = self.breakpoint;
+
+        self.breakpoint = false;
+
+        hit
+    }
+
+    /// Return a copy of the registers
+    pub fn registers(&self) -> CpuRegisters {
+        CpuRegisters {
+            pc: self.pc(),
+            sp: self.sp(),
+            a:  self.a(),
+            f:  self.f(),
+            b:  self.b(),
+            c:  self.c(),
+            d:  self.d(),
+            e:  self.e(),
+            h:  self.h(),
+            l:  self.l(),
+        }
+    }
+
+    /// Return the interconnect used by this CPU
+    pub fn interconnect(&self) -> &Interconnect {
+        &self.inter
+    }
+
+    /// Return a mutable reference to the interconnect used by this
+    /// CPU
+    pub fn interconnect_mut(&mut self) -> &mut Interconnect {
+        &mut self.inter
+    }
+
     /// Execute interrupt handler for `it`
     fn interrupt(&mut self, it: Interrupt) {
 
@@ -157,6 +223,7 @@ impl<'a> Cpu<'a> {
             Interrupt::VBlank => 0x40,
             Interrupt::Lcdc   => 0x48,
             Interrupt::Timer  => 0x50,
+            Interrupt::Serial => 0x58,
         };
 
         // Push current value to stack
@@ -490,14 +557,54 @@ impl<'a> Cpu<'a> {
         panic!("STOP is not implemented");
     }
 
-    // Partial reset procedure used for benchmarks
-    #[cfg(test)]
+    /// Restart execution at the beginning of the bootrom. The other
+    /// registers are initialized by the bootrom itself.
     pub fn reset(&mut self) {
         self.set_pc(0);
+        self.halted     = false;
+        self.breakpoint = false;
+    }
+
+    /// Serialize the CPU state followed by the rest of the machine
+    pub fn save_state(&self, w: &mut Writer) {
+        w.u16(self.pc());
+        w.u16(self.sp());
+        w.u16(self.af());
+        w.u16(self.bc());
+        w.u16(self.de());
+        w.u16(self.hl());
+        w.bool(self.iten);
+        w.bool(self.iten_enable_next);
+        w.bool(self.halted);
+
+        self.inter.save_state(w);
+    }
+
+    /// Restore a state serialized by `save_state`
+    pub fn load_state(&mut self, r: &mut Reader) -> Result<(), SaveStateError> {
+        let pc = try!(r.u16());
+        let sp = try!(r.u16());
+        let af = try!(r.u16());
+        let bc = try!(r.u16());
+        let de = try!(r.u16());
+        let hl = try!(r.u16());
+
+        self.set_pc(pc);
+        self.set_sp(sp);
+        self.set_af(af);
+        self.set_bc(bc);
+        self.set_de(de);
+        self.set_hl(hl);
+
+        self.iten             = try!(r.bool());
+        self.iten_enable_next = try!(r.bool());
+        self.halted           = try!(r.bool());
+
+        self.inter.load_state(r)
     }
 }
 
-impl<'a> Debug for Cpu<'a> {
+impl Debug for Cpu {
     fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
         try!(writeln!(f, "Registers:"));
 
@@ -0,0 +1,45 @@
+//! CRC-32 (IEEE 802.3), used to validate patches and by the PNG
+//! encoder
+
+/// Compute the CRC-32 of `data`
+pub fn crc32(data: &[u8]) -> u32 {
+    let table = table();
+
+    let crc = data.iter().fold(!0u32, |crc, &b| {
+        let index = ((crc ^ b as u32) & 0xff) as usize;
+
+        table[index] ^ (crc >> 8)
+    });
+
+    !crc
+}
+
+/// Build the lookup table for the reversed polynomial 0xedb88320
+fn table() -> [u32; 256] {
+    let mut table = [0; 256];
+
+    for (n, entry) in table.iter_mut().enumerate() {
+        let mut c = n as u32;
+
+        for _ in 0..8 {
+            c = if c & 1 != 0 {
+                0xedb88320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+        }
+
+        *entry = c;
+    }
+
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn crc32() {
+        assert!(super::crc32(b"") == 0);
+        assert!(super::crc32(b"123456789") == 0xcbf43926);
+    }
+}
@@ -1,14 +1,12 @@
 //! Game Boy GPU emulation
 
-use ui::Display;
 use gpu::sprite::Sprite;
+use savestate::{Writer, Reader, SaveStateError};
 
 mod sprite;
 
 /// GPU state.
-pub struct Gpu<'a> {
-    /// Emulator Display
-    display: &'a mut (Display + 'a),
+pub struct Gpu {
     /// Current line. [0,143] is active video, [144,153] is blanking.
     line: u8,
     /// Counter for the horizontal period
@@ -69,6 +67,10 @@ pub struct Gpu<'a> {
     /// None. There can't be more than 10 sprites displayed on each
     /// line.
     line_cache: [[Option<u8>; 10]; 144],
+    /// Picture being drawn
+    back: Frame,
+    /// Last complete picture
+    frame: Frame,
 }
 
 /// Current GPU mode
@@ -114,16 +116,15 @@ enum LcdItStatus {
     Acked,
 }
 
-impl<'a> Gpu<'a> {
+impl Gpu {
     /// Create a new Gpu instance.
-    pub fn new<'n>(display: &'n mut Display) -> Gpu<'n> {
+    pub fn new() -> Gpu {
 
         Gpu { line:                   0,
               htick:                  0,
               mode:                   Mode::Prelude,
               oam:                    [Sprite::new(); 40],
               vram:                   [0xca; 0x2000],
-              display:                display,
               enabled:                false,
               window_tile_map:        TileMap::Low,
               window_enabled:         false,
@@ -147,17 +148,24 @@ impl<'a> Gpu<'a> {
               wx:                     0,
               wy:                     0,
               line_cache:             [[None; 10]; 144],
+              back:                   [Color::White;
+                                       SCREEN_WIDTH * SCREEN_HEIGHT],
+              frame:                  [Color::White;
+                                       SCREEN_WIDTH * SCREEN_HEIGHT],
         }
     }
 
     /// Called at each tick of the system clock. Move the emulated
-    /// state one step forward.
-    pub fn step(&mut self) {
+    /// state one step forward. Returns `true` when a frame has just
+    /// been completed and flipped.
+    pub fn step(&mut self) -> bool {
 
         if !self.enabled {
-            return;
+            return false;
         }
 
+        let mut frame_done = false;
+
         self.htick = (self.htick + 1) % timings::HTOTAL;
 
         let cur_mode = self.mode;
@@ -184,7 +192,8 @@ impl<'a> Gpu<'a> {
                             // We're entering vertical blanking, we're
                             // done drawing the current frame
                             self.it_vblank = true;
-                            self.display.flip();
+                            self.frame = self.back;
+                            frame_done = true;
                             Mode::VBlank
                         } else {
                             Mode::Prelude
@@ -218,6 +227,116 @@ impl<'a> Gpu<'a> {
             }
         }
         self.update_ldc_interrupt();
+
+        frame_done
+    }
+
+    /// Return the last complete picture, row by row
+    pub fn frame(&self) -> &Frame {
+        &self.frame
+    }
+
+    /// Serialize the GPU state
+    pub fn save_state(&self, w: &mut Writer) {
+        w.u8(self.lcdc());
+        w.u8(self.line);
+        w.u16(self.htick);
+        w.u8(self.mode as u8);
+
+        let oam: Vec<u8> = (0..0xa0).map(|addr| self.oam(addr)).collect();
+
+        w.bytes(&oam);
+        w.bytes(&self.vram);
+
+        w.u8(self.bgp.into_reg());
+        w.u8(self.obp0.into_reg());
+        w.u8(self.obp1.into_reg());
+        w.u8(self.lyc);
+        w.bool(self.it_vblank);
+        w.bool(self.iten_lyc);
+        w.bool(self.iten_prelude);
+        w.bool(self.iten_vblank);
+        w.bool(self.iten_hblank);
+        w.u8(match self.lcd_it_status {
+            LcdItStatus::Inactive  => 0,
+            LcdItStatus::Triggered => 1,
+            LcdItStatus::Acked     => 2,
+        });
+        w.u8(self.scy);
+        w.u8(self.scx);
+        w.u8(self.wx);
+        w.u8(self.wy);
+
+        // Both pictures are saved so that the frame in progress and
+        // the one currently displayed are restored exactly
+        let back: Vec<u8> = self.back.iter().map(|&c| c as u8).collect();
+        let frame: Vec<u8> = self.frame.iter().map(|&c| c as u8).collect();
+
+        w.bytes(&back);
+        w.bytes(&frame);
+    }
+
+    /// Restore a state serialized by `save_state`
+    pub fn load_state(&mut self, r: &mut Reader) -> Result<(), SaveStateError> {
+        // This resets the position if the LCD is disabled so it must
+        // be restored first
+        self.set_lcdc(try!(r.u8()));
+
+        self.line  = try!(r.u8());
+        self.htick = try!(r.u16());
+        self.mode  = match try!(r.u8()) {
+            0 => Mode::HBlank,
+            1 => Mode::VBlank,
+            2 => Mode::Prelude,
+            3 => Mode::Active,
+            _ => return Err(SaveStateError::Invalid("bad GPU mode")),
+        };
+
+        if self.line >= timings::VTOTAL || self.htick >= timings::HTOTAL {
+            return Err(SaveStateError::Invalid("bad GPU position"));
+        }
+
+        let oam = try!(r.bytes());
+
+        if oam.len() != 0xa0 {
+            return Err(SaveStateError::Invalid("bad OAM size"));
+        }
+
+        for (sprite, attrs) in self.oam.iter_mut().zip(oam.chunks(4)) {
+            sprite.set_y_pos(attrs[0]);
+            sprite.set_x_pos(attrs[1]);
+            sprite.set_tile(attrs[2]);
+            sprite.set_flags(attrs[3]);
+        }
+
+        try!(r.bytes_into(&mut self.vram));
+
+        self.bgp           = Palette::from_reg(try!(r.u8()));
+        self.obp0          = Palette::from_reg(try!(r.u8()));
+        self.obp1          = Palette::from_reg(try!(r.u8()));
+        self.lyc           = try!(r.u8());
+        self.it_vblank     = try!(r.bool());
+        self.iten_lyc      = try!(r.bool());
+        self.iten_prelude  = try!(r.bool());
+        self.iten_vblank   = try!(r.bool());
+        self.iten_hblank   = try!(r.bool());
+        self.lcd_it_status = match try!(r.u8()) {
+            0 => LcdItStatus::Inactive,
+            1 => LcdItStatus::Triggered,
+            2 => LcdItStatus::Acked,
+            _ => return Err(SaveStateError::Invalid("bad LCD IT status")),
+        };
+        self.scy = try!(r.u8());
+        self.scx = try!(r.u8());
+        self.wx  = try!(r.u8());
+        self.wy  = try!(r.u8());
+
+        try!(load_frame(r, &mut self.back));
+        try!(load_frame(r, &mut self.frame));
+
+        self.rebuild_line_cache();
+
+        Ok(())
     }
 
     /// Return current GPU mode
@@ -750,7 +869,7 @@ impl<'a> Gpu<'a> {
             bg_col.color
         };
 
-        self.display.set_pixel(x as u32, y as u32, col);
+        self.back[y as usize * SCREEN_WIDTH + x as usize] = col;
     }
 
     fn render_sprite(&self, x: u8, y: u8, bg_col: AlphaColor) -> Color {
@@ -827,12 +946,44 @@ impl<'a> Gpu<'a> {
 
 }
 
+/// Width of the screen in pixels
+pub const SCREEN_WIDTH:  usize = 160;
+/// Height of the screen in pixels
+pub const SCREEN_HEIGHT: usize = 144;
+
+/// A complete picture, `SCREEN_WIDTH` x `SCREEN_HEIGHT` pixels row by
+/// row
+pub type Frame = [Color; SCREEN_WIDTH * SCREEN_HEIGHT];
+
+/// Restore a picture saved as one byte per pixel
+fn load_frame(r: &mut Reader, frame: &mut Frame) -> Result<(), SaveStateError> {
+    let pixels = try!(r.bytes());
+
+    if pixels.len() != frame.len() {
+        return Err(SaveStateError::Invalid("bad frame size"));
+    }
+
+    for (p, &c) in frame.iter_mut().zip(pixels) {
+        if c > 3 {
+            return Err(SaveStateError::Invalid("bad color"));
+        }
+
+        *p = Color::from_u8(c);
+    }
+
+    Ok(())
+}
+
 /// All possible color values on the original game boy
-#[derive(Clone,Copy,PartialEq,Eq)]
+#[derive(Clone,Copy,PartialEq,Eq,Debug)]
 pub enum Color {
+    /// Lightest shade
     White     = 0,
+    /// Light grey
     LightGrey = 1,
+    /// Dark grey
     DarkGrey  = 2,
+    /// Darkest shade
     Black     = 3,
 }
 
@@ -1014,8 +1165,7 @@ mod tests {
     /// of steps. The point is to help spot regressions.
     #[test]
     fn gpu_step() {
-        let mut d = ::ui::dummy::DummyDisplay;
-        let mut gpu = super::Gpu::new(&mut d);
+        let mut gpu = super::Gpu::new();
 
         for _ in 0..1000 {
             gpu.step();
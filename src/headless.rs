@@ -0,0 +1,205 @@
+//! gb-headless: run a ROM without any window or audio device, for
+//! automated testing. The emulator runs as fast as possible for a
+//! number of frames or until the ROM prints something on the serial
+//! link, then the last picture and the serial output can be dumped.
+
+extern crate gb_rs;
+extern crate getopts;
+
+use std::path::Path;
+use std::fs::File;
+use std::io::Write;
+use getopts::{Options, Matches};
+
+use gb_rs::{GameBoy, cartridge, movie, ui};
+use gb_rs::ui::Display;
+use gb_rs::ui::dummy::{DummyDisplay, DummyController};
+
+fn main() {
+    let argv: Vec<_> = std::env::args().collect();
+
+    let mut opts = Options::new();
+
+    opts.optopt("f", "frames",
+                "stop after N frames (default 3600, one minute of \
+                 emulated time)", "N");
+    opts.optmulti("u", "until-serial",
+                  "stop as soon as TEXT is sent on the serial link \
+                   (can be given several times)", "TEXT");
+    opts.optopt("i", "input",
+                "press buttons according to SCRIPT, for instance \
+                 '60:start,65:,200:a+right'", "SCRIPT");
+    opts.optopt("", "png", "write the last picture to FILE", "FILE");
+    opts.optopt("", "serial",
+                "write the serial output to FILE ('-' for stdout)", "FILE");
+    opts.optopt("", "expect-hash",
+                "fail if the hash of the last picture isn't HASH", "HASH");
+    opts.optflag("h", "help", "print this help");
+
+    let matches = match opts.parse(&argv[1..]) {
+        Ok(m)  => m,
+        Err(e) => {
+            println!("{}", e);
+            print_usage(&argv[0], &opts);
+            std::process::exit(1);
+        }
+    };
+
+    if matches.opt_present("help") || matches.free.len() != 1 {
+        print_usage(&argv[0], &opts);
+        return;
+    }
+
+    let rompath = Path::new(&matches.free[0]);
+
+    let frames = parse_number(&matches, "frames").unwrap_or(3600);
+
+    let expected_hash = matches.opt_str("expect-hash").map(|h| {
+        match u64::from_str_radix(h.trim_start_matches("0x"), 16) {
+            Ok(h)  => h,
+            Err(e) => {
+                println!("Invalid hash '{}': {}", h, e);
+                std::process::exit(1);
+            }
+        }
+    });
+
+    let until = matches.opt_strs("until-serial");
+
+    let controller =
+        match matches.opt_str("input") {
+            Some(script) => match DummyController::from_script(&script) {
+                Ok(c)  => c,
+                Err(e) => {
+                    println!("Invalid input script: {}", e);
+                    std::process::exit(1);
+                }
+            },
+            None => DummyController::new(),
+        };
+
+    let mut options = cartridge::LoadOptions::new();
+
+    // Always start from blank RAM and never leave a save file behind
+    options.no_save = true;
+
+    let cart = match cartridge::Cartridge::from_path_with_options(&rompath,
+                                                                  &options) {
+        Ok(c)  => c,
+        Err(e) => {
+            println!("Failed to load ROM {}: {}", rompath.display(), e);
+            std::process::exit(1);
+        }
+    };
+
+    let mut gameboy = GameBoy::new(cart);
+
+    let mut display = DummyDisplay::new();
+
+    let mut serial = Vec::new();
+    let mut found  = None;
+
+    for frame in 0..frames {
+        controller.update(frame);
+
+        gameboy.set_buttons(controller.buttons().get());
+        gameboy.set_tilt(controller.tilt().get());
+
+        gameboy.run_frame();
+
+        display.flip(gameboy.framebuffer());
+
+        // Nobody is listening, don't let the sound pile up
+        while let Some(_) = gameboy.next_audio_buffer() {
+        }
+
+        serial.extend(gameboy.take_serial_output());
+
+        found = until.iter().find(|t| contains(&serial, t.as_bytes()));
+
+        if found.is_some() {
+            break;
+        }
+    }
+
+    println!("Ran {} frames", display.flips());
+
+    if let Some(path) = matches.opt_str("png") {
+        let result = File::create(&path).and_then(|mut f| {
+            ui::png::write_frame(&mut f, display.frame())
+        });
+
+        if let Err(e) = result {
+            println!("Can't write {}: {}", path, e);
+            std::process::exit(1);
+        }
+    }
+
+    match matches.opt_str("serial") {
+        Some(ref path) if path == "-" => {
+            println!("Serial output:");
+            println!("{}", String::from_utf8_lossy(&serial));
+        }
+        Some(path) => {
+            let result = File::create(&path).and_then(|mut f| {
+                f.write_all(&serial)
+            });
+
+            if let Err(e) = result {
+                println!("Can't write {}: {}", path, e);
+                std::process::exit(1);
+            }
+        }
+        None => (),
+    }
+
+    let hash = movie::frame_hash(gameboy.framebuffer());
+
+    println!("Frame hash: {:016x}", hash);
+
+    let mut ok = true;
+
+    if !until.is_empty() {
+        match found {
+            Some(text) => println!("Found '{}' on the serial link", text),
+            None       => {
+                println!("Serial output condition not met");
+                ok = false;
+            }
+        }
+    }
+
+    if let Some(expected) = expected_hash {
+        if expected != hash {
+            println!("Frame hash mismatch: expected {:016x}", expected);
+            ok = false;
+        }
+    }
+
+    if !ok {
+        std::process::exit(1);
+    }
+}
+
+/// Return `true` if `needle` appears in `haystack`
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    needle.is_empty() || haystack.windows(needle.len()).any(|w| w == needle)
+}
+
+fn parse_number(matches: &Matches, name: &str) -> Option<u32> {
+    matches.opt_str(name).map(|v| {
+        match v.parse() {
+            Ok(n)  => n,
+            Err(e) => {
+                println!("Invalid value for --{} '{}': {}", name, v, e);
+                std::process::exit(1);
+            }
+        }
+    })
+}
+
+fn print_usage(program: &str, opts: &Options) {
+    let brief = format!("Usage: {} [options] <rom-file>", program);
+
+    print!("{}", opts.usage(&brief));
+}
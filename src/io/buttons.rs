@@ -6,31 +6,35 @@
 //! one of those two lines with a pin of the gameboy and sets one bit
 //! in the INPUT register (if the line is selected).
 
+use savestate::{Writer, Reader, SaveStateError};
 
-use std::cell::Cell;
-
-pub struct Buttons<'a> {
+pub struct Buttons {
     /// `true` if the "directions" line is active
     directions_selected: bool,
     /// `true` if the "buttons" line is active
     /// Controller interface
     buttons_selected:    bool,
-    /// Abstract interface to the actual UI
-    buttons:             &'a Cell<::ui::Buttons>,
+    /// State of the buttons, updated by the frontend
+    buttons:             ::ui::Buttons,
 }
 
-impl<'a> Buttons<'a> {
-    pub fn new<'n>(buttons: &'n Cell<::ui::Buttons>) -> Buttons<'n> {
+impl Buttons {
+    pub fn new() -> Buttons {
         Buttons { directions_selected: false,
                   buttons_selected:    false,
-                  buttons:             buttons,
+                  buttons:             ::ui::Buttons::new(::ui::ButtonState::Up),
         }
     }
 
+    /// Update the state of the buttons
+    pub fn set_buttons(&mut self, buttons: ::ui::Buttons) {
+        self.buttons = buttons;
+    }
+
     /// Return the value of the INPUT register. Lines are to 1 when
     /// inactive.
     pub fn input(&self) -> u8 {
-        let buttons = self.buttons.get();
+        let buttons = self.buttons;
 
         // For simplicity we'll mark the active lines with 1 and
         // invert the value at the end
@@ -63,4 +67,18 @@ impl<'a> Buttons<'a> {
         self.directions_selected = val & 0x10 == 0;
         self.buttons_selected    = val & 0x20 == 0;
     }
+
+    /// Only the line selection is saved, the state of the buttons
+    /// is up to the frontend
+    pub fn save_state(&self, w: &mut Writer) {
+        w.bool(self.directions_selected);
+        w.bool(self.buttons_selected);
+    }
+
+    pub fn load_state(&mut self, r: &mut Reader) -> Result<(), SaveStateError> {
+        self.directions_selected = try!(r.bool());
+        self.buttons_selected    = try!(r.bool());
+
+        Ok(())
+    }
 }
@@ -3,20 +3,22 @@
 use gpu::Gpu;
 use spu::Spu;
 
-use std::cell::Cell;
 use cartridge::Cartridge;
+use cheats::Cheats;
+use savestate::{Writer, Reader, SaveStateError};
 
 use self::io_map::{NR3_RAM_START, NR3_RAM_END};
 
 pub mod ram;
 pub mod timer;
+pub mod serial;
 pub mod buttons;
 
 mod bootrom;
 
 /// Interconnect struct used by the CPU and GPU to access the ROM, RAM
 /// and registers
-pub struct Interconnect<'a> {
+pub struct Interconnect {
     /// Cartridge interface
     cartridge:  Cartridge,
     /// internal RAM
@@ -25,8 +27,10 @@ pub struct Interconnect<'a> {
     zpage:      ram::Ram,
     /// Timer instance
     timer:      timer::Timer,
+    /// Serial link
+    serial:     serial::Serial,
     /// GPU instance
-    gpu:        Gpu<'a>,
+    gpu:        Gpu,
     /// SPU instance
     spu:        Spu,
     /// Enabled interrupts
@@ -36,19 +40,22 @@ pub struct Interconnect<'a> {
     /// Current DMA index in OAM
     dma_idx:    u16,
     /// Controller interface
-    buttons:    buttons::Buttons<'a>,
+    buttons:    buttons::Buttons,
+    /// Console tilt, forwarded to cartridges with an accelerometer
+    tilt:       ::ui::Tilt,
+    /// Game Genie and GameShark codes
+    cheats:     Cheats,
     /// The game boy starts up mapping the bootrom at address [0,
     /// 0xff]. The last thing the bootrom does is writing 0x01 to
     /// UNMAP_BOOTROM to remove itself from the memory map.
     bootrom:    bool,
 }
 
-impl<'a> Interconnect<'a> {
+impl Interconnect {
     /// Create a new Interconnect
-    pub fn new<'n>(cartridge:  Cartridge,
-                   gpu:        Gpu<'n>,
-                   spu:        Spu,
-                   buttons:    &'n Cell<::ui::Buttons>) -> Interconnect<'n> {
+    pub fn new(cartridge:  Cartridge,
+               gpu:        Gpu,
+               spu:        Spu) -> Interconnect {
 
         let iram = ram::Ram::new(0x2000);
         let zpage = ram::Ram::new(0x7f);
@@ -57,27 +64,37 @@ impl<'a> Interconnect<'a> {
 
         let it_enabled = Interrupts::from_register(0);
 
-        let buttons = buttons::Buttons::new(buttons);
+        let buttons = buttons::Buttons::new();
 
         Interconnect { cartridge:  cartridge,
                        iram:       iram,
                        zpage:      zpage,
                        timer:      timer,
+                       serial:     serial::Serial::new(),
                        gpu:        gpu,
                        spu:        spu,
                        it_enabled: it_enabled,
                        dma_src:    0,
                        dma_idx:    map::range_size(map::OAM),
                        buttons:    buttons,
+                       tilt:       ::ui::Tilt::new(),
+                       cheats:     Cheats::new(),
                        bootrom:    true,
         }
     }
 
     pub fn step(&mut self) {
-        self.gpu.step();
+        if self.gpu.step() {
+            // GameShark codes are applied once per frame when the
+            // GPU enters VBlank
+            self.apply_ram_cheats();
+        }
+
         self.spu.step();
         self.dma_step();
         self.timer.step();
+        self.serial.step();
+        self.cartridge.step();
     }
 
     pub fn dma_step(&mut self) {
@@ -104,7 +121,9 @@ impl<'a> Interconnect<'a> {
                 return bootrom::BOOTROM[off as usize];
             }
 
-            return self.cartridge.rom_byte(off);
+            let b = self.cartridge.rom_byte(off);
+
+            return self.cheats.rom_byte(off, b);
         }
 
         if let Some(off) = map::in_range(addr, map::VRAM) {
@@ -154,6 +173,10 @@ impl<'a> Interconnect<'a> {
         }
 
         if let Some(off) = map::in_range(addr, map::RAM_BANK) {
+            // Sample the tilt in case the write latches the
+            // accelerometer
+            self.cartridge.set_tilt(self.tilt);
+
             return self.cartridge.set_ram_byte(off, val);
         }
 
@@ -192,6 +215,123 @@ impl<'a> Interconnect<'a> {
         debug!("Write to unmapped memory {:04x}: {:02x}", addr, val);
     }
 
+    /// Write the active GameShark codes to RAM
+    fn apply_ram_cheats(&mut self) {
+        for i in 0..self.cheats.ram_writes().len() {
+            let (addr, val) = self.cheats.ram_writes()[i];
+
+            self.store_byte(addr, val);
+        }
+    }
+
+    /// Replace the set of cheats applied to the game
+    pub fn set_cheats(&mut self, cheats: Cheats) {
+        self.cheats = cheats;
+    }
+
+    /// Access the cheats, for instance to toggle them
+    pub fn cheats_mut(&mut self) -> &mut Cheats {
+        &mut self.cheats
+    }
+
+    /// Return the cartridge
+    pub fn cartridge(&self) -> &Cartridge {
+        &self.cartridge
+    }
+
+    /// Return the cartridge
+    pub fn cartridge_mut(&mut self) -> &mut Cartridge {
+        &mut self.cartridge
+    }
+
+    /// Insert a new cartridge and return the previous one. The
+    /// cheats are removed since they're specific to a game. This
+    /// should be followed by a `reset`.
+    pub fn set_cartridge(&mut self, cartridge: Cartridge) -> Cartridge {
+        self.cheats = Cheats::new();
+
+        ::std::mem::replace(&mut self.cartridge, cartridge)
+    }
+
+    /// Power cycle the console: clear the RAM, switch off the LCD and
+    /// sound and map the bootrom back. The bootrom will reinitialize
+    /// the rest of the hardware.
+    pub fn reset(&mut self) {
+        self.iram       = ram::Ram::new(0x2000);
+        self.zpage      = ram::Ram::new(0x7f);
+        self.timer      = timer::Timer::new();
+        self.serial     = serial::Serial::new();
+        self.it_enabled = Interrupts::from_register(0);
+        self.dma_idx    = map::range_size(map::OAM);
+        self.bootrom    = true;
+
+        self.gpu.set_lcdc(0);
+        self.gpu.force_it_vblank(false);
+        self.gpu.force_it_lcd(false);
+        self.spu.set_nr52(0);
+    }
+
+    /// Serialize the state of the machine. The cheats aren't part
+    /// of it.
+    pub fn save_state(&self, w: &mut Writer) {
+        self.iram.save_state(w);
+        self.zpage.save_state(w);
+        self.timer.save_state(w);
+        self.serial.save_state(w);
+        w.u8(self.it_enabled.as_register());
+        w.u16(self.dma_src);
+        w.u16(self.dma_idx);
+        self.buttons.save_state(w);
+        w.bool(self.bootrom);
+
+        self.gpu.save_state(w);
+        self.spu.save_state(w);
+        self.cartridge.save_state(w);
+    }
+
+    /// Restore a state serialized by `save_state`
+    pub fn load_state(&mut self, r: &mut Reader) -> Result<(), SaveStateError> {
+        try!(self.iram.load_state(r));
+        try!(self.zpage.load_state(r));
+        try!(self.timer.load_state(r));
+        try!(self.serial.load_state(r));
+        self.it_enabled = Interrupts::from_register(try!(r.u8()));
+        self.dma_src    = try!(r.u16());
+        self.dma_idx    = try!(r.u16());
+        try!(self.buttons.load_state(r));
+        self.bootrom    = try!(r.bool());
+
+        try!(self.gpu.load_state(r));
+        try!(self.spu.load_state(r));
+        self.cartridge.load_state(r)
+    }
+
+    /// Update the state of the buttons
+    pub fn set_buttons(&mut self, buttons: ::ui::Buttons) {
+        self.buttons.set_buttons(buttons);
+    }
+
+    /// Update the tilt of the console
+    pub fn set_tilt(&mut self, tilt: ::ui::Tilt) {
+        self.tilt = tilt;
+    }
+
+    /// Return the last picture rendered by the GPU
+    pub fn frame(&self) -> &::gpu::Frame {
+        self.gpu.frame()
+    }
+
+    /// Return `true` if the cartridge's rumble motor is running
+    pub fn rumble(&self) -> bool {
+        self.cartridge.rumble()
+    }
+
+    /// Return the bytes sent through the serial link since the last
+    /// call
+    pub fn take_serial_output(&mut self) -> Vec<u8> {
+        self.serial.take_output()
+    }
+
     /// Return the highest priority active interrupt after
     /// acknowledging it. If no interrupt is pending return `None`.
     pub fn next_interrupt_ack(&mut self) -> Option<Interrupt> {
@@ -204,6 +344,9 @@ impl<'a> Interconnect<'a> {
         } else if self.it_enabled.timer && self.timer.interrupt() {
             self.timer.ack_interrupt();
             Some(Interrupt::Timer)
+        } else if self.it_enabled.serial && self.serial.interrupt() {
+            self.serial.ack_interrupt();
+            Some(Interrupt::Serial)
         } else {
             None
         }
@@ -218,6 +361,8 @@ impl<'a> Interconnect<'a> {
             Some(Interrupt::Lcdc)
         } else if self.it_enabled.timer && self.timer.interrupt() {
             Some(Interrupt::Timer)
+        } else if self.it_enabled.serial && self.serial.interrupt() {
+            Some(Interrupt::Serial)
         } else {
             None
         }
@@ -229,14 +374,8 @@ impl<'a> Interconnect<'a> {
             // Controller input
             io_map::INPUT    => self.buttons.input(),
             // Serial link
-            io_map::SB       => {
-                debug!("unhandled read from serial data");
-                0
-            }
-            io_map::SC       => {
-                debug!("unhandled read from serial control");
-                0
-            }
+            io_map::SB       => self.serial.data(),
+            io_map::SC       => self.serial.control(),
             // Timers
             io_map::DIV      => self.timer.div(),
             io_map::TIMA     => self.timer.counter(),
@@ -250,7 +389,7 @@ impl<'a> Interconnect<'a> {
                     vblank: self.gpu.it_vblank(),
                     lcdc:   self.gpu.it_lcd(),
                     timer:  self.timer.interrupt(),
-                    serial: false,
+                    serial: self.serial.interrupt(),
                     button: false,
                 }.as_register(),
             // SPU registers
@@ -307,11 +446,9 @@ impl<'a> Interconnect<'a> {
         match addr {
             // Controller input
             io_map::INPUT    => self.buttons.set_input(val),
-            // Seral link
-            io_map::SB       =>
-                debug!("unhandled write to serial data: 0x{:02x}", val),
-            io_map::SC       =>
-                debug!("unhandled write to serial control: 0x{:02x}", val),
+            // Serial link
+            io_map::SB       => self.serial.set_data(val),
+            io_map::SC       => self.serial.set_control(val),
             // Timers
             io_map::DIV      => self.timer.reset_div(),
             io_map::TIMA     => self.timer.set_counter(val),
@@ -328,6 +465,7 @@ impl<'a> Interconnect<'a> {
                 self.gpu.force_it_vblank(f.vblank);
                 self.gpu.force_it_lcd(f.lcdc);
                 self.timer.force_interrupt(f.timer);
+                self.serial.force_interrupt(f.serial);
             }
             // SPU registers
             io_map::NR10     => self.spu.set_nr10(val),
@@ -399,7 +537,9 @@ pub enum Interrupt {
     Lcdc,
     /// Timer overflow
     Timer,
-    // TODO: implement other interrupts
+    /// Serial transfer done
+    Serial,
+    // TODO: implement joypad interrupt
 }
 
 /// GB Interrupts, from highest to lowest priority
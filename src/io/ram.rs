@@ -2,6 +2,8 @@
 
 use std::iter;
 
+use savestate::{Writer, Reader, SaveStateError};
+
 /// RAM image
 pub struct Ram {
     data: Vec<u8>,
@@ -23,4 +25,12 @@ impl Ram {
     pub fn set_byte(&mut self, offset: u16, val: u8) {
         self.data[offset as usize] = val;
     }
+
+    pub fn save_state(&self, w: &mut Writer) {
+        w.bytes(&self.data);
+    }
+
+    pub fn load_state(&mut self, r: &mut Reader) -> Result<(), SaveStateError> {
+        r.bytes_into(&mut self.data)
+    }
 }
@@ -0,0 +1,201 @@
+//! Serial link emulation. There's never anything plugged into the
+//! link port: the bytes sent by the game are kept in an output
+//! buffer (test ROMs use it to report their results) and the bytes
+//! received are always 0xff.
+
+use std::collections::VecDeque;
+
+use savestate::{Writer, Reader, SaveStateError};
+
+/// Serial port state
+pub struct Serial {
+    /// Data register, shifted out MSB first during a transfer
+    data:      u8,
+    /// True while a transfer is in progress
+    transfer:  bool,
+    /// True if we generate the clock, otherwise we wait for the
+    /// other end to clock the transfer (which never happens)
+    internal:  bool,
+    /// Number of sysclk cycles left before the end of the current
+    /// transfer
+    remaining: u16,
+    /// True if interrupt is pending
+    interrupt: bool,
+    /// Bytes sent since the output was last taken
+    output:    VecDeque<u8>,
+}
+
+impl Serial {
+    pub fn new() -> Serial {
+        Serial {
+            data:      0,
+            transfer:  false,
+            internal:  false,
+            remaining: TRANSFER_CYCLES,
+            interrupt: false,
+            output:    VecDeque::new(),
+        }
+    }
+
+    pub fn step(&mut self) {
+        if !self.transfer || !self.internal {
+            return;
+        }
+
+        self.remaining -= 1;
+
+        if self.remaining == 0 {
+            let sent = self.data;
+
+            // Nobody's listening, we shift in 1s
+            self.push_output(sent);
+
+            self.data      = 0xff;
+            self.transfer  = false;
+            self.interrupt = true;
+        }
+    }
+
+    /// Return the value of the SB register
+    pub fn data(&self) -> u8 {
+        self.data
+    }
+
+    /// Set the value of the SB register
+    pub fn set_data(&mut self, data: u8) {
+        self.data = data;
+    }
+
+    /// Return the value of the SC register
+    pub fn control(&self) -> u8 {
+        let mut r = 0x7e;
+
+        r |= (self.transfer as u8) << 7;
+        r |= self.internal as u8;
+
+        r
+    }
+
+    /// Set the value of the SC register, starts a transfer if bit 7
+    /// is set
+    pub fn set_control(&mut self, ctrl: u8) {
+        self.transfer  = ctrl & 0x80 != 0;
+        self.internal  = ctrl & 1 != 0;
+        self.remaining = TRANSFER_CYCLES;
+    }
+
+    /// Return interrupt status
+    pub fn interrupt(&self) -> bool {
+        self.interrupt
+    }
+
+    /// Acknowledge interrupt
+    pub fn ack_interrupt(&mut self) {
+        self.interrupt = false;
+    }
+
+    /// Force interrupt state
+    pub fn force_interrupt(&mut self, set: bool) {
+        self.interrupt = set;
+    }
+
+    /// Return the bytes sent since the last call and empty the
+    /// output buffer
+    pub fn take_output(&mut self) -> Vec<u8> {
+        let output = ::std::mem::replace(&mut self.output, VecDeque::new());
+
+        output.into_iter().collect()
+    }
+
+    fn push_output(&mut self, b: u8) {
+        // Don't let the buffer grow forever if the frontend doesn't
+        // care about the output
+        if self.output.len() >= OUTPUT_MAX {
+            self.output.pop_front();
+        }
+
+        self.output.push_back(b);
+    }
+
+    /// The output buffer isn't part of the state, it belongs to
+    /// whoever reads it
+    pub fn save_state(&self, w: &mut Writer) {
+        w.u8(self.data);
+        w.bool(self.transfer);
+        w.bool(self.internal);
+        w.u16(self.remaining);
+        w.bool(self.interrupt);
+    }
+
+    pub fn load_state(&mut self, r: &mut Reader) -> Result<(), SaveStateError> {
+        self.data      = try!(r.u8());
+        self.transfer  = try!(r.bool());
+        self.internal  = try!(r.bool());
+        self.remaining = try!(r.u16());
+        self.interrupt = try!(r.bool());
+
+        // The counter stays at 0 once a transfer is done
+        if self.remaining > TRANSFER_CYCLES ||
+            (self.transfer && self.remaining == 0) {
+            return Err(SaveStateError::Invalid("bad serial transfer counter"));
+        }
+
+        Ok(())
+    }
+}
+
+/// Duration of a transfer using the internal 8192Hz clock: 8 bits at
+/// 512 sysclk cycles per bit
+const TRANSFER_CYCLES: u16 = 8 * 512;
+
+/// Maximum number of bytes kept in the output buffer
+const OUTPUT_MAX: usize = 0x10000;
+
+#[cfg(test)]
+mod tests {
+    use super::{Serial, TRANSFER_CYCLES};
+    use savestate::{Writer, Reader};
+
+    #[test]
+    fn transfer() {
+        let mut serial = Serial::new();
+
+        serial.set_data(b'A');
+        serial.set_control(0x81);
+
+        for _ in 1..TRANSFER_CYCLES {
+            serial.step();
+        }
+
+        assert!(serial.control() & 0x80 != 0);
+        assert!(!serial.interrupt());
+
+        serial.step();
+
+        assert!(serial.control() & 0x80 == 0);
+        assert!(serial.interrupt());
+        assert!(serial.data() == 0xff);
+        assert!(serial.take_output() == b"A");
+        assert!(serial.take_output().is_empty());
+
+        // The state is still valid once the transfer is over
+        let mut w = Writer::new();
+
+        serial.save_state(&mut w);
+
+        let state = w.into_bytes();
+
+        assert!(Serial::new().load_state(&mut Reader::new(&state)).is_ok());
+
+        // External clock: nothing happens
+        serial.set_data(b'B');
+        serial.set_control(0x80);
+
+        for _ in 0..TRANSFER_CYCLES * 2 {
+            serial.step();
+        }
+
+        assert!(serial.control() & 0x80 != 0);
+        assert!(serial.take_output().is_empty());
+    }
+}
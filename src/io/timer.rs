@@ -1,5 +1,7 @@
 //! Timer emulation
 
+use savestate::{Writer, Reader, SaveStateError};
+
 /// Timer state
 pub struct Timer {
     /// Timer counter. Generates an interrupt on overflow.
@@ -119,6 +121,34 @@ impl Timer {
     pub fn force_interrupt(&mut self, set: bool) {
         self.interrupt = set;
     }
+
+    pub fn save_state(&self, w: &mut Writer) {
+        w.u8(self.counter);
+        w.u8(self.modulo);
+        w.bool(self.enabled);
+        w.u8(self.divider as u8);
+        w.u32(self.counter_16k);
+        w.bool(self.interrupt);
+    }
+
+    pub fn load_state(&mut self, r: &mut Reader) -> Result<(), SaveStateError> {
+        self.counter = try!(r.u8());
+        self.modulo  = try!(r.u8());
+        self.enabled = try!(r.bool());
+
+        self.divider = match try!(r.u8()) {
+            4  => Divider::Div16,
+            6  => Divider::Div64,
+            8  => Divider::Div256,
+            10 => Divider::Div1024,
+            _  => return Err(SaveStateError::Invalid("bad timer divider")),
+        };
+
+        self.counter_16k = try!(r.u32());
+        self.interrupt   = try!(r.bool());
+
+        Ok(())
+    }
 }
 
 /// Possible divider values usable as timer clock source.
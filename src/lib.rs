@@ -0,0 +1,471 @@
+//! gb-rs: Game Boy emulator
+//! Ressources:
+//!
+//! Opcode map: http://www.pastraiser.com/cpu/gameboy/gameboy_opcodes.html
+//! JS emulator: http://imrannazar.com/GameBoy-Emulation-in-JavaScript:-The-CPU
+//! Lots of info about GC quircks: http://www.devrs.com/gb/files/faqs.html
+//! Accuracy tests: http://tasvideos.org/EmulatorResources/GBAccuracyTests.html
+//!
+//! The emulator core is exposed through the `GameBoy` type. It owns
+//! all of its state: the frontends push the input and pull the
+//! frames and sound samples, so a `GameBoy` can be stored anywhere
+//! or sent to a worker thread.
+
+#![cfg_attr(test, feature(test))]
+
+#![warn(missing_docs)]
+
+#[macro_use]
+extern crate log;
+#[cfg(feature = "sdl2_frontend")]
+extern crate sdl2;
+#[cfg(feature = "sdl2_frontend")]
+extern crate sdl2_sys;
+#[cfg(feature = "sdl2_frontend")]
+extern crate num;
+extern crate ascii;
+extern crate time;
+extern crate flate2;
+extern crate zip;
+
+#[cfg(test)]
+extern crate test;
+
+use std::sync::mpsc::Receiver;
+use std::path::Path;
+use std::fs::{File, rename};
+use std::io::{Read, Write};
+use std::io::Result as IoResult;
+
+use cartridge::Cartridge;
+use cheats::Cheats;
+use savestate::{Writer, Reader};
+
+pub use gpu::{Color, Frame, SCREEN_WIDTH, SCREEN_HEIGHT};
+pub use spu::{Sample, SampleBuffer, SAMPLES_PER_BUFFER, SAMPLE_RATE};
+pub use spu::samples_per_steps;
+pub use savestate::SaveStateError;
+pub use cpu::CpuRegisters;
+
+pub mod cartridge;
+pub mod cheats;
+pub mod movie;
+pub mod rewind;
+pub mod ui;
+
+mod cpu;
+mod io;
+mod gpu;
+mod spu;
+mod savestate;
+mod crc;
+#[cfg(feature = "sdl2_frontend")]
+mod resampler;
+
+/// An emulated Game Boy. The frontend updates the state of the
+/// controls and drives the emulation one frame at a time.
+pub struct GameBoy {
+    /// The CPU owns the rest of the hardware through the
+    /// interconnect
+    cpu:     cpu::Cpu,
+    /// Channel receiving the sound samples, if the frontend hasn't
+    /// taken it
+    audio:   Option<Receiver<SampleBuffer>>,
+    /// Number of cycles run past the end of the last frame
+    overrun: u32,
+}
+
+impl GameBoy {
+    /// Power up a Game Boy with `cartridge` inserted
+    pub fn new(cartridge: Cartridge) -> GameBoy {
+        let gpu = gpu::Gpu::new();
+
+        let (spu, audio) = spu::Spu::new();
+
+        let inter = io::Interconnect::new(cartridge, gpu, spu);
+
+        GameBoy {
+            cpu:     cpu::Cpu::new(inter),
+            audio:   Some(audio),
+            overrun: 0,
+        }
+    }
+
+    /// Swap the cartridge and power cycle the console. Returns the
+    /// previous cartridge, its RAM is saved when it's dropped.
+    pub fn load_cartridge(&mut self, cartridge: Cartridge) -> Cartridge {
+        let previous = self.cpu.interconnect_mut().set_cartridge(cartridge);
+
+        self.cpu.interconnect_mut().reset();
+        self.cpu.reset();
+        self.overrun = 0;
+
+        previous
+    }
+
+    /// Run the emulation for the duration of one frame
+    /// (`CYCLES_PER_FRAME` system clock cycles). Instructions can't
+    /// be interrupted so the extra cycles run past the end of the
+    /// frame are deducted from the next one.
+    pub fn run_frame(&mut self) {
+        self.run(false);
+    }
+
+    /// Same as `run_frame` but stop right after an `LD B, B`
+    /// instruction, that way the registers can be inspected before
+    /// the code moves on. Returns `true` if we stopped on such a
+    /// breakpoint, the rest of the frame is then run by the next
+    /// call.
+    pub fn run_until_breakpoint(&mut self) -> bool {
+        self.run(true)
+    }
+
+    fn run(&mut self, stop_at_breakpoint: bool) -> bool {
+        let mut cycles = self.overrun;
+
+        while cycles < CYCLES_PER_FRAME {
+            cycles += self.cpu.run_next_instruction() as u32;
+
+            if stop_at_breakpoint && self.cpu.take_breakpoint() {
+                // Pick up where we left off next time
+                self.overrun =
+                    match cycles < CYCLES_PER_FRAME {
+                        true  => cycles,
+                        false => cycles - CYCLES_PER_FRAME,
+                    };
+
+                return true;
+            }
+        }
+
+        self.overrun = cycles - CYCLES_PER_FRAME;
+
+        false
+    }
+
+    /// Update the state of the buttons
+    pub fn set_buttons(&mut self, buttons: ui::Buttons) {
+        self.cpu.interconnect_mut().set_buttons(buttons);
+    }
+
+    /// Update the tilt of the console, used by cartridges with an
+    /// accelerometer
+    pub fn set_tilt(&mut self, tilt: ui::Tilt) {
+        self.cpu.interconnect_mut().set_tilt(tilt);
+    }
+
+    /// Return the last complete picture, ready to be passed to
+    /// `ui::Display::flip`
+    pub fn framebuffer(&self) -> &Frame {
+        self.cpu.interconnect().frame()
+    }
+
+    /// Take the channel receiving the sound samples, for instance to
+    /// feed them to an audio thread. Once taken `next_audio_buffer`
+    /// always returns `None`.
+    pub fn take_audio_channel(&mut self) -> Option<Receiver<SampleBuffer>> {
+        self.audio.take()
+    }
+
+    /// Return the next buffer of `SAMPLE_RATE` Hz audio samples if
+    /// one is ready. Should be called regularly if the channel hasn't
+    /// been taken, otherwise samples will be dropped.
+    pub fn next_audio_buffer(&mut self) -> Option<SampleBuffer> {
+        self.audio.as_ref().and_then(|a| a.try_recv().ok())
+    }
+
+    /// Return the cartridge currently inserted
+    pub fn cartridge(&self) -> &Cartridge {
+        self.cpu.interconnect().cartridge()
+    }
+
+    /// Return the cartridge currently inserted
+    pub fn cartridge_mut(&mut self) -> &mut Cartridge {
+        self.cpu.interconnect_mut().cartridge_mut()
+    }
+
+    /// Replace the cheats applied to the game
+    pub fn set_cheats(&mut self, cheats: Cheats) {
+        self.cpu.interconnect_mut().set_cheats(cheats);
+    }
+
+    /// Access the cheats applied to the game
+    pub fn cheats_mut(&mut self) -> &mut Cheats {
+        self.cpu.interconnect_mut().cheats_mut()
+    }
+
+    /// Return `true` if the cartridge's rumble motor is running
+    pub fn rumble(&self) -> bool {
+        self.cpu.interconnect().rumble()
+    }
+
+    /// Return the bytes sent through the serial link since the last
+    /// call. Nothing is ever connected to the other end but test
+    /// ROMs use it to report their results.
+    pub fn take_serial_output(&mut self) -> Vec<u8> {
+        self.cpu.interconnect_mut().take_serial_output()
+    }
+
+    /// Return `true` if the CPU ran `LD B, B` since the last call.
+    /// Mooneye's test ROMs use it to signal the end of the test.
+    pub fn take_breakpoint(&mut self) -> bool {
+        self.cpu.take_breakpoint()
+    }
+
+    /// Return a copy of the CPU registers
+    pub fn registers(&self) -> CpuRegisters {
+        self.cpu.registers()
+    }
+
+    /// Read the byte at `addr` without side effects, for debugging
+    /// and test ROMs
+    pub fn peek(&self, addr: u16) -> u8 {
+        self.cpu.interconnect().fetch_byte(addr)
+    }
+
+    /// Take a snapshot of the complete state of the machine. The
+    /// state of the buttons and the cheats aren't included.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut w = Writer::new();
+
+        for &b in savestate::MAGIC {
+            w.u8(b);
+        }
+
+        w.u32(savestate::VERSION);
+        w.u32(self.cartridge().rom_crc32());
+
+        let mut state = w.into_bytes();
+
+        state.extend(self.snapshot());
+
+        state
+    }
+
+    /// Restore a snapshot made by `save_state`. If the state can't be
+    /// loaded the machine is left untouched.
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), SaveStateError> {
+        let mut r = Reader::new(state);
+
+        for &b in savestate::MAGIC {
+            if try!(r.u8().map_err(|_| SaveStateError::BadMagic)) != b {
+                return Err(SaveStateError::BadMagic);
+            }
+        }
+
+        let version = try!(r.u32());
+
+        if version != savestate::VERSION {
+            return Err(SaveStateError::UnsupportedVersion(version));
+        }
+
+        let crc      = try!(r.u32());
+        let expected = self.cartridge().rom_crc32();
+
+        if crc != expected {
+            return Err(SaveStateError::WrongRom {
+                expected: expected,
+                got:      crc,
+            });
+        }
+
+        self.restore(r.remaining())
+    }
+
+    /// Write a snapshot of the machine to `path`. The file is
+    /// replaced atomically.
+    pub fn save_state_to_path(&self, path: &Path) -> IoResult<()> {
+        let tmp_path = path.with_extension("tmp");
+
+        {
+            let mut f = try!(File::create(&tmp_path));
+
+            try!(f.write_all(&self.save_state()));
+            try!(f.sync_all());
+        }
+
+        rename(&tmp_path, path)
+    }
+
+    /// Restore a snapshot written by `save_state_to_path`
+    pub fn load_state_from_path(&mut self, path: &Path)
+                                -> Result<(), SaveStateError> {
+        let mut state = Vec::new();
+
+        let mut f = try!(File::open(path));
+
+        try!(f.read_to_end(&mut state));
+
+        self.load_state(&state)
+    }
+
+    /// Serialize the machine without the save state header. Used
+    /// directly by the rewind buffer where the header would only be
+    /// overhead.
+    fn snapshot(&self) -> Vec<u8> {
+        let mut w = Writer::new();
+
+        w.u32(self.overrun);
+
+        self.cpu.save_state(&mut w);
+
+        w.into_bytes()
+    }
+
+    /// Restore a state serialized by `snapshot`. If it fails half way
+    /// through the previous state is put back.
+    fn restore(&mut self, snapshot: &[u8]) -> Result<(), SaveStateError> {
+        // Keep the current state around in case the snapshot turns
+        // out to be corrupted
+        let backup = self.snapshot();
+
+        let result = self.restore_unchecked(snapshot);
+
+        if result.is_err() {
+            self.restore_unchecked(&backup)
+                .ok().expect("couldn't restore the previous state");
+        }
+
+        result
+    }
+
+    fn restore_unchecked(&mut self, snapshot: &[u8]) -> Result<(), SaveStateError> {
+        let mut r = Reader::new(snapshot);
+
+        self.overrun = try!(r.u32());
+
+        if self.overrun >= CYCLES_PER_FRAME {
+            return Err(SaveStateError::Invalid("bad frame overrun"));
+        }
+
+        try!(self.cpu.load_state(&mut r));
+
+        match r.remaining().is_empty() {
+            true  => Ok(()),
+            false => Err(SaveStateError::Invalid("trailing data")),
+        }
+    }
+}
+
+/// Gameboy sysclk frequency: 4.19Mhz
+pub const SYSCLK_FREQ:      i64 = 0x400000;
+
+/// Number of system clock cycles in a frame (154 lines of 456
+/// cycles), that's a little less than 60 frames per second
+pub const CYCLES_PER_FRAME: u32 = 70224;
+
+#[cfg(test)]
+mod benchmark {
+    use test::Bencher;
+
+    use std::thread::spawn;
+
+    #[bench]
+    fn bench_rom(b: &mut Bencher) {
+        let rom = ::std::iter::repeat(0).take(0x8000).collect();
+        let cart = ::cartridge::Cartridge::from_vec(rom).unwrap();
+
+        let mut gb = ::GameBoy::new(cart);
+
+        let audio_channel = gb.take_audio_channel().unwrap();
+
+        spawn(move|| {
+            // Dummy consumer
+            while let Ok(_) = audio_channel.recv() {
+            }
+        });
+
+        b.iter(|| {
+            gb.cpu.reset();
+
+            // Simulate 100ms of emulated time so that the benchmark
+            // doesn't run for too long.
+            for _ in 0..6 {
+                gb.run_frame();
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread::spawn;
+
+    use GameBoy;
+    use cartridge::Cartridge;
+    use gpu::Color;
+
+    /// The emulator state can be moved to another thread
+    #[test]
+    fn send() {
+        let rom = ::std::iter::repeat(0).take(0x8000).collect();
+        let cart = ::cartridge::Cartridge::from_vec(rom).unwrap();
+
+        let mut gb = GameBoy::new(cart);
+
+        gb.run_frame();
+
+        let gb = spawn(move || {
+            gb.run_frame();
+            gb
+        }).join().unwrap();
+
+        assert!(gb.framebuffer().len() == ::SCREEN_WIDTH * ::SCREEN_HEIGHT);
+    }
+
+    /// Build a blank 32KB ROM with a garbage logo derived from
+    /// `seed`. Also used by the other modules' tests.
+    pub fn test_cartridge(seed: u8) -> Cartridge {
+        let mut rom = vec![0; 0x8000];
+
+        // Garbage logo so that the bootrom has something to scroll
+        for (i, b) in rom[0x104..0x134].iter_mut().enumerate() {
+            *b = (i as u8).wrapping_mul(37) ^ seed;
+        }
+
+        Cartridge::from_vec(rom).unwrap()
+    }
+
+    /// Run `n` frames and return them
+    fn run_frames(gb: &mut GameBoy, n: usize) -> Vec<Vec<Color>> {
+        (0..n).map(|_| {
+            gb.run_frame();
+            gb.framebuffer().to_vec()
+        }).collect()
+    }
+
+    /// Running from a restored state gives the same result as
+    /// running from the original one
+    #[test]
+    fn save_state_round_trip() {
+        let mut gb = GameBoy::new(test_cartridge(0));
+
+        run_frames(&mut gb, 20);
+
+        let state = gb.save_state();
+
+        let expected = run_frames(&mut gb, 30);
+        let expected_state = gb.save_state();
+
+        gb.load_state(&state).unwrap();
+
+        assert!(run_frames(&mut gb, 30) == expected);
+        assert!(gb.save_state() == expected_state);
+
+        // A freshly powered up console ends up in the same state
+        let mut other = GameBoy::new(test_cartridge(0));
+
+        other.load_state(&state).unwrap();
+
+        assert!(run_frames(&mut other, 30) == expected);
+
+        // Broken states are rejected and leave the machine untouched
+        assert!(gb.load_state(&state[..state.len() / 2]).is_err());
+        assert!(gb.load_state(&state[1..]).is_err());
+        assert!(gb.save_state() == expected_state);
+
+        let mut wrong_rom = GameBoy::new(test_cartridge(1));
+
+        assert!(wrong_rom.load_state(&state).is_err());
+    }
+}
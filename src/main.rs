@@ -1,81 +1,261 @@
-//! gb-rs: Game Boy emulator
-//! Ressources:
-//!
-//! Opcode map: http://www.pastraiser.com/cpu/gameboy/gameboy_opcodes.html
-//! JS emulator: http://imrannazar.com/GameBoy-Emulation-in-JavaScript:-The-CPU
-//! Lots of info about GC quircks: http://www.devrs.com/gb/files/faqs.html
-//! Accuracy tests: http://tasvideos.org/EmulatorResources/GBAccuracyTests.html
-
-#![cfg_attr(test, feature(test))]
-
-#![warn(missing_docs)]
-
-#[macro_use]
-extern crate log;
-extern crate sdl2;
-extern crate ascii;
-extern crate num;
-
-#[cfg(test)]
-extern crate test;
-
-use std::sync::mpsc::channel;
-use ui::Audio;
-use std::path::Path;
-
-mod cpu;
-mod io;
-mod gpu;
-mod ui;
-mod cartridge;
-mod spu;
-mod resampler;
+//! gb-rs: Game Boy emulator. SDL2 frontend built on top of the
+//! gb_rs library.
+
+extern crate gb_rs;
+extern crate getopts;
+
+use std::sync::mpsc::{channel, sync_channel};
+use std::path::{Path, PathBuf};
+use getopts::{Options, Matches};
+
+use gb_rs::{GameBoy, cartridge, cheats, ui};
+use gb_rs::rewind::{Rewind, RewindOptions, RewindAudio};
+use gb_rs::movie::{self, Movie};
+use gb_rs::ui::{Audio, Rumble, Display};
+use gb_rs::{SYSCLK_FREQ, CYCLES_PER_FRAME};
 
 #[allow(dead_code)]
 fn main() {
     let argv: Vec<_> = std::env::args().collect();
 
-    if argv.len() < 2 {
-        println!("Usage: {} <rom-file>", argv[0]);
+    let mut opts = Options::new();
+
+    opts.optflag("i", "info", "print the cartridge header and exit");
+    opts.optopt("p", "patch", "apply an IPS, UPS or BPS patch to the ROM",
+                "FILE");
+    opts.optopt("", "flush-delay",
+                "flush the save file MS milliseconds after the last write \
+                 (0 to only save on exit)", "MS");
+    opts.optflag("", "save-backup",
+                 "keep the previous save file as a .sav.bak");
+    opts.optopt("", "save-dir",
+                "store save files in DIR instead of next to the ROM", "DIR");
+    opts.optflag("", "save-by-checksum",
+                 "name save files after the ROM's header checksum \
+                  instead of its file name");
+    opts.optopt("", "rewind-buffer",
+                "memory used by the rewind buffer (0 to disable, \
+                 default 32)", "MB");
+    opts.optopt("", "rewind-interval",
+                "take a rewind snapshot every N frames (default 4)", "N");
+    opts.optopt("", "rewind-speed",
+                "go back N snapshots per frame while rewinding \
+                 (default 1)", "N");
+    opts.optopt("", "rewind-audio",
+                "sound while rewinding: mute (default) or reverse", "MODE");
+    opts.optopt("", "record", "record the inputs to a movie file", "FILE");
+    opts.optopt("", "play", "play back a movie file", "FILE");
+    opts.optopt("", "verify",
+                "replay a movie without display as fast as possible and \
+                 check that it ends on the same picture", "FILE");
+    opts.optflag("h", "help", "print this help");
+
+    let matches = match opts.parse(&argv[1..]) {
+        Ok(m)  => m,
+        Err(e) => {
+            println!("{}", e);
+            print_usage(&argv[0], &opts);
+            std::process::exit(1);
+        }
+    };
+
+    if matches.opt_present("help") || matches.free.len() != 1 {
+        print_usage(&argv[0], &opts);
         return;
     }
 
-    let rompath = Path::new(&argv[1]);
+    let rompath = Path::new(&matches.free[0]);
+
+    let mut options = cartridge::LoadOptions::new();
+
+    options.patch  = matches.opt_str("patch").map(PathBuf::from);
+    options.backup = matches.opt_present("save-backup");
+
+    options.save_dir = matches.opt_str("save-dir").map(PathBuf::from);
+
+    if matches.opt_present("save-by-checksum") {
+        options.save_naming = cartridge::SaveNaming::GlobalChecksum;
+    }
+
+    if let Some(delay) = matches.opt_str("flush-delay") {
+        options.flush_delay_ms = match delay.parse() {
+            Ok(0)  => None,
+            Ok(ms) => Some(ms),
+            Err(e) => {
+                println!("Invalid flush delay '{}': {}", delay, e);
+                std::process::exit(1);
+            }
+        };
+    }
+
+    if matches.opt_present("info") {
+        match cartridge::Header::from_path(&rompath, &options) {
+            Ok(h)  => println!("{}", h),
+            Err(e) => {
+                println!("Failed to load ROM {}: {}", rompath.display(), e);
+                std::process::exit(1);
+            }
+        }
+
+        return;
+    }
+
+    let mut rewind_options = RewindOptions::new();
+
+    if let Some(mb) = parse_number(&matches, "rewind-buffer") {
+        rewind_options.max_size = mb as usize * 1024 * 1024;
+    }
+
+    if let Some(n) = parse_number(&matches, "rewind-interval") {
+        rewind_options.interval = n;
+    }
+
+    if let Some(n) = parse_number(&matches, "rewind-speed") {
+        rewind_options.speed = n;
+    }
 
-    let cart = match cartridge::Cartridge::from_path(&rompath) {
+    if let Some(mode) = matches.opt_str("rewind-audio") {
+        rewind_options.audio = match &*mode {
+            "mute"    => RewindAudio::Mute,
+            "reverse" => RewindAudio::Reverse,
+            _         => {
+                println!("Invalid rewind audio mode '{}'", mode);
+                std::process::exit(1);
+            }
+        };
+    }
+
+    let record_path = matches.opt_str("record").map(PathBuf::from);
+
+    let play_movie =
+        match matches.opt_str("play").or(matches.opt_str("verify")) {
+            Some(path) => {
+                // The movie brings its own RAM contents, leave the
+                // save file alone
+                options.no_save = true;
+
+                match Movie::from_path(Path::new(&path)) {
+                    Ok(m)  => Some(m),
+                    Err(e) => {
+                        println!("Can't load movie {}: {}", path, e);
+                        std::process::exit(1);
+                    }
+                }
+            }
+            None => None,
+        };
+
+    let cart = cartridge::Cartridge::from_path_with_options(&rompath, &options);
+
+    let mut cart = match cart {
         Ok(r)  => r,
-        Err(e) => panic!("Failed to load ROM: {}", e),
+        Err(e) => {
+            println!("Failed to load ROM {}: {}", rompath.display(), e);
+            std::process::exit(1);
+        }
     };
 
+    if cart.has_camera() {
+        // Use the image next to the ROM if there's one, otherwise
+        // stick with the test pattern
+        let mut image_path = rompath.to_path_buf();
+        image_path.set_extension("pgm");
+
+        match ui::camera::StillImage::from_path(&image_path) {
+            Ok(image) => {
+                println!("Camera image: {}", image_path.display());
+                cart.set_image_source(Box::new(image));
+            }
+            Err(e) =>
+                println!("Can't load camera image {} ({}), \
+                          using test pattern", image_path.display(), e),
+        }
+    }
+
     println!("Loaded ROM {:?}", cart);
 
+    if matches.opt_present("verify") {
+        let movie = play_movie.unwrap();
+
+        let mut gameboy = GameBoy::new(cart);
+
+        std::process::exit(verify_movie(&movie, &mut gameboy));
+    }
+
     let sdl2 = ui::sdl2::Context::new();
 
     let mut display = sdl2.new_display(1);
 
-    let gpu = gpu::Gpu::new(&mut display);
+    let mut gameboy = GameBoy::new(cart);
+
+    // Movie being played back along with the index of the next
+    // frame
+    let mut playback = None;
+
+    if let Some(movie) = play_movie {
+        if let Err(e) = movie.start(&mut gameboy) {
+            println!("Can't start movie: {}", e);
+            std::process::exit(1);
+        }
+
+        println!("Playing back {} frames", movie.inputs().len());
+
+        playback = Some((movie, 0));
+    }
+
+    let mut recording = record_path.map(|path| {
+        println!("Recording movie to {}", path.display());
+
+        (Movie::new(&gameboy), path)
+    });
+
+    // Cheats, rewind and save state loading would all break the
+    // movie
+    let movie_mode = playback.is_some() || recording.is_some();
 
-    let (spu, audio_channel) = spu::Spu::new();
+    // The samples go through us instead of going straight to the
+    // audio backend so that we can record them for the rewind and
+    // play them backwards
+    let (audio_tx, audio_rx) = sync_channel(AUDIO_CHANNEL_DEPTH);
 
-    let mut audio = ui::sdl2::Audio::new(audio_channel);
+    let mut audio = ui::sdl2::Audio::new(audio_rx);
+
+    let mut rewind =
+        match rewind_options.max_size {
+            _ if movie_mode => None,
+            0 => None,
+            _ => {
+                println!("Hold backspace to rewind");
+                Some(Rewind::new(rewind_options))
+            }
+        };
 
     audio.start();
 
-    let inter = io::Interconnect::new(cart, gpu, spu, sdl2.buttons());
+    // Load the cheats next to the ROM if there are any
+    let mut cheats_path = rompath.to_path_buf();
+    cheats_path.set_extension("cht");
 
-    let mut cpu = cpu::Cpu::new(inter);
+    if !movie_mode && std::fs::metadata(&cheats_path).is_ok() {
+        match cheats::Cheats::from_path(&cheats_path) {
+            Ok(c) => {
+                println!("Loaded {} cheats from {} (F2 to toggle)",
+                         c.codes().len(), cheats_path.display());
+                gameboy.set_cheats(c);
+            }
+            Err(e) =>
+                println!("Can't load cheats {}: {}", cheats_path.display(), e),
+        }
+    }
 
     // In order to synchronize the emulation speed with the wall clock
     // we need to wait at some point so that we don't go too
     // fast. Waiting between each cycle would mean a storm of syscalls
     // back and forth between the kernel and us, so instead we execute
-    // instructions in batches of GRANULARITY cycles and then sleep
-    // for a while. If the GRANULARITY value is too low we'll go to
-    // sleep very often which will have poor performance. If it's too
-    // high it might look like the emulation is stuttering.
+    // a whole frame at a time and then sleep until the next one.
 
-    let batch_duration_ns = GRANULARITY * (1_000_000_000 /
-                                           SYSCLK_FREQ);
+    let batch_duration_ns = CYCLES_PER_FRAME as i64 * (1_000_000_000 /
+                                                       SYSCLK_FREQ);
 
     // No sub-ms precision in stable rust sleep for now...
     let batch_duration_ms = (batch_duration_ns / 1_000_000) as u32;
@@ -96,93 +276,202 @@ fn main() {
 
     let mut audio_adjust_count = 0;
 
-    let mut cycles = 0;
+    // Save state slot selected with the number keys
+    let mut slot = 0;
 
     loop {
-        while cycles < GRANULARITY {
+        let rewinding = sdl2.rewind() && rewind.is_some();
+
+        if rewinding {
+            if let Some(ref mut r) = rewind {
+                // Once the buffer is exhausted we just stay on the
+                // oldest snapshot
+                r.rewind(&mut gameboy);
+
+                while let Some(samples) = r.next_audio_buffer() {
+                    let _ = audio_tx.try_send(samples);
+                }
+            }
+        } else {
+            let mut input = movie::Input {
+                buttons: sdl2.buttons().get(),
+                tilt:    sdl2.tilt().get(),
+            };
+
+            let movie_done =
+                match playback {
+                    Some((ref movie, ref mut frame)) =>
+                        match movie.inputs().get(*frame) {
+                            Some(&i) => {
+                                input = i;
+                                *frame += 1;
+                                false
+                            }
+                            None => true,
+                        },
+                    None => false,
+                };
+
+            if movie_done {
+                if let Some((movie, _)) = playback.take() {
+                    // Back to the live controls
+                    println!("Movie finished");
+                    check_movie_hash(&movie, &gameboy);
+                }
+            }
+
+            if let Some((ref mut movie, _)) = recording {
+                movie.push(input);
+            }
+
+            gameboy.set_buttons(input.buttons);
+            gameboy.set_tilt(input.tilt);
+
             // The actual emulator takes place here!
-            cycles += cpu.run_next_instruction() as i64;
+            gameboy.run_frame();
+
+            if let Some(ref mut r) = rewind {
+                r.record(&gameboy);
+            }
+
+            while let Some(samples) = gameboy.next_audio_buffer() {
+                if let Some(ref mut r) = rewind {
+                    r.record_audio(&samples);
+                }
+
+                // If the backend can't keep up we drop the samples
+                let _ = audio_tx.try_send(samples);
+            }
         }
 
-        cycles -= GRANULARITY;
+        display.flip(gameboy.framebuffer());
 
         // Update controller status
         match sdl2.update_buttons() {
-            ui::Event::PowerOff => break,
-            ui::Event::None     => (),
+            ui::Event::PowerOff     => break,
+            ui::Event::ToggleCheats => {
+                let on = gameboy.cheats_mut().toggle();
+
+                println!("Cheats {}", if on { "enabled" } else { "disabled" });
+            }
+            ui::Event::SelectSlot(s) => {
+                slot = s;
+
+                println!("Save state slot {}", slot);
+            }
+            ui::Event::SaveState    => {
+                let path = gameboy.cartridge().state_path(slot);
+
+                match gameboy.save_state_to_path(&path) {
+                    Ok(_)  => println!("Saved state to {}", path.display()),
+                    Err(e) => println!("Can't save state to {}: {}",
+                                       path.display(), e),
+                }
+            }
+            ui::Event::LoadState if movie_mode =>
+                println!("Can't load a save state while a movie is running"),
+            ui::Event::LoadState    => {
+                let path = gameboy.cartridge().state_path(slot);
+
+                match gameboy.load_state_from_path(&path) {
+                    Ok(_)  => println!("Loaded state from {}", path.display()),
+                    Err(e) => println!("Can't load state from {}: {}",
+                                       path.display(), e),
+                }
+            }
+            ui::Event::None         => (),
         }
 
+        sdl2.set_rumble(gameboy.rumble());
+
         // Sleep until next batch cycle
         if let Err(e) = tick_rx.recv() {
              panic!("Timer died: {:?}", e);
         }
 
-        audio_adjust_count += GRANULARITY;
+        audio_adjust_count += CYCLES_PER_FRAME as i64;
 
         if audio_adjust_count >= SYSCLK_FREQ * AUDIO_ADJUST_SEC {
             // Retrieve the number of samples generated since the last
             // adjustment
-            let s = spu::samples_per_steps(audio_adjust_count as u32);
+            let s = gb_rs::samples_per_steps(audio_adjust_count as u32);
 
             audio.adjust_resampling(s);
 
             audio_adjust_count = 0;
         }
     }
-}
 
-/// Number of instructions executed between sleeps (i.e. giving the
-/// hand back to the scheduler). Low values increase CPU usage and can
-/// result in poor performance, high values will cause stuttering.
-const GRANULARITY:      i64 = 0x10000;
+    if let Some((mut movie, path)) = recording {
+        movie.finish(&gameboy);
 
-/// Gameboy sysclk frequency: 4.19Mhz
-const SYSCLK_FREQ:      i64 = 0x400000;
-
-/// How often should we adjust the audio resampling rate. In seconds.
-const AUDIO_ADJUST_SEC: i64 = 1;
-
-#[cfg(test)]
-mod benchmark {
-    use test::Bencher;
-
-    use std::thread::spawn;
+        match movie.save(&path) {
+            Ok(_)  => println!("Saved {} frames to {}",
+                               movie.inputs().len(), path.display()),
+            Err(e) => println!("Can't save movie {}: {}", path.display(), e),
+        }
+    }
+}
 
-    #[bench]
-    fn bench_rom(b: &mut Bencher) {
-        let mut display = ::ui::dummy::DummyDisplay;
-        let controller  = ::ui::dummy::DummyController::new();
+/// Parse the numeric option `name`, exit on error
+fn parse_number(matches: &Matches, name: &str) -> Option<u32> {
+    matches.opt_str(name).map(|v| {
+        match v.parse() {
+            Ok(n)  => n,
+            Err(e) => {
+                println!("Invalid value for --{} '{}': {}", name, v, e);
+                std::process::exit(1);
+            }
+        }
+    })
+}
 
-        let rom = ::std::iter::repeat(0).take(0x4000).collect();
-        let cart = ::cartridge::Cartridge::from_vec(rom);
+/// Print whether the current picture matches the end of `movie`.
+/// Returns `false` on mismatch.
+fn check_movie_hash(movie: &Movie, gameboy: &GameBoy) -> bool {
+    let hash = movie::frame_hash(gameboy.framebuffer());
 
-        let (spu, audio_channel) = ::spu::Spu::new();
+    match movie.final_hash() {
+        Some(h) if h == hash => {
+            println!("Final picture matches ({:016x})", hash);
+            true
+        }
+        Some(h) => {
+            println!("Final picture mismatch: expected {:016x} got {:016x}",
+                     h, hash);
+            false
+        }
+        None => {
+            println!("The movie has no final hash, got {:016x}", hash);
+            true
+        }
+    }
+}
 
-        spawn(move|| {
-            // Dummy consumer
-            while let Ok(_) = audio_channel.recv() {
-            }
-        });
+/// Replay `movie` and return the exit status: 0 if the final picture
+/// matches, 1 otherwise
+fn verify_movie(movie: &Movie, gameboy: &mut GameBoy) -> i32 {
+    if let Err(e) = movie.replay(gameboy) {
+        println!("Can't start movie: {}", e);
+        return 1;
+    }
 
-        let gpu = ::gpu::Gpu::new(&mut display);
+    println!("Replayed {} frames", movie.inputs().len());
 
-        let inter = ::io::Interconnect::new(cart,
-                                            gpu,
-                                            spu,
-                                            controller.buttons());
+    match check_movie_hash(movie, gameboy) {
+        true  => 0,
+        false => 1,
+    }
+}
 
-        let mut cpu = ::cpu::Cpu::new(inter);
+fn print_usage(program: &str, opts: &Options) {
+    let brief = format!("Usage: {} [options] <rom-file>", program);
 
-        b.iter(|| {
-            cpu.reset();
+    print!("{}", opts.usage(&brief));
+}
 
-            // Simulate 100ms of emulated time so that the benchmark
-            // doesn't run for too long.
-            let mut cycle = 0;
+/// How often should we adjust the audio resampling rate. In seconds.
+const AUDIO_ADJUST_SEC: i64 = 1;
 
-            while cycle < super::SYSCLK_FREQ / 10 {
-                cycle += cpu.run_next_instruction() as i64;
-            }
-        });
-    }
-}
+/// Number of sample buffers queued for the audio backend
+const AUDIO_CHANNEL_DEPTH: usize = 4;
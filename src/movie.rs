@@ -0,0 +1,350 @@
+//! Input movies.
+//!
+//! A movie contains a save state taken at power on (which includes
+//! the contents of the cartridge RAM and the clock) followed by the
+//! state of the controls for every frame. Since the core is
+//! deterministic replaying the inputs from the same state reproduces
+//! the run exactly. A hash of the last frame can be stored at the
+//! end to check that it's actually the case.
+//!
+//! Movies don't record the cheats, the rewind or the save states
+//! loaded during the run so the frontend should disable those.
+
+use std::fmt;
+use std::io;
+use std::error;
+use std::path::Path;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::io::Result as IoResult;
+
+use GameBoy;
+use gpu::Frame;
+use ui::{Buttons, ButtonState, Tilt};
+use savestate::{Writer, Reader, SaveStateError};
+
+/// State of the controls for one frame
+#[derive(Debug,Clone,Copy)]
+pub struct Input {
+    /// Buttons
+    pub buttons: Buttons,
+    /// Console tilt, only used by cartridges with an accelerometer
+    pub tilt:    Tilt,
+}
+
+/// A recorded run
+pub struct Movie {
+    /// Save state at the beginning of the movie
+    state:      Vec<u8>,
+    /// Input for each frame
+    inputs:     Vec<Input>,
+    /// Hash of the picture after the last frame, if known
+    final_hash: Option<u64>,
+}
+
+impl Movie {
+    /// Start recording a new movie from the current state of `gb`,
+    /// which should have just been powered up
+    pub fn new(gb: &GameBoy) -> Movie {
+        Movie {
+            state:      gb.save_state(),
+            inputs:     Vec::new(),
+            final_hash: None,
+        }
+    }
+
+    /// Load a movie file
+    pub fn from_path(path: &Path) -> Result<Movie, MovieError> {
+        let mut data = Vec::new();
+
+        let mut f = try!(File::open(path));
+
+        try!(f.read_to_end(&mut data));
+
+        Movie::from_bytes(&data)
+    }
+
+    /// Parse a movie serialized by `to_bytes`
+    pub fn from_bytes(data: &[u8]) -> Result<Movie, MovieError> {
+        let mut r = Reader::new(data);
+
+        for &b in MAGIC {
+            if try!(r.u8().map_err(|_| MovieError::BadMagic)) != b {
+                return Err(MovieError::BadMagic);
+            }
+        }
+
+        let version = try!(r.u32());
+
+        if version != VERSION {
+            return Err(MovieError::UnsupportedVersion(version));
+        }
+
+        let state = try!(r.bytes()).to_vec();
+
+        let nframes = try!(r.u32()) as usize;
+
+        let mut inputs = Vec::with_capacity(nframes);
+
+        for _ in 0..nframes {
+            let buttons = try!(r.u8());
+            let x       = try!(r.u16()) as i16;
+            let y       = try!(r.u16()) as i16;
+
+            inputs.push(Input {
+                buttons: buttons_from_byte(buttons),
+                tilt:    Tilt { x: x, y: y },
+            });
+        }
+
+        let final_hash =
+            match try!(r.bool()) {
+                true  => {
+                    let lo = try!(r.u32()) as u64;
+                    let hi = try!(r.u32()) as u64;
+
+                    Some(lo | (hi << 32))
+                }
+                false => None,
+            };
+
+        Ok(Movie {
+            state:      state,
+            inputs:     inputs,
+            final_hash: final_hash,
+        })
+    }
+
+    /// Serialize the movie
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut w = Writer::new();
+
+        for &b in MAGIC {
+            w.u8(b);
+        }
+
+        w.u32(VERSION);
+        w.bytes(&self.state);
+        w.u32(self.inputs.len() as u32);
+
+        for input in &self.inputs {
+            w.u8(buttons_to_byte(&input.buttons));
+            w.u16(input.tilt.x as u16);
+            w.u16(input.tilt.y as u16);
+        }
+
+        match self.final_hash {
+            Some(hash) => {
+                w.bool(true);
+                w.u32(hash as u32);
+                w.u32((hash >> 32) as u32);
+            }
+            None => w.bool(false),
+        }
+
+        w.into_bytes()
+    }
+
+    /// Write the movie to `path`
+    pub fn save(&self, path: &Path) -> IoResult<()> {
+        let mut f = try!(File::create(path));
+
+        f.write_all(&self.to_bytes())
+    }
+
+    /// Record the input for the next frame
+    pub fn push(&mut self, input: Input) {
+        self.inputs.push(input);
+    }
+
+    /// Stop recording: store the hash of the current picture of `gb`
+    pub fn finish(&mut self, gb: &GameBoy) {
+        self.final_hash = Some(frame_hash(gb.framebuffer()));
+    }
+
+    /// Put `gb` back in the state it was in at the beginning of the
+    /// movie
+    pub fn start(&self, gb: &mut GameBoy) -> Result<(), SaveStateError> {
+        gb.load_state(&self.state)
+    }
+
+    /// Return the input for each frame
+    pub fn inputs(&self) -> &[Input] {
+        &self.inputs
+    }
+
+    /// Return the hash of the picture at the end of the movie, if
+    /// it was recorded until the end
+    pub fn final_hash(&self) -> Option<u64> {
+        self.final_hash
+    }
+
+    /// Replay the whole movie on `gb` as fast as possible and return
+    /// the hash of the last picture
+    pub fn replay(&self, gb: &mut GameBoy) -> Result<u64, SaveStateError> {
+        try!(self.start(gb));
+
+        for input in &self.inputs {
+            gb.set_buttons(input.buttons);
+            gb.set_tilt(input.tilt);
+            gb.run_frame();
+
+            // Nobody is listening, don't let the sound pile up
+            while let Some(_) = gb.next_audio_buffer() {
+            }
+        }
+
+        Ok(frame_hash(gb.framebuffer()))
+    }
+}
+
+/// 64bit FNV-1a hash of a picture
+pub fn frame_hash(frame: &Frame) -> u64 {
+    frame.iter().fold(0xcbf29ce484222325, |hash, &c| {
+        (hash ^ c as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+fn buttons_to_byte(b: &Buttons) -> u8 {
+    let states = [b.a, b.b, b.select, b.start, b.right, b.left, b.up, b.down];
+
+    states.iter()
+        .enumerate()
+        .fold(0, |v, (i, s)| v | ((s.is_down() as u8) << i))
+}
+
+fn buttons_from_byte(v: u8) -> Buttons {
+    let state = |i: usize| match v & (1 << i) != 0 {
+        true  => ButtonState::Down,
+        false => ButtonState::Up,
+    };
+
+    let mut b = Buttons::new(ButtonState::Up);
+
+    b.a      = state(0);
+    b.b      = state(1);
+    b.select = state(2);
+    b.start  = state(3);
+    b.right  = state(4);
+    b.left   = state(5);
+    b.up     = state(6);
+    b.down   = state(7);
+
+    b
+}
+
+/// Reasons why a movie couldn't be loaded
+#[derive(Debug)]
+pub enum MovieError {
+    /// Error while accessing the movie file
+    Io(io::Error),
+    /// The file isn't a movie
+    BadMagic,
+    /// The movie was recorded by an incompatible version of the
+    /// emulator. Contains the version found in the file.
+    UnsupportedVersion(u32),
+    /// The movie ends prematurely
+    Truncated,
+}
+
+impl fmt::Display for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MovieError::Io(ref e) =>
+                write!(f, "{}", e),
+            MovieError::BadMagic =>
+                write!(f, "not a movie"),
+            MovieError::UnsupportedVersion(v) =>
+                write!(f, "unsupported movie version {}", v),
+            MovieError::Truncated =>
+                write!(f, "truncated movie"),
+        }
+    }
+}
+
+impl error::Error for MovieError {
+    fn description(&self) -> &str {
+        match *self {
+            MovieError::Io(ref e)             => e.description(),
+            MovieError::BadMagic              => "not a movie",
+            MovieError::UnsupportedVersion(_) => "unsupported movie version",
+            MovieError::Truncated             => "truncated movie",
+        }
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            MovieError::Io(ref e) => Some(e),
+            _                     => None,
+        }
+    }
+}
+
+impl From<io::Error> for MovieError {
+    fn from(e: io::Error) -> MovieError {
+        MovieError::Io(e)
+    }
+}
+
+impl From<SaveStateError> for MovieError {
+    fn from(e: SaveStateError) -> MovieError {
+        match e {
+            SaveStateError::Io(e) => MovieError::Io(e),
+            // The rest of the file is only checked when the state is
+            // loaded, the only thing that can go wrong while parsing
+            // the movie is running out of data
+            _ => MovieError::Truncated,
+        }
+    }
+}
+
+/// Magic string at the beginning of every movie
+const MAGIC: &'static [u8; 8] = b"gb-rs-mv";
+
+/// Version of the movie layout
+const VERSION: u32 = 1;
+
+#[cfg(test)]
+mod tests {
+    use super::{Movie, Input};
+    use GameBoy;
+    use tests::test_cartridge;
+    use ui::{Buttons, ButtonState, Tilt};
+
+    #[test]
+    fn replay() {
+        let mut gb = GameBoy::new(test_cartridge(0));
+
+        let mut movie = Movie::new(&gb);
+
+        for frame in 0..60 {
+            let mut buttons = Buttons::new(ButtonState::Up);
+
+            if frame % 3 == 0 {
+                buttons.a = ButtonState::Down;
+            }
+
+            let input = Input {
+                buttons: buttons,
+                tilt:    Tilt::new(),
+            };
+
+            movie.push(input);
+
+            gb.set_buttons(input.buttons);
+            gb.run_frame();
+        }
+
+        movie.finish(&gb);
+
+        // Mess with the machine, the movie brings its own state
+        gb.run_frame();
+
+        let movie = Movie::from_bytes(&movie.to_bytes()).unwrap();
+
+        assert!(movie.inputs().len() == 60);
+        assert!(movie.inputs()[3].buttons.a.is_down());
+        assert!(!movie.inputs()[4].buttons.a.is_down());
+        assert!(movie.replay(&mut gb).ok() == movie.final_hash());
+    }
+}
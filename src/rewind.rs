@@ -0,0 +1,416 @@
+//! Rewind buffer.
+//!
+//! A snapshot of the machine is taken every few frames. Only the
+//! most recent one is kept as is, the older ones are stored as the
+//! difference with their successor: the two snapshots are XORed
+//! together (most of the state doesn't change between two snapshots
+//! so the result is mostly zeroes) and the runs of zeroes are
+//! run-length encoded. Rewinding pops the deltas one by one to
+//! rebuild the previous snapshots, when the buffer is full the
+//! oldest deltas are dropped.
+
+use std::collections::VecDeque;
+
+use GameBoy;
+use spu::{Sample, SampleBuffer, SAMPLES_PER_BUFFER};
+
+/// What to do with the sound while rewinding
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum RewindAudio {
+    /// No sound while rewinding
+    Mute,
+    /// Play the sound backwards. The samples have to be stored along
+    /// with the snapshots so this uses more memory.
+    Reverse,
+}
+
+/// Rewind buffer configuration
+#[derive(Debug,Clone,Copy)]
+pub struct RewindOptions {
+    /// Number of frames between two snapshots
+    pub interval: u32,
+    /// Number of snapshots to go back for each rewound frame
+    pub speed:    u32,
+    /// Maximum amount of memory used by the buffer, in bytes
+    pub max_size: usize,
+    /// Sound while rewinding
+    pub audio:    RewindAudio,
+}
+
+impl RewindOptions {
+    /// Default options: a snapshot every 4 frames rewound at the
+    /// same pace and 32MB of buffer, which is enough for several
+    /// minutes with most games. The sound is muted.
+    pub fn new() -> RewindOptions {
+        RewindOptions {
+            interval: 4,
+            speed:    1,
+            max_size: 32 * 1024 * 1024,
+            audio:    RewindAudio::Mute,
+        }
+    }
+}
+
+/// Difference between a snapshot and the following one
+struct Delta {
+    /// Compressed XOR of the two snapshots
+    data:  Vec<u8>,
+    /// Sound generated between the two snapshots, decimated to
+    /// roughly one frame worth of samples. Empty if the sound is
+    /// muted while rewinding.
+    audio: Vec<Sample>,
+}
+
+impl Delta {
+    fn size(&self) -> usize {
+        self.data.len() + self.audio.len()
+    }
+}
+
+/// Ring buffer of compressed snapshots
+pub struct Rewind {
+    options:  RewindOptions,
+    /// Deltas, oldest first. Applying the last one to `last` gives
+    /// the previous snapshot.
+    deltas:   VecDeque<Delta>,
+    /// Most recent snapshot
+    last:     Option<Vec<u8>>,
+    /// Total size of the deltas in bytes
+    size:     usize,
+    /// Number of frames run since the last snapshot
+    frames:   u32,
+    /// Sound recorded since the last snapshot
+    audio:    Vec<Sample>,
+    /// Used to decimate the recorded sound
+    decimate: u32,
+    /// Reversed sound waiting to be played
+    reversed: VecDeque<Sample>,
+}
+
+impl Rewind {
+    /// Create an empty rewind buffer
+    pub fn new(options: RewindOptions) -> Rewind {
+        let mut options = options;
+
+        // Zero would make no sense for those and would only cause
+        // divisions by zero
+        options.interval = ::std::cmp::max(options.interval, 1);
+        options.speed    = ::std::cmp::max(options.speed, 1);
+
+        Rewind {
+            options:  options,
+            deltas:   VecDeque::new(),
+            last:     None,
+            size:     0,
+            frames:   0,
+            audio:    Vec::new(),
+            decimate: 0,
+            reversed: VecDeque::new(),
+        }
+    }
+
+    /// Return the options used by this buffer
+    pub fn options(&self) -> &RewindOptions {
+        &self.options
+    }
+
+    /// Drop all the snapshots, for instance after loading a save
+    /// state
+    pub fn clear(&mut self) {
+        self.deltas.clear();
+        self.last   = None;
+        self.size   = 0;
+        self.frames = 0;
+        self.audio.clear();
+        self.reversed.clear();
+    }
+
+    /// Return the memory used by the compressed snapshots in bytes
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Return the number of snapshots in the buffer
+    pub fn len(&self) -> usize {
+        self.deltas.len() + self.last.is_some() as usize
+    }
+
+    /// Must be called after each emulated frame, takes a snapshot of
+    /// `gb` every `interval` frames
+    pub fn record(&mut self, gb: &GameBoy) {
+        self.frames += 1;
+
+        if self.frames < self.options.interval {
+            return;
+        }
+
+        self.frames = 0;
+
+        let state = gb.snapshot();
+
+        match self.last.take() {
+            Some(ref last) if last.len() == state.len() => {
+                let delta = Delta {
+                    data:  xor_encode(last, &state),
+                    audio: ::std::mem::replace(&mut self.audio, Vec::new()),
+                };
+
+                self.size += delta.size();
+                self.deltas.push_back(delta);
+
+                while self.size > self.options.max_size {
+                    match self.deltas.pop_front() {
+                        Some(d) => self.size -= d.size(),
+                        None    => break,
+                    }
+                }
+            }
+            // The layout of the state changed (new cartridge?), we
+            // can't go back further
+            _ => self.clear(),
+        }
+
+        self.last = Some(state);
+    }
+
+    /// Record the sound generated while running the emulator, only
+    /// used if it's played backwards while rewinding
+    pub fn record_audio(&mut self, samples: &[Sample]) {
+        if self.options.audio != RewindAudio::Reverse {
+            return;
+        }
+
+        // We only keep one sample out of `interval`: when rewinding
+        // we move one snapshot back for each frame so this is
+        // roughly the number of samples we'll have to play
+        for &s in samples {
+            if self.decimate == 0 {
+                self.audio.push(s);
+            }
+
+            self.decimate = (self.decimate + 1) % self.options.interval;
+        }
+    }
+
+    /// Move `speed` snapshots back in time and restore the machine
+    /// in that state. Returns `false` if there's nothing left to
+    /// rewind, in which case `gb` is left untouched.
+    pub fn rewind(&mut self, gb: &mut GameBoy) -> bool {
+        if self.last.is_none() {
+            return false;
+        }
+
+        let mut moved = false;
+
+        for _ in 0..self.options.speed {
+            if self.frames > 0 {
+                // We've run past the last snapshot, go back to it
+                self.frames = 0;
+
+                let audio = ::std::mem::replace(&mut self.audio, Vec::new());
+
+                self.play_reversed(&audio);
+            } else {
+                let delta =
+                    match self.deltas.pop_back() {
+                        Some(d) => d,
+                        None    => break,
+                    };
+
+                self.size -= delta.size();
+
+                if let Some(ref mut last) = self.last {
+                    xor_decode(&delta.data, last);
+                }
+
+                self.play_reversed(&delta.audio);
+            }
+
+            moved = true;
+        }
+
+        if moved {
+            if let Some(ref last) = self.last {
+                gb.restore(last)
+                    .ok().expect("couldn't restore rewind snapshot");
+            }
+        }
+
+        moved
+    }
+
+    /// Return the next buffer of reversed sound if one is ready
+    pub fn next_audio_buffer(&mut self) -> Option<SampleBuffer> {
+        if self.reversed.len() < SAMPLES_PER_BUFFER {
+            return None;
+        }
+
+        let mut buffer = [0; SAMPLES_PER_BUFFER];
+
+        for s in buffer.iter_mut() {
+            *s = self.reversed.pop_front().unwrap();
+        }
+
+        Some(buffer)
+    }
+
+    /// Queue `samples` to be played backwards. When rewinding faster
+    /// than one snapshot per frame we drop samples to keep up.
+    fn play_reversed(&mut self, samples: &[Sample]) {
+        let speed = self.options.speed as usize;
+
+        let samples = samples.iter()
+            .rev()
+            .enumerate()
+            .filter(|&(i, _)| i % speed == 0)
+            .map(|(_, &s)| s);
+
+        self.reversed.extend(samples);
+    }
+}
+
+/// XOR `a` and `b` and encode the result as a series of runs: the
+/// number of zeroes (identical bytes), the number of literal bytes
+/// and the literal bytes themselves
+fn xor_encode(a: &[u8], b: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+
+    let len = a.len();
+    let mut i = 0;
+
+    while i < len {
+        let start = i;
+
+        while i < len && a[i] == b[i] {
+            i += 1;
+        }
+
+        push_varint(&mut out, i - start);
+
+        let start = i;
+
+        while i < len && a[i] != b[i] {
+            i += 1;
+        }
+
+        push_varint(&mut out, i - start);
+
+        out.extend(a[start..i].iter().zip(&b[start..i]).map(|(x, y)| x ^ y));
+    }
+
+    out
+}
+
+/// Apply a delta built by `xor_encode` to `state`
+fn xor_decode(delta: &[u8], state: &mut [u8]) {
+    let mut delta = delta;
+    let mut pos   = 0;
+
+    while !delta.is_empty() {
+        pos += read_varint(&mut delta);
+
+        let n = read_varint(&mut delta);
+
+        for (s, &x) in state[pos..pos + n].iter_mut().zip(&delta[..n]) {
+            *s ^= x;
+        }
+
+        delta = &delta[n..];
+        pos  += n;
+    }
+}
+
+/// Push `v` 7 bits at a time, the MSB is set if more bytes follow
+fn push_varint(out: &mut Vec<u8>, v: usize) {
+    let mut v = v;
+
+    while v >= 0x80 {
+        out.push((v as u8) | 0x80);
+        v >>= 7;
+    }
+
+    out.push(v as u8);
+}
+
+fn read_varint(data: &mut &[u8]) -> usize {
+    let mut v     = 0;
+    let mut shift = 0;
+
+    loop {
+        let b = data[0];
+
+        *data = &data[1..];
+
+        v |= ((b & 0x7f) as usize) << shift;
+        shift += 7;
+
+        if b & 0x80 == 0 {
+            return v;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Rewind, RewindOptions, xor_encode, xor_decode};
+    use GameBoy;
+    use tests::test_cartridge;
+
+    #[test]
+    fn delta() {
+        let a: Vec<u8> = (0..1000).map(|i| (i * 7) as u8).collect();
+
+        let mut b = a.clone();
+
+        b[0]   = 0xff;
+        b[500] = 0x12;
+        b[501] = 0x34;
+        b[999] = 0;
+
+        let delta = xor_encode(&b, &a);
+
+        assert!(delta.len() < 20);
+
+        let mut c = a.clone();
+
+        xor_decode(&delta, &mut c);
+
+        assert!(c == b);
+    }
+
+    #[test]
+    fn rewind() {
+        let mut gb = GameBoy::new(test_cartridge(0));
+
+        let mut options = RewindOptions::new();
+
+        options.interval = 2;
+
+        let mut rewind = Rewind::new(options);
+
+        let mut snapshots = Vec::new();
+
+        for frame in 1..41 {
+            gb.run_frame();
+            rewind.record(&gb);
+
+            if frame % 2 == 0 {
+                snapshots.push(gb.snapshot());
+            }
+        }
+
+        assert!(rewind.len() == 20);
+
+        // Run past the last snapshot, the first rewind goes back to
+        // it
+        gb.run_frame();
+        rewind.record(&gb);
+
+        while let Some(expected) = snapshots.pop() {
+            assert!(rewind.rewind(&mut gb));
+            assert!(gb.snapshot() == expected);
+        }
+
+        assert!(!rewind.rewind(&mut gb));
+    }
+}
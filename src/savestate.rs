@@ -0,0 +1,252 @@
+//! Save states: snapshots of the complete state of the machine.
+//!
+//! A save state starts with a small header (magic, format version
+//! and CRC of the ROM it was taken with) followed by the state of
+//! each component, serialized one field at a time in little endian
+//! by the component's `save_state` method and read back in the same
+//! order by `load_state`. Any change to the layout must bump
+//! `VERSION`.
+
+use std::fmt;
+use std::io;
+use std::error;
+
+/// Magic string at the beginning of every save state
+pub const MAGIC: &'static [u8; 8] = b"gb-rs-ss";
+
+/// Version of the save state layout
+pub const VERSION: u32 = 2;
+
+/// Serializer used to build a save state
+pub struct Writer {
+    buf: Vec<u8>,
+}
+
+impl Writer {
+    /// Create an empty state
+    pub fn new() -> Writer {
+        Writer {
+            buf: Vec::new(),
+        }
+    }
+
+    /// Store a byte
+    pub fn u8(&mut self, v: u8) {
+        self.buf.push(v);
+    }
+
+    /// Store a 16bit word, little endian
+    pub fn u16(&mut self, v: u16) {
+        self.u8(v as u8);
+        self.u8((v >> 8) as u8);
+    }
+
+    /// Store a 32bit word, little endian
+    pub fn u32(&mut self, v: u32) {
+        self.u16(v as u16);
+        self.u16((v >> 16) as u16);
+    }
+
+    /// Store a boolean as a single 0 or 1 byte
+    pub fn bool(&mut self, v: bool) {
+        self.u8(v as u8);
+    }
+
+    /// Store a variable length buffer, prefixed by its length
+    pub fn bytes(&mut self, v: &[u8]) {
+        self.u32(v.len() as u32);
+        self.buf.extend(v.iter().cloned());
+    }
+
+    /// Return the serialized state
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+/// Deserializer used to restore a save state
+pub struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    /// Read the state serialized in `data`
+    pub fn new(data: &'a [u8]) -> Reader<'a> {
+        Reader {
+            data: data,
+        }
+    }
+
+    /// Read a byte stored by `Writer::u8`
+    pub fn u8(&mut self) -> Result<u8, SaveStateError> {
+        match self.data.split_first() {
+            Some((&v, rest)) => {
+                self.data = rest;
+                Ok(v)
+            }
+            None => Err(SaveStateError::Truncated),
+        }
+    }
+
+    /// Read a 16bit word stored by `Writer::u16`
+    pub fn u16(&mut self) -> Result<u16, SaveStateError> {
+        let lo = try!(self.u8()) as u16;
+        let hi = try!(self.u8()) as u16;
+
+        Ok(lo | (hi << 8))
+    }
+
+    /// Read a 32bit word stored by `Writer::u32`
+    pub fn u32(&mut self) -> Result<u32, SaveStateError> {
+        let lo = try!(self.u16()) as u32;
+        let hi = try!(self.u16()) as u32;
+
+        Ok(lo | (hi << 16))
+    }
+
+    /// Read a boolean stored by `Writer::bool`. Any value other
+    /// than 0 or 1 is an error.
+    pub fn bool(&mut self) -> Result<bool, SaveStateError> {
+        match try!(self.u8()) {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(SaveStateError::Invalid("bad boolean")),
+        }
+    }
+
+    /// Read a buffer stored by `Writer::bytes`
+    pub fn bytes(&mut self) -> Result<&'a [u8], SaveStateError> {
+        let len = try!(self.u32()) as usize;
+
+        if len > self.data.len() {
+            return Err(SaveStateError::Truncated);
+        }
+
+        let (v, rest) = self.data.split_at(len);
+
+        self.data = rest;
+
+        Ok(v)
+    }
+
+    /// Read a buffer stored by `Writer::bytes` into `buf`, its size
+    /// must match exactly
+    pub fn bytes_into(&mut self, buf: &mut [u8]) -> Result<(), SaveStateError> {
+        let v = try!(self.bytes());
+
+        if v.len() != buf.len() {
+            return Err(SaveStateError::Invalid("bad buffer size"));
+        }
+
+        buf.clone_from_slice(v);
+
+        Ok(())
+    }
+
+    /// Return the data that hasn't been read yet
+    pub fn remaining(&self) -> &'a [u8] {
+        self.data
+    }
+}
+
+/// Reasons why a save state couldn't be loaded
+#[derive(Debug)]
+pub enum SaveStateError {
+    /// Error while accessing the save state file
+    Io(io::Error),
+    /// The file isn't a save state
+    BadMagic,
+    /// The save state was made by an incompatible version of the
+    /// emulator. Contains the version found in the file.
+    UnsupportedVersion(u32),
+    /// The save state was taken with a different ROM
+    WrongRom {
+        /// CRC of the current ROM
+        expected: u32,
+        /// CRC stored in the save state
+        got:      u32,
+    },
+    /// The save state ends prematurely
+    Truncated,
+    /// The save state contains an impossible value
+    Invalid(&'static str),
+}
+
+impl fmt::Display for SaveStateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SaveStateError::Io(ref e) =>
+                write!(f, "{}", e),
+            SaveStateError::BadMagic =>
+                write!(f, "not a save state"),
+            SaveStateError::UnsupportedVersion(v) =>
+                write!(f, "unsupported save state version {}", v),
+            SaveStateError::WrongRom { expected, got } =>
+                write!(f, "save state made with another ROM: \
+                           expected CRC {:08x} got {:08x}",
+                       expected, got),
+            SaveStateError::Truncated =>
+                write!(f, "truncated save state"),
+            SaveStateError::Invalid(msg) =>
+                write!(f, "invalid save state: {}", msg),
+        }
+    }
+}
+
+impl error::Error for SaveStateError {
+    fn description(&self) -> &str {
+        match *self {
+            SaveStateError::Io(ref e)              => e.description(),
+            SaveStateError::BadMagic               => "not a save state",
+            SaveStateError::UnsupportedVersion(_)  => "unsupported save state version",
+            SaveStateError::WrongRom { .. }        => "save state made with another ROM",
+            SaveStateError::Truncated              => "truncated save state",
+            SaveStateError::Invalid(_)             => "invalid save state",
+        }
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            SaveStateError::Io(ref e) => Some(e),
+            _                         => None,
+        }
+    }
+}
+
+impl From<io::Error> for SaveStateError {
+    fn from(e: io::Error) -> SaveStateError {
+        SaveStateError::Io(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Writer, Reader, SaveStateError};
+
+    #[test]
+    fn round_trip() {
+        let mut w = Writer::new();
+
+        w.u8(0x12);
+        w.u16(0x3456);
+        w.u32(0x789abcde);
+        w.bool(true);
+        w.bytes(&[1, 2, 3]);
+
+        let state = w.into_bytes();
+
+        let mut r = Reader::new(&state);
+
+        assert!(r.u8().unwrap() == 0x12);
+        assert!(r.u16().unwrap() == 0x3456);
+        assert!(r.u32().unwrap() == 0x789abcde);
+        assert!(r.bool().unwrap() == true);
+        assert!(r.bytes().unwrap() == [1, 2, 3]);
+        assert!(r.remaining().is_empty());
+
+        match r.u8() {
+            Err(SaveStateError::Truncated) => (),
+            _ => panic!("read past the end of the state"),
+        }
+    }
+}
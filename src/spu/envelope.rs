@@ -1,6 +1,7 @@
 //! Envelope function used by sounds 1, 2 and 4
 
 use spu::{Sample, SOUND_MAX};
+use savestate::{Writer, Reader, SaveStateError};
 
 #[derive(Clone,Copy)]
 pub struct Envelope {
@@ -39,6 +40,19 @@ impl Envelope {
         (vol << 4) | (dir << 3) | l
     }
 
+    pub fn save_state(&self, w: &mut Writer) {
+        w.u8(self.into_reg());
+        w.u32(self.counter);
+    }
+
+    pub fn load_state(r: &mut Reader) -> Result<Envelope, SaveStateError> {
+        let mut envelope = Envelope::from_reg(try!(r.u8()));
+
+        envelope.counter = try!(r.u32());
+
+        Ok(envelope)
+    }
+
     pub fn step(&mut self) {
         if self.step_duration == 0 {
             // If the step duration is 0 the envelope is not active
@@ -3,6 +3,7 @@
 
 use spu::{Sample, Mode};
 use spu::envelope::Envelope;
+use savestate::{Writer, Reader, SaveStateError};
 
 pub struct LfsrWave {
     /// True if the wave is generating samples
@@ -31,6 +32,26 @@ impl LfsrWave {
         }
     }
 
+    pub fn save_state(&self, w: &mut Writer) {
+        w.bool(self.running);
+        self.lfsr.save_state(w);
+        self.start_envelope.save_state(w);
+        self.envelope.save_state(w);
+        self.mode.save_state(w);
+        w.u32(self.remaining);
+    }
+
+    pub fn load_state(&mut self, r: &mut Reader) -> Result<(), SaveStateError> {
+        self.running        = try!(r.bool());
+        self.lfsr           = try!(Lfsr::load_state(r));
+        self.start_envelope = try!(Envelope::load_state(r));
+        self.envelope       = try!(Envelope::load_state(r));
+        self.mode           = try!(Mode::load_state(r));
+        self.remaining      = try!(r.u32());
+
+        Ok(())
+    }
+
     pub fn step(&mut self) {
         if self.mode == Mode::Counter {
             if self.remaining == 0 {
@@ -160,6 +181,21 @@ impl Lfsr {
         self.reg
     }
 
+    fn save_state(&self, w: &mut Writer) {
+        w.u8(self.reg);
+        w.u16(self.register);
+        w.u32(self.counter);
+    }
+
+    fn load_state(r: &mut Reader) -> Result<Lfsr, SaveStateError> {
+        let mut lfsr = Lfsr::from_reg(try!(r.u8()));
+
+        lfsr.register = try!(r.u16());
+        lfsr.counter  = try!(r.u32());
+
+        Ok(lfsr)
+    }
+
     fn step(&mut self) {
         self.counter += 1;
         self.counter %= self.step_duration;
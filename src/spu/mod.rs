@@ -6,6 +6,7 @@ use spu::rectangle_wave::{RectangleWave, DutyCycle, Sweep};
 use spu::envelope::Envelope;
 use spu::lfsr_wave::{LfsrWave, Lfsr};
 use spu::ram_wave::{RamWave, OutputLevel};
+use savestate::{Writer, Reader, SaveStateError};
 
 mod envelope;
 mod rectangle_wave;
@@ -576,6 +577,40 @@ impl Spu {
         }
     }
 
+    /// Serialize the SPU state, including the samples not yet sent
+    /// to the frontend
+    pub fn save_state(&self, w: &mut Writer) {
+        w.bool(self.enabled);
+        w.u32(self.divider);
+        w.bytes(&self.buffer);
+        w.u32(self.position as u32);
+        self.sound1.save_state(w);
+        self.sound2.save_state(w);
+        self.sound3.save_state(w);
+        self.sound4.save_state(w);
+        self.so1.save_state(w);
+        self.so2.save_state(w);
+    }
+
+    /// Restore a state serialized by `save_state`
+    pub fn load_state(&mut self, r: &mut Reader) -> Result<(), SaveStateError> {
+        self.enabled = try!(r.bool());
+        self.divider = try!(r.u32());
+        try!(r.bytes_into(&mut self.buffer));
+        self.position = try!(r.u32()) as usize;
+
+        if self.position >= self.buffer.len() {
+            return Err(SaveStateError::Invalid("bad sample buffer position"));
+        }
+
+        try!(self.sound1.load_state(r));
+        try!(self.sound2.load_state(r));
+        try!(self.sound3.load_state(r));
+        try!(self.sound4.load_state(r));
+        try!(self.so1.load_state(r));
+        self.so2.load_state(r)
+    }
+
     /// Reinitialize the entire SPU to default values. The only
     /// exception is the waveform RAM that remains untouched.
     fn reset(&mut self) {
@@ -597,6 +632,20 @@ enum Mode {
     Counter    = 1,
 }
 
+impl Mode {
+    fn save_state(self, w: &mut Writer) {
+        w.u8(self as u8);
+    }
+
+    fn load_state(r: &mut Reader) -> Result<Mode, SaveStateError> {
+        match try!(r.u8()) {
+            0 => Ok(Mode::Continuous),
+            1 => Ok(Mode::Counter),
+            _ => Err(SaveStateError::Invalid("bad sound mode")),
+        }
+    }
+}
+
 /// The Game Boy has two sound outputs: SO0 and SO1
 struct SoundOutput {
     /// Sound mixer for this output
@@ -633,6 +682,18 @@ impl SoundOutput {
     fn set_volume(&mut self, volume: OutputVolume) {
         self.volume = volume;
     }
+
+    fn save_state(&self, w: &mut Writer) {
+        w.u8(self.mixer.into_field());
+        w.u8(self.volume.into_field());
+    }
+
+    fn load_state(&mut self, r: &mut Reader) -> Result<(), SaveStateError> {
+        self.mixer  = Mixer::from_field(try!(r.u8()));
+        self.volume = OutputVolume::from_field(try!(r.u8()));
+
+        Ok(())
+    }
 }
 
 /// Each of the 4 sounds can be enabled or disabled
@@ -722,6 +783,7 @@ pub fn samples_per_steps(steps: u32) -> u32 {
 /// that the sum is in the range [0, 60], so a u8 is plenty enough.
 pub type Sample = u8;
 
+/// Buffer of samples sent to the audio backend
 pub type SampleBuffer = [Sample; SAMPLES_PER_BUFFER];
 
 /// We buffer the sound samples before we send them to the next
@@ -740,6 +802,7 @@ pub const SAMPLES_PER_BUFFER: usize = 0x200;
 /// frequency.
 const SAMPLER_DIVIDER: u32 = 95;
 
+/// Audio sample rate in Hz
 pub const SAMPLE_RATE: u32 = ::SYSCLK_FREQ as u32 / SAMPLER_DIVIDER;
 
 /// Depth of the channel between the Spu and the audio
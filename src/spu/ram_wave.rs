@@ -1,6 +1,7 @@
 //! Game Boy sound 3 generates a sound from samples stored in RAM
 
 use spu::{Sample, Mode};
+use savestate::{Writer, Reader, SaveStateError};
 
 pub struct RamWave {
     /// True if the sound is generating samples
@@ -48,6 +49,43 @@ impl RamWave {
         rw
     }
 
+    pub fn save_state(&self, w: &mut Writer) {
+        w.bool(self.running);
+        w.bool(self.enabled);
+        w.u32(self.remaining);
+        w.u8(self.output_level.into_field());
+        w.u16(self.divider);
+        w.u16(self.counter);
+        self.mode.save_state(w);
+        w.bytes(&self.samples);
+        w.u8(self.index);
+    }
+
+    pub fn load_state(&mut self, r: &mut Reader) -> Result<(), SaveStateError> {
+        self.running   = try!(r.bool());
+        self.enabled   = try!(r.bool());
+        self.remaining = try!(r.u32());
+
+        let level = try!(r.u8());
+
+        if level > 3 {
+            return Err(SaveStateError::Invalid("bad output level"));
+        }
+
+        self.output_level = OutputLevel::from_field(level);
+        self.divider      = try!(r.u16());
+        self.counter      = try!(r.u16());
+        self.mode         = try!(Mode::load_state(r));
+        try!(r.bytes_into(&mut self.samples));
+        self.index        = try!(r.u8());
+
+        if self.index as usize >= self.samples.len() {
+            return Err(SaveStateError::Invalid("bad sample index"));
+        }
+
+        Ok(())
+    }
+
     pub fn step(&mut self) {
         // Counter runs even if the channel is disabled
         if self.mode == Mode::Counter {
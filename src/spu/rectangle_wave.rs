@@ -4,6 +4,7 @@
 
 use spu::{Sample, Mode};
 use spu::envelope::Envelope;
+use savestate::{Writer, Reader, SaveStateError};
 
 pub struct RectangleWave {
     /// True if the sound is generating samples
@@ -89,6 +90,46 @@ impl RectangleWave {
         self.counter -= 1;
     }
 
+    pub fn save_state(&self, w: &mut Writer) {
+        w.bool(self.running);
+        w.u8(self.duty.into_field());
+        w.u16(self.counter);
+        w.u16(self.divider);
+        w.u8(self.phase);
+        self.start_envelope.save_state(w);
+        self.envelope.save_state(w);
+        self.mode.save_state(w);
+        w.u32(self.remaining);
+        self.sweep.save_state(w);
+    }
+
+    pub fn load_state(&mut self, r: &mut Reader) -> Result<(), SaveStateError> {
+        self.running = try!(r.bool());
+
+        let duty = try!(r.u8());
+
+        if duty > 3 {
+            return Err(SaveStateError::Invalid("bad duty cycle"));
+        }
+
+        self.duty    = DutyCycle::from_field(duty);
+        self.counter = try!(r.u16());
+        self.divider = try!(r.u16());
+        self.phase   = try!(r.u8());
+
+        if self.divider >= 0x800 || self.phase >= 8 {
+            return Err(SaveStateError::Invalid("bad rectangle wave state"));
+        }
+
+        self.start_envelope = try!(Envelope::load_state(r));
+        self.envelope       = try!(Envelope::load_state(r));
+        self.mode           = try!(Mode::load_state(r));
+        self.remaining      = try!(r.u32());
+        self.sweep          = try!(Sweep::load_state(r));
+
+        Ok(())
+    }
+
     pub fn sample(&self) -> Sample {
 
         if !self.running {
@@ -255,6 +296,19 @@ impl Sweep {
         (1 << 7) | (l << 4) | (dir << 3) | self.shift
     }
 
+    fn save_state(&self, w: &mut Writer) {
+        w.u8(self.into_reg());
+        w.u32(self.counter);
+    }
+
+    fn load_state(r: &mut Reader) -> Result<Sweep, SaveStateError> {
+        let mut sweep = Sweep::from_reg(try!(r.u8()));
+
+        sweep.counter = try!(r.u32());
+
+        Ok(sweep)
+    }
+
     /// Step through the Sweep state machine, returning the updated
     /// divider or None if the sound must be stopped
     fn step(&mut self, div: u16) -> Option<u16> {
@@ -0,0 +1,200 @@
+//! Image sources for the Game Boy Camera. They don't need any
+//! hardware so they work headless.
+
+use std::path::Path;
+use std::fs::File;
+use std::io::{Read, Error, ErrorKind};
+use std::io::Result as IoResult;
+
+use super::{ImageSource, CAMERA_WIDTH, CAMERA_HEIGHT};
+
+/// Generates a test pattern: a horizontal gradient with a
+/// checkerboard in the middle.
+pub struct TestPattern;
+
+impl ImageSource for TestPattern {
+    fn capture(&mut self, pixels: &mut [u8]) {
+        for y in 0..CAMERA_HEIGHT {
+            for x in 0..CAMERA_WIDTH {
+                let checker = x >= 32 && x < 96 && y >= 24 && y < 88;
+
+                let v =
+                    if checker {
+                        if (x / 8 + y / 8) % 2 == 0 { 0x00 } else { 0xff }
+                    } else {
+                        (x * 0xff / (CAMERA_WIDTH - 1)) as u8
+                    };
+
+                pixels[y * CAMERA_WIDTH + x] = v;
+            }
+        }
+    }
+}
+
+/// Returns the same still image for every capture
+pub struct StillImage {
+    pixels: Vec<u8>,
+}
+
+impl StillImage {
+    /// Load a still image from a binary PGM ("P5") file. The image is
+    /// scaled to the camera's resolution.
+    pub fn from_path(path: &Path) -> IoResult<StillImage> {
+        let mut f = try!(File::open(path));
+
+        let mut data = Vec::new();
+
+        try!(f.read_to_end(&mut data));
+
+        StillImage::from_pgm(&data)
+    }
+
+    fn from_pgm(data: &[u8]) -> IoResult<StillImage> {
+        let mut pos = 0;
+
+        let mut header = [0; 4];
+
+        for h in header.iter_mut() {
+            *h = match next_token(data, &mut pos) {
+                Some(t) => t,
+                None    => return Err(invalid("truncated PGM header")),
+            };
+        }
+
+        if !data[header[0]..].starts_with(b"P5") {
+            return Err(invalid("not a binary PGM file"));
+        }
+
+        let width  = try!(parse_number(data, header[1]));
+        let height = try!(parse_number(data, header[2]));
+        let maxval = try!(parse_number(data, header[3]));
+
+        if width == 0 || height == 0 || maxval == 0 || maxval > 0xff {
+            return Err(invalid("unsupported PGM format"));
+        }
+
+        // A single whitespace separates the header from the pixels
+        let start = pos + 1;
+
+        let end = width.checked_mul(height).and_then(|n| n.checked_add(start));
+
+        match end {
+            Some(end) if end <= data.len() => (),
+            Some(_) => return Err(invalid("truncated PGM data")),
+            None    => return Err(invalid("PGM image too large")),
+        }
+
+        let image = &data[start..];
+
+        let mut pixels = vec![0; CAMERA_WIDTH * CAMERA_HEIGHT];
+
+        // Nearest neighbour scaling
+        for y in 0..CAMERA_HEIGHT {
+            let sy = y * height / CAMERA_HEIGHT;
+
+            for x in 0..CAMERA_WIDTH {
+                let sx = x * width / CAMERA_WIDTH;
+
+                let v = image[sy * width + sx] as usize;
+
+                pixels[y * CAMERA_WIDTH + x] = (v * 0xff / maxval) as u8;
+            }
+        }
+
+        Ok(StillImage { pixels: pixels })
+    }
+}
+
+impl ImageSource for StillImage {
+    fn capture(&mut self, pixels: &mut [u8]) {
+        for (p, &v) in pixels.iter_mut().zip(self.pixels.iter()) {
+            *p = v;
+        }
+    }
+}
+
+fn invalid(msg: &'static str) -> Error {
+    Error::new(ErrorKind::InvalidInput, msg)
+}
+
+/// Skip whitespace and comments in a PGM header and return the
+/// offset of the next token. `pos` is updated to point right after
+/// it.
+fn next_token(data: &[u8], pos: &mut usize) -> Option<usize> {
+    loop {
+        match data.get(*pos) {
+            Some(&b'#') =>
+                while *pos < data.len() && data[*pos] != b'\n' {
+                    *pos += 1;
+                },
+            Some(c) if is_space(*c) => *pos += 1,
+            Some(_) => break,
+            None    => return None,
+        }
+    }
+
+    let start = *pos;
+
+    while *pos < data.len() && !is_space(data[*pos]) {
+        *pos += 1;
+    }
+
+    Some(start)
+}
+
+fn parse_number(data: &[u8], pos: usize) -> IoResult<usize> {
+    let mut n = 0usize;
+    let mut pos = pos;
+
+    while pos < data.len() && !is_space(data[pos]) {
+        let c = data[pos];
+
+        if c < b'0' || c > b'9' {
+            return Err(invalid("invalid number in PGM header"));
+        }
+
+        let digit = (c - b'0') as usize;
+
+        n = match n.checked_mul(10).and_then(|n| n.checked_add(digit)) {
+            Some(n) => n,
+            None    => return Err(invalid("number too large in PGM header")),
+        };
+
+        pos += 1;
+    }
+
+    Ok(n)
+}
+
+fn is_space(c: u8) -> bool {
+    c == b' ' || c == b'\t' || c == b'\n' || c == b'\r'
+}
+
+#[cfg(test)]
+mod tests {
+    use super::StillImage;
+    use ui::{ImageSource, CAMERA_WIDTH, CAMERA_HEIGHT};
+
+    #[test]
+    fn pgm() {
+        // 2x1 image, black then white
+        let pgm = b"P5\n# comment\n2 1\n255\n\x00\xff";
+
+        let mut image = StillImage::from_pgm(pgm).unwrap();
+
+        let mut pixels = vec![0x55; CAMERA_WIDTH * CAMERA_HEIGHT];
+
+        image.capture(&mut pixels);
+
+        assert!(pixels[0] == 0x00);
+        assert!(pixels[CAMERA_WIDTH - 1] == 0xff);
+        assert!(pixels[CAMERA_WIDTH * CAMERA_HEIGHT - 1] == 0xff);
+
+        // Overflowing headers are rejected
+        assert!(StillImage::from_pgm(b"P5 99999999999999999999999 1 255\n")
+                .is_err());
+        assert!(StillImage::from_pgm(b"P5 4294967296 4294967296 255\n")
+                .is_err());
+        assert!(StillImage::from_pgm(b"P5 2 2 255\n\x00").is_err());
+    }
+}
@@ -1,16 +1,15 @@
 //! User Interface. Objects used to display the GB Screen, get user
 //! input etc...
 
+#[cfg(feature = "sdl2_frontend")]
 pub mod sdl2;
+pub mod camera;
+pub mod png;
 
 /// GB screen. Screen resolution is always 160x144
 pub trait Display {
-    /// Clear the display
-    fn clear(&mut self);
-    /// Paint pixel at (x, y) using `color`. (0, 0) is top left.
-    fn set_pixel(&mut self, x: u32, y: u32, color: ::gpu::Color);
-    /// Current frame is done and can be displayed.
-    fn flip(&mut self);
+    /// Display a complete frame
+    fn flip(&mut self, frame: &::gpu::Frame);
 }
 
 /// Audio interface
@@ -21,6 +20,27 @@ pub trait Audio {
     fn adjust_resampling(&mut self, in_samples: u32);
 }
 
+/// Force feedback interface, used to drive the rumble motor found in
+/// certain cartridges
+pub trait Rumble {
+    /// Turn the rumble motor on or off
+    fn set_rumble(&self, on: bool);
+}
+
+/// Image sensor used by the Game Boy Camera. It's part of the
+/// cartridge so it has to be `Send` to let the emulator run in its
+/// own thread.
+pub trait ImageSource: Send {
+    /// Fill `pixels` with a `CAMERA_WIDTH`x`CAMERA_HEIGHT` 8bit
+    /// grayscale image, row by row. 0 is black, 0xff is white.
+    fn capture(&mut self, pixels: &mut [u8]);
+}
+
+/// Width of the Game Boy Camera's picture in pixels
+pub const CAMERA_WIDTH:  usize = 128;
+/// Height of the Game Boy Camera's picture in pixels
+pub const CAMERA_HEIGHT: usize = 112;
+
 /// Special events that need to be handled synchronously (instead of
 /// waiting for the GB program to come check the INPUT register)
 pub enum Event {
@@ -28,6 +48,15 @@ pub enum Event {
     None,
     /// Shutdown the emulator
     PowerOff,
+    /// Turn the cheat codes on or off
+    ToggleCheats,
+    /// Select the save state slot used by `SaveState` and
+    /// `LoadState`
+    SelectSlot(u8),
+    /// Save the state of the machine in the current slot
+    SaveState,
+    /// Restore the state saved in the current slot
+    LoadState,
 }
 
 /// Description of a button's state
@@ -40,6 +69,7 @@ pub enum ButtonState {
 }
 
 impl ButtonState {
+    /// Return `true` if the key is pressed
     pub fn is_down(self) -> bool {
         match self {
             ButtonState::Down => true,
@@ -51,13 +81,21 @@ impl ButtonState {
 /// State of all the GB buttons
 #[derive(Debug,Clone,Copy)]
 pub struct Buttons {
+    /// D-pad up
     pub up:        ButtonState,
+    /// D-pad down
     pub down:      ButtonState,
+    /// D-pad left
     pub left:      ButtonState,
+    /// D-pad right
     pub right:     ButtonState,
+    /// A button
     pub a:         ButtonState,
+    /// B button
     pub b:         ButtonState,
+    /// Start button
     pub start:     ButtonState,
+    /// Select button
     pub select:    ButtonState,
     /// State of the interrupt that occurs at the moment a button is
     /// pressed
@@ -65,6 +103,7 @@ pub struct Buttons {
 }
 
 impl Buttons {
+    /// Create a `Buttons` with all the buttons in `default_state`
     pub fn new(default_state: ButtonState) -> Buttons {
         Buttons {
             a:         default_state,
@@ -80,39 +119,192 @@ impl Buttons {
     }
 }
 
-#[cfg(test)]
+/// Tilt of the console, used by cartridges with an accelerometer.
+/// Each axis ranges from -32768 to 32767 (same as a controller's
+/// analog stick). Positive `x` means tilted to the right, positive
+/// `y` means tilted towards the player.
+#[derive(Debug,Clone,Copy)]
+pub struct Tilt {
+    /// Left/right axis
+    pub x: i16,
+    /// Away from/towards the player axis
+    pub y: i16,
+}
+
+impl Tilt {
+    /// Return a `Tilt` for a console laying flat
+    pub fn new() -> Tilt {
+        Tilt { x: 0, y: 0 }
+    }
+}
+
 pub mod dummy {
-    //! Dummy implementations of the user interface for use in tests
-    //! and benchmarks
+    //! Dummy implementations of the user interface, used by the
+    //! tests and the headless frontend
 
     use std::cell::Cell;
 
-    pub struct DummyDisplay;
+    use gpu::{Color, Frame};
+    use super::{Buttons, ButtonState, Tilt};
 
-    impl super::Display for DummyDisplay {
-        fn clear(&mut self) {
+    /// Display keeping a copy of the last frame instead of showing
+    /// it
+    pub struct DummyDisplay {
+        frame:  Vec<Color>,
+        flips:  u32,
+    }
+
+    impl DummyDisplay {
+        /// Create a display showing a white screen
+        pub fn new() -> DummyDisplay {
+            DummyDisplay {
+                frame:  vec![Color::White; ::SCREEN_WIDTH * ::SCREEN_HEIGHT],
+                flips:  0,
+            }
         }
 
-        fn set_pixel(&mut self, _: u32, _: u32, _: ::gpu::Color) {
+        /// Return the last frame displayed
+        pub fn frame(&self) -> &[Color] {
+            &self.frame
         }
 
-        fn flip(&mut self) {
+        /// Return the number of frames displayed so far
+        pub fn flips(&self) -> u32 {
+            self.flips
+        }
+    }
+
+    impl super::Display for DummyDisplay {
+        fn flip(&mut self, frame: &Frame) {
+            self.frame.clear();
+            self.frame.extend(frame.iter().cloned());
+            self.flips += 1;
         }
     }
 
+    /// Controller driven by a script instead of a human
     pub struct DummyController {
-        buttons: Cell<super::Buttons>,
+        buttons: Cell<Buttons>,
+        tilt:    Cell<Tilt>,
+        /// List of (frame, buttons) sorted by frame
+        script:  Vec<(u32, Buttons)>,
     }
 
     impl DummyController {
+        /// Create a controller with all the buttons released
         pub fn new() -> DummyController {
             DummyController {
-                buttons: Cell::new(super::Buttons::new(super::ButtonState::Up)),
+                buttons: Cell::new(Buttons::new(ButtonState::Up)),
+                tilt:    Cell::new(Tilt::new()),
+                script:  Vec::new(),
+            }
+        }
+
+        /// Create a controller pressing the buttons described in
+        /// `script`: a comma separated list of `FRAME:BUTTONS`
+        /// entries where `BUTTONS` is a list of button names
+        /// (`a`, `b`, `start`, `select`, `up`, `down`, `left`,
+        /// `right`) separated by `+`. The buttons are held from
+        /// `FRAME` until the next entry, an empty list releases
+        /// everything. For instance `60:start,65:,200:a+right`.
+        pub fn from_script(script: &str) -> Result<DummyController, String> {
+            let mut controller = DummyController::new();
+
+            for entry in script.split(',').filter(|e| !e.is_empty()) {
+                let mut fields = entry.splitn(2, ':');
+
+                let frame = fields.next().unwrap();
+                let names = fields.next().unwrap_or("");
+
+                let frame =
+                    match frame.trim().parse() {
+                        Ok(f)  => f,
+                        Err(_) => return Err(format!("invalid frame '{}'",
+                                                     frame)),
+                    };
+
+                let mut buttons = Buttons::new(ButtonState::Up);
+
+                for name in names.split('+').map(|n| n.trim()) {
+                    let button =
+                        match name {
+                            ""       => continue,
+                            "a"      => &mut buttons.a,
+                            "b"      => &mut buttons.b,
+                            "start"  => &mut buttons.start,
+                            "select" => &mut buttons.select,
+                            "up"     => &mut buttons.up,
+                            "down"   => &mut buttons.down,
+                            "left"   => &mut buttons.left,
+                            "right"  => &mut buttons.right,
+                            _        =>
+                                return Err(format!("unknown button '{}'",
+                                                   name)),
+                        };
+
+                    *button = ButtonState::Down;
+                }
+
+                controller.script.push((frame, buttons));
+            }
+
+            controller.script.sort_by(|a, b| a.0.cmp(&b.0));
+
+            Ok(controller)
+        }
+
+        /// Update the buttons for `frame` according to the script
+        pub fn update(&self, frame: u32) {
+            let current = self.script.iter()
+                .take_while(|&&(f, _)| f <= frame)
+                .last();
+
+            if let Some(&(_, buttons)) = current {
+                self.buttons.set(buttons);
             }
         }
 
-        pub fn buttons(&self) -> &Cell<super::Buttons> {
+        /// State of the buttons
+        pub fn buttons(&self) -> &Cell<Buttons> {
             &self.buttons
         }
+
+        /// Tilt of the console
+        pub fn tilt(&self) -> &Cell<Tilt> {
+            &self.tilt
+        }
+
+        /// Scripted tilt input
+        pub fn set_tilt(&self, x: i16, y: i16) {
+            self.tilt.set(Tilt { x: x, y: y });
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::DummyController;
+
+        #[test]
+        fn script() {
+            let c = DummyController::from_script("10:a+start,20:,15:down")
+                .unwrap();
+
+            c.update(0);
+            assert!(!c.buttons().get().a.is_down());
+
+            c.update(12);
+            assert!(c.buttons().get().a.is_down());
+            assert!(c.buttons().get().start.is_down());
+
+            c.update(15);
+            assert!(!c.buttons().get().a.is_down());
+            assert!(c.buttons().get().down.is_down());
+
+            c.update(100);
+            assert!(!c.buttons().get().down.is_down());
+
+            assert!(DummyController::from_script("10:x").is_err());
+            assert!(DummyController::from_script("a:b").is_err());
+        }
     }
 }
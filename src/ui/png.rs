@@ -0,0 +1,115 @@
+//! Minimal PNG encoder used to dump the screen
+
+use std::io::{Write, Result};
+
+use flate2::Compression;
+use flate2::write::ZlibEncoder;
+
+use crc::crc32;
+use gpu::Color;
+
+/// Write `frame` as a `SCREEN_WIDTH`x`SCREEN_HEIGHT` 8bit grayscale
+/// PNG
+pub fn write_frame<W: Write>(out: &mut W, frame: &[Color]) -> Result<()> {
+    assert!(frame.len() == ::SCREEN_WIDTH * ::SCREEN_HEIGHT);
+
+    try!(out.write_all(b"\x89PNG\r\n\x1a\n"));
+
+    let mut ihdr = Vec::new();
+
+    push_u32(&mut ihdr, ::SCREEN_WIDTH as u32);
+    push_u32(&mut ihdr, ::SCREEN_HEIGHT as u32);
+    // 8bit depth, grayscale, deflate, adaptive filtering, no
+    // interlacing
+    ihdr.extend([8, 0, 0, 0, 0].iter().cloned());
+
+    try!(write_chunk(out, b"IHDR", &ihdr));
+
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::Default);
+
+    for line in frame.chunks(::SCREEN_WIDTH) {
+        // Each line starts with its filter type, 0 is none
+        let mut row = vec![0];
+
+        row.extend(line.iter().map(|&c| gray(c)));
+
+        try!(encoder.write_all(&row));
+    }
+
+    let idat = try!(encoder.finish());
+
+    try!(write_chunk(out, b"IDAT", &idat));
+
+    write_chunk(out, b"IEND", &[])
+}
+
+/// Same shades as the SDL2 display
+fn gray(c: Color) -> u8 {
+    match c {
+        Color::Black     => 0x00,
+        Color::DarkGrey  => 0x55,
+        Color::LightGrey => 0xab,
+        Color::White     => 0xff,
+    }
+}
+
+fn write_chunk<W: Write>(out: &mut W, kind: &[u8; 4], data: &[u8]) -> Result<()> {
+    let mut chunk = Vec::with_capacity(data.len() + 12);
+
+    push_u32(&mut chunk, data.len() as u32);
+    chunk.extend(kind.iter().cloned());
+    chunk.extend(data.iter().cloned());
+
+    // The CRC covers the type and the data but not the length
+    let crc = crc32(&chunk[4..]);
+
+    push_u32(&mut chunk, crc);
+
+    out.write_all(&chunk)
+}
+
+/// PNG integers are big endian
+fn push_u32(v: &mut Vec<u8>, n: u32) {
+    v.push((n >> 24) as u8);
+    v.push((n >> 16) as u8);
+    v.push((n >> 8) as u8);
+    v.push(n as u8);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use flate2::read::ZlibDecoder;
+
+    use gpu::Color;
+    use super::write_frame;
+
+    #[test]
+    fn encode() {
+        let frame = vec![Color::DarkGrey; ::SCREEN_WIDTH * ::SCREEN_HEIGHT];
+
+        let mut png = Vec::new();
+
+        write_frame(&mut png, &frame).unwrap();
+
+        assert!(&png[..8] == b"\x89PNG\r\n\x1a\n");
+        assert!(&png[12..16] == b"IHDR");
+        assert!(&png[png.len() - 8..png.len() - 4] == b"IEND");
+
+        // IDAT right after the 25 bytes of the IHDR chunk
+        let idat = &png[33..];
+        let len = ((idat[0] as usize) << 24) | ((idat[1] as usize) << 16) |
+                  ((idat[2] as usize) << 8) | idat[3] as usize;
+
+        assert!(&idat[4..8] == b"IDAT");
+
+        let mut pixels = Vec::new();
+
+        ZlibDecoder::new(&idat[8..8 + len]).read_to_end(&mut pixels).unwrap();
+
+        assert!(pixels.len() == (::SCREEN_WIDTH + 1) * ::SCREEN_HEIGHT);
+        assert!(pixels[0] == 0);
+        assert!(pixels[1] == 0x55);
+    }
+}
@@ -24,12 +24,15 @@ impl AudioCallback for Reader {
     }
 }
 
+/// Audio output, plays the samples generated by the SPU
 pub struct Audio {
     dev:   AudioDevice<Reader>,
     async: Arc<Async<Sample>>
 }
 
 impl Audio {
+    /// Open the audio device, fed by the SPU through `channel`.
+    /// The device starts paused.
     pub fn new(channel: Receiver<::spu::SampleBuffer>) -> Audio {
 
         let resampler = Resampler::new(channel, SAMPLE_RATE);
@@ -58,6 +61,7 @@ impl Audio {
         }
     }
 
+    /// Start the playback
     pub fn start(&self) {
         self.dev.resume();
     }
@@ -7,16 +7,26 @@ use sdl2::controller::{GameController, Button, Axis};
 use sdl2::sdl::Sdl;
 
 use ui::ButtonState;
+use ui::sdl2::haptic::Haptic;
 
+/// Keyboard and game controller input
 pub struct Controller {
     buttons:      Cell<::ui::Buttons>,
+    /// Console tilt, driven by the right analog stick
+    tilt:         Cell<::ui::Tilt>,
+    /// `true` while the rewind key is held down
+    rewind:       Cell<bool>,
     #[allow(dead_code)]
     controller:   Option<controller::GameController>,
+    /// Force feedback device used for cartridges with a rumble motor
+    haptic:       Option<Haptic>,
     x_axis_state: Cell<AxisState>,
     y_axis_state: Cell<AxisState>,
 }
 
 impl Controller {
+    /// Create a controller, using the first game controller found
+    /// if there's any
     pub fn new() -> Controller {
         // Attempt to add a game controller
 
@@ -55,14 +65,25 @@ impl Controller {
             None    => println!("No controller found"),
         }
 
+        let haptic = Haptic::open();
+
+        if haptic.is_some() {
+            println!("Rumble support enabled");
+        }
+
         Controller {
             buttons:      Cell::new(::ui::Buttons::new(ButtonState::Up)),
+            tilt:         Cell::new(::ui::Tilt::new()),
+            rewind:       Cell::new(false),
             controller:   controller,
+            haptic:       haptic,
             x_axis_state: Cell::new(AxisState::Neutral),
             y_axis_state: Cell::new(AxisState::Neutral),
         }
     }
 
+    /// Process the pending SDL events and return the last frontend
+    /// event among them
     pub fn update(&self, sdl2: &Sdl) -> ::ui::Event {
         let mut event = ::ui::Event::None;
 
@@ -72,8 +93,17 @@ impl Controller {
             match e {
                 Event::KeyDown { keycode: KeyCode::Escape, .. } =>
                     event = ::ui::Event::PowerOff,
+                Event::KeyDown { keycode: KeyCode::F2, .. } =>
+                    event = ::ui::Event::ToggleCheats,
+                Event::KeyDown { keycode: KeyCode::F5, .. } =>
+                    event = ::ui::Event::SaveState,
+                Event::KeyDown { keycode: KeyCode::F8, .. } =>
+                    event = ::ui::Event::LoadState,
                 Event::KeyDown { keycode: key, .. } =>
-                    self.update_key(key, ButtonState::Down),
+                    match slot_key(key) {
+                        Some(slot) => event = ::ui::Event::SelectSlot(slot),
+                        None       => self.update_key(key, ButtonState::Down),
+                    },
                 Event::KeyUp { keycode: key, .. } =>
                     self.update_key(key, ButtonState::Up),
                 Event::ControllerButtonDown{ button, .. } =>
@@ -91,10 +121,29 @@ impl Controller {
         event
     }
 
+    /// State of the Game Boy buttons
     pub fn buttons(&self) -> &Cell<::ui::Buttons> {
         &self.buttons
     }
 
+    /// Tilt of the console
+    pub fn tilt(&self) -> &Cell<::ui::Tilt> {
+        &self.tilt
+    }
+
+    /// Return `true` while the rewind key is held down
+    pub fn rewind(&self) -> bool {
+        self.rewind.get()
+    }
+
+    /// Start or stop the controller's rumble effect if it supports
+    /// it
+    pub fn set_rumble(&self, on: bool) {
+        if let Some(ref h) = self.haptic {
+            h.set_rumble(on);
+        }
+    }
+
     /// Update key state. For now keybindings are hardcoded.
     fn update_key(&self, key: KeyCode, state: ButtonState) {
         let mut b = self.buttons.get();
@@ -108,6 +157,7 @@ impl Controller {
             KeyCode::LCtrl     => b.b      = state,
             KeyCode::Return    => b.start  = state,
             KeyCode::RShift    => b.select = state,
+            KeyCode::Backspace => self.rewind.set(state.is_down()),
             _                  => (),
         }
 
@@ -127,15 +177,18 @@ impl Controller {
             Button::DPadDown  => b.down   = state,
             Button::Start     => b.start  = state,
             Button::Back      => b.select = state,
+            Button::LeftShoulder => self.rewind.set(state.is_down()),
             _                 => (),
         }
 
         self.buttons.set(b);
     }
 
-    /// Map left stick X/Y to directional buttons
+    /// Map left stick X/Y to directional buttons and right stick X/Y
+    /// to the console tilt
     fn update_axis(&self, axis: Axis, val: i16) {
         let mut b = self.buttons.get();
+        let mut t = self.tilt.get();
 
         let state = AxisState::from_value(val);
 
@@ -156,10 +209,13 @@ impl Controller {
                     b.down = state.down_if_positive();
                 }
             }
+            Axis::RightX => t.x = val,
+            Axis::RightY => t.y = val,
             _ => (),
         }
 
         self.buttons.set(b);
+        self.tilt.set(t);
     }
 }
 
@@ -202,3 +258,23 @@ impl AxisState {
 /// avoid spurious events this constant says how far from 0 the axis
 /// has to move for us to register the event.
 const AXIS_DEAD_ZONE: i16 = 10_000;
+
+/// Return the save state slot selected by `key` if it's one of the
+/// number keys
+fn slot_key(key: KeyCode) -> Option<u8> {
+    let slot = match key {
+        KeyCode::Num0 => 0,
+        KeyCode::Num1 => 1,
+        KeyCode::Num2 => 2,
+        KeyCode::Num3 => 3,
+        KeyCode::Num4 => 4,
+        KeyCode::Num5 => 5,
+        KeyCode::Num6 => 6,
+        KeyCode::Num7 => 7,
+        KeyCode::Num8 => 8,
+        KeyCode::Num9 => 9,
+        _             => return None,
+    };
+
+    Some(slot)
+}
@@ -1,23 +1,26 @@
 use sdl2::video::Window;
-use sdl2::render::Renderer;
-use sdl2::pixels::Color::RGB;
-use sdl2::rect::{Point, Rect};
+use sdl2::render::{Renderer, Texture};
+use sdl2::pixels::PixelFormatEnum;
 use sdl2::sdl::Sdl;
 
-use gpu::Color;
+use gpu::{Color, Frame, SCREEN_WIDTH, SCREEN_HEIGHT};
 
+/// SDL2 window displaying the frames
 pub struct Display {
     renderer: Renderer<'static>,
-    /// Upscaling factor, log2.
-    upscale:  u8,
+    /// Streaming texture the frames are uploaded to. It's scaled to
+    /// the size of the window when it's rendered.
+    texture:  Texture,
 }
 
 impl Display {
+    /// Open a window `2^upscale` times bigger than the Game Boy
+    /// screen
     pub fn new(sdl2: &Sdl, upscale: u8) -> Display {
         let up = 1 << (upscale as usize);
 
-        let xres = 160 * up;
-        let yres = 144 * up;
+        let xres = SCREEN_WIDTH  as i32 * up;
+        let yres = SCREEN_HEIGHT as i32 * up;
 
         let window = match Window::new(sdl2, "gb-rs",
                                        ::sdl2::video::WindowPos::PosCentered,
@@ -35,45 +38,50 @@ impl Display {
             Err(err) => panic!("failed to create SDL2 renderer: {}", err)
         };
 
-        Display { renderer: renderer, upscale: upscale }
+        let texture =
+            match renderer.create_texture_streaming(PixelFormatEnum::RGB24,
+                                                    (SCREEN_WIDTH  as i32,
+                                                     SCREEN_HEIGHT as i32)) {
+            Ok(texture) => texture,
+            Err(err) => panic!("failed to create SDL2 texture: {}", err)
+        };
+
+        Display { renderer: renderer, texture: texture }
     }
 }
 
 impl ::ui::Display for Display {
-    fn clear(&mut self) {
-        let mut drawer = self.renderer.drawer();
-
-        let _ = drawer.set_draw_color(RGB(0xff, 0x00, 0x00));
-        let _ = drawer.clear();
-    }
-
-    fn set_pixel(&mut self, x: u32, y: u32, color: Color) {
-        let color = match color {
-            Color::Black     => RGB(0x00, 0x00, 0x00),
-            Color::DarkGrey  => RGB(0x55, 0x55, 0x55),
-            Color::LightGrey => RGB(0xab, 0xab, 0xab),
-            Color::White     => RGB(0xff, 0xff, 0xff),
-        };
+    fn flip(&mut self, frame: &Frame) {
+        // Convert the frame to RGB24 straight into the texture
+        let upload = self.texture.with_lock(None, |pixels: &mut [u8],
+                                                   pitch: usize| {
+            for (y, line) in frame.chunks(SCREEN_WIDTH).enumerate() {
+                let line_pixels = &mut pixels[y * pitch..];
+
+                for (x, &color) in line.iter().enumerate() {
+                    let shade = match color {
+                        Color::Black     => 0x00,
+                        Color::DarkGrey  => 0x55,
+                        Color::LightGrey => 0xab,
+                        Color::White     => 0xff,
+                    };
+
+                    for c in &mut line_pixels[x * 3..x * 3 + 3] {
+                        *c = shade;
+                    }
+                }
+            }
+        });
+
+        if let Err(e) = upload {
+            error!("Couldn't upload frame: {}", e);
+            return;
+        }
 
         let mut drawer = self.renderer.drawer();
 
-        let _ = drawer.set_draw_color(color);
-
-        if self.upscale == 0 {
-            let _ = drawer.draw_point(Point::new(x as i32, y as i32));
-        } else {
-            let up = 1 << (self.upscale as usize);
-
-            // Translate coordinates
-            let x = x as i32 * up;
-            let y = y as i32 * up;
-
-            let _ = drawer.fill_rect(Rect::new(x, y, up, up));
-        }
-    }
-
-    fn flip(&mut self) {
-        self.renderer.drawer().present();
-        self.clear();
+        let _ = drawer.clear();
+        let _ = drawer.copy(&self.texture, None, None);
+        drawer.present();
     }
 }
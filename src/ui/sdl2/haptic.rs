@@ -0,0 +1,81 @@
+//! Rumble support. The `sdl2` crate doesn't wrap the SDL haptic API
+//! yet so we have to call it directly through `sdl2-sys`. This is the
+//! only unsafe code in the emulator.
+
+use std::cell::Cell;
+
+use sdl2_sys::haptic as ll;
+
+/// Haptic device able to play rumble effects
+pub struct Haptic {
+    /// Raw SDL haptic device handle
+    raw: *mut ll::SDL_Haptic,
+    /// `true` if the rumble effect is currently playing
+    on:  Cell<bool>,
+}
+
+impl Haptic {
+    /// Open the first haptic device supporting rumble effects. Returns
+    /// `None` if none can be found.
+    pub fn open() -> Option<Haptic> {
+        let n = unsafe { ll::SDL_NumHaptics() };
+
+        for id in 0..n {
+            let raw = unsafe { ll::SDL_HapticOpen(id) };
+
+            if raw.is_null() {
+                continue;
+            }
+
+            let usable = unsafe {
+                ll::SDL_HapticRumbleSupported(raw) == 1 &&
+                ll::SDL_HapticRumbleInit(raw) == 0
+            };
+
+            if usable {
+                return Some(Haptic {
+                    raw: raw,
+                    on:  Cell::new(false),
+                });
+            }
+
+            unsafe { ll::SDL_HapticClose(raw) };
+        }
+
+        None
+    }
+
+    /// Start or stop the rumble effect
+    pub fn set_rumble(&self, on: bool) {
+        if on == self.on.get() {
+            return;
+        }
+
+        self.on.set(on);
+
+        let r = unsafe {
+            match on {
+                true  => ll::SDL_HapticRumblePlay(self.raw,
+                                                  RUMBLE_STRENGTH,
+                                                  HAPTIC_INFINITY),
+                false => ll::SDL_HapticRumbleStop(self.raw),
+            }
+        };
+
+        if r != 0 {
+            error!("Couldn't update rumble effect");
+        }
+    }
+}
+
+impl Drop for Haptic {
+    fn drop(&mut self) {
+        unsafe { ll::SDL_HapticClose(self.raw) };
+    }
+}
+
+/// Rumble strength, between 0 and 1
+const RUMBLE_STRENGTH: f32 = 0.75;
+
+/// Effect duration meaning "play until stopped"
+const HAPTIC_INFINITY: u32 = 0xffffffff;
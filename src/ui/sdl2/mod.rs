@@ -9,17 +9,21 @@ pub use ui::sdl2::audio::Audio;
 mod display;
 mod audio;
 mod controller;
+mod haptic;
 
+/// SDL2 context, owns the controller and creates the display
 pub struct Context {
     sdl2: ::sdl2::sdl::Sdl,
     controller: controller::Controller,
 }
 
 impl Context {
+    /// Initialize SDL2 and open the first game controller, if any
     pub fn new() -> Context {
         let sdl2 =
             ::sdl2::init(::sdl2::INIT_VIDEO |
                          ::sdl2::INIT_GAME_CONTROLLER |
+                         ::sdl2::INIT_HAPTIC |
                          ::sdl2::INIT_AUDIO).unwrap();
 
         Context {
@@ -28,15 +32,36 @@ impl Context {
         }
     }
 
+    /// Open a window `2^upscale` times bigger than the Game Boy
+    /// screen
     pub fn new_display(&self, upscale: u8) -> display::Display {
         display::Display::new(&self.sdl2, upscale)
     }
 
+    /// State of the Game Boy buttons
     pub fn buttons(&self) -> &Cell<::ui::Buttons> {
         self.controller.buttons()
     }
 
+    /// Tilt of the console
+    pub fn tilt(&self) -> &Cell<::ui::Tilt> {
+        self.controller.tilt()
+    }
+
+    /// Return `true` while the rewind key is held down
+    pub fn rewind(&self) -> bool {
+        self.controller.rewind()
+    }
+
+    /// Process the pending SDL events and return the last frontend
+    /// event among them
     pub fn update_buttons(&self) -> ::ui::Event {
         self.controller.update(&self.sdl2)
     }
 }
+
+impl ::ui::Rumble for Context {
+    fn set_rumble(&self, on: bool) {
+        self.controller.set_rumble(on)
+    }
+}
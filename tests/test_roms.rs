@@ -0,0 +1,212 @@
+//! Run the accuracy test ROMs (Blargg's and Mooneye's) found in the
+//! directory pointed to by `GB_TEST_ROMS`. The test is skipped if the
+//! variable isn't set, the ROMs aren't distributed with gb-rs.
+//!
+//! Every ".gb" file found in the directory (recursively) must pass,
+//! so point it at the tests the emulator is expected to pass. Build
+//! with `--release` or it'll take a while:
+//!
+//!     GB_TEST_ROMS=~/gb-test-roms cargo test --release --test test_roms \
+//!         -- --nocapture
+
+extern crate gb_rs;
+
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use gb_rs::GameBoy;
+use gb_rs::cartridge::{Cartridge, LoadOptions};
+
+/// Give up after two minutes of emulated time, in frames
+const MAX_FRAMES: u32 = 60 * 120;
+
+/// Blargg's tests write this signature at 0xa001 when they report
+/// their status in the cartridge RAM
+const BLARGG_SIGNATURE: [u8; 3] = [0xde, 0xb0, 0x61];
+
+/// Value of the status byte at 0xa000 while the test is running
+const BLARGG_RUNNING: u8 = 0x80;
+
+enum Verdict {
+    Pass,
+    Fail(String),
+    Timeout,
+}
+
+impl fmt::Display for Verdict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Verdict::Pass        => write!(f, "pass"),
+            Verdict::Fail(ref m) => write!(f, "FAIL ({})", m),
+            Verdict::Timeout     => write!(f, "FAIL (timeout)"),
+        }
+    }
+}
+
+#[test]
+fn accuracy_roms() {
+    let dir =
+        match env::var("GB_TEST_ROMS") {
+            Ok(d)  => PathBuf::from(d),
+            Err(_) => {
+                println!("GB_TEST_ROMS not set, skipping the test ROMs");
+                return;
+            }
+        };
+
+    if !dir.is_dir() {
+        println!("{} not found, skipping the test ROMs", dir.display());
+        return;
+    }
+
+    let mut roms = Vec::new();
+
+    find_roms(&dir, &mut roms);
+
+    roms.sort();
+
+    let mut failures = 0;
+
+    for rom in &roms {
+        let verdict = run(rom);
+
+        let name = rom.strip_prefix(&dir).unwrap_or(rom);
+
+        println!("{:<60} {}", name.display(), verdict);
+
+        match verdict {
+            Verdict::Pass => (),
+            _             => failures += 1,
+        }
+    }
+
+    println!("{} ROMs, {} passed, {} failed",
+             roms.len(), roms.len() - failures, failures);
+
+    assert!(failures == 0);
+}
+
+fn find_roms(dir: &Path, roms: &mut Vec<PathBuf>) {
+    let entries = fs::read_dir(dir)
+        .ok().expect("can't read the test ROM directory");
+
+    for entry in entries {
+        let path = entry.unwrap().path();
+
+        if path.is_dir() {
+            find_roms(&path, roms);
+        } else if path.extension().map_or(false, |e| e == "gb") {
+            roms.push(path);
+        }
+    }
+}
+
+fn run(path: &Path) -> Verdict {
+    let mut options = LoadOptions::new();
+
+    // Start from blank RAM and don't litter the ROM directory with
+    // save files
+    options.no_save = true;
+
+    let cart =
+        match Cartridge::from_path_with_options(path, &options) {
+            Ok(c)  => c,
+            Err(e) => return Verdict::Fail(format!("can't load ROM: {}", e)),
+        };
+
+    let mut gb = GameBoy::new(cart);
+
+    let mut serial = String::new();
+    let mut frames = 0;
+
+    while frames < MAX_FRAMES {
+        // Mooneye: `LD B, B` once done, with the Fibonacci sequence
+        // in the registers if the test passed. Other ROMs (Blargg's
+        // "06-ld r,r" for one) run that instruction as well so we
+        // keep going if the registers don't hold a result.
+        let breakpoint = gb.run_until_breakpoint();
+
+        if breakpoint {
+            if let Some(verdict) = mooneye_verdict(&gb) {
+                return verdict;
+            }
+        } else {
+            // Stopping on a breakpoint cuts the frame short, only
+            // count the frames that ran to the end
+            frames += 1;
+        }
+
+        while let Some(_) = gb.next_audio_buffer() {
+        }
+
+        // Blargg: "Passed" or "Failed" on the serial link...
+        let output = gb.take_serial_output();
+
+        serial.push_str(&String::from_utf8_lossy(&output));
+
+        if serial.contains("Passed") {
+            return Verdict::Pass;
+        }
+
+        if let Some(pos) = serial.find("Failed") {
+            // The number of the failed test follows, wait for the end
+            // of the line or for the output to stop
+            let stalled = !breakpoint && output.is_empty();
+
+            if serial[pos..].contains('\n') || stalled {
+                return Verdict::Fail(serial.trim().to_string());
+            }
+        }
+
+        // ... or in the cartridge RAM for the tests that don't use
+        // the serial link
+        if let Some(verdict) = blargg_ram_verdict(&gb) {
+            return verdict;
+        }
+    }
+
+    if serial.contains("Failed") {
+        return Verdict::Fail(serial.trim().to_string());
+    }
+
+    Verdict::Timeout
+}
+
+fn mooneye_verdict(gb: &GameBoy) -> Option<Verdict> {
+    let r = gb.registers();
+
+    let regs = [r.b, r.c, r.d, r.e, r.h, r.l];
+
+    if regs == [3, 5, 8, 13, 21, 34] {
+        Some(Verdict::Pass)
+    } else if regs == [0x42; 6] {
+        Some(Verdict::Fail("test failed".to_string()))
+    } else {
+        None
+    }
+}
+
+fn blargg_ram_verdict(gb: &GameBoy) -> Option<Verdict> {
+    let signature = [gb.peek(0xa001), gb.peek(0xa002), gb.peek(0xa003)];
+
+    if signature != BLARGG_SIGNATURE {
+        return None;
+    }
+
+    match gb.peek(0xa000) {
+        BLARGG_RUNNING => None,
+        0              => Some(Verdict::Pass),
+        code           => {
+            // The test's output follows the status, NUL terminated
+            let text: String = (0xa004..0xc000)
+                .map(|a| gb.peek(a))
+                .take_while(|&c| c != 0)
+                .map(|c| c as char)
+                .collect();
+
+            Some(Verdict::Fail(format!("code {}: {}", code, text.trim())))
+        }
+    }
+}